@@ -1,7 +1,9 @@
+mod modify;
 mod parser;
 use crate::lexer::TokenType;
 use std::fmt::Display;
 
+pub use modify::*;
 pub use parser::Parser;
 
 pub type Ident = String;
@@ -22,7 +24,12 @@ impl Display for Program {
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Statement {
     Let(LetStmt),
+    Destructure(DestructureStmt),
+    Func(FuncStmt),
     Return(ReturnStmt),
+    Import(ImportStmt),
+    Export(ExportStmt),
+    Throw(ThrowStmt),
     Expression(Expression),
 }
 
@@ -30,7 +37,12 @@ impl Display for Statement {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Statement::Let(s) => write!(f, "{}", s),
+            Statement::Destructure(s) => write!(f, "{}", s),
+            Statement::Func(s) => write!(f, "{}", s),
             Statement::Return(s) => write!(f, "{}", s),
+            Statement::Import(s) => write!(f, "{}", s),
+            Statement::Export(s) => write!(f, "{}", s),
+            Statement::Throw(s) => write!(f, "{}", s),
             Statement::Expression(s) => write!(f, "{}", s),
         }
     }
@@ -41,16 +53,74 @@ pub struct LetStmt {
     pub ident: Ident,
     pub expr: Expression,
 }
+/// `let` binding an array or hash pattern
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DestructureStmt {
+    pub pattern: Pattern,
+    pub expr: Expression,
+}
+/// `fn name(params) { ... }`, hoisted to the top of its block
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct FuncStmt {
+    pub name: Ident,
+    pub func: FuncExpr,
+    /// Line of the `fn` keyword
+    pub line: usize,
+}
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct ReturnStmt {
     pub expr: Expression,
 }
+/// `import "path" as name;`, only valid at the top level of a file
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ImportStmt {
+    /// Relative to the importing file until resolved by `module::load_imports`
+    pub path: String,
+    pub name: Ident,
+}
+/// `export a, b;`, names visible to the files importing this one
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ExportStmt {
+    pub names: Vec<Ident>,
+}
+
+/// `throw expr;`, raising the value as an error caught by the nearest `try`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ThrowStmt {
+    pub expr: Expression,
+    pub line: usize,
+}
 
 impl Display for LetStmt {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "let {} = {};", self.ident, self.expr)
     }
 }
+impl Display for DestructureStmt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "let {} = {};", self.pattern, self.expr)
+    }
+}
+impl Display for FuncStmt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.func.fmt_with_head(f, &format!("fn {}", self.name))
+    }
+}
+impl Display for ImportStmt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "import \"{}\" as {};", self.path, self.name)
+    }
+}
+impl Display for ExportStmt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "export {};", self.names.join(", "))
+    }
+}
+impl Display for ThrowStmt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "throw {};", self.expr)
+    }
+}
 impl Display for ReturnStmt {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "return {};", self.expr)
@@ -67,10 +137,21 @@ pub enum Expression {
     Bool(bool),
     If(IfExpr),
     Func(FuncExpr),
+    /// `macro(params) { body }`, only valid bound by a top-level `let`
+    Macro(FuncExpr),
     Call(CallExpr),
+    Method(MethodExpr),
     Array(ArrayExpr),
     Index(IndexExpr),
+    Slice(SliceExpr),
+    Range(RangeExpr),
+    Template(TemplateExpr),
     Hash(HashExpr),
+    Match(MatchExpr),
+    Try(TryExpr),
+    For(ForExpr),
+    /// `...expr`, only valid as a call argument
+    Spread(Box<Expression>),
 }
 
 impl Display for Expression {
@@ -84,10 +165,19 @@ impl Display for Expression {
             Expression::Bool(b) => write!(f, "{}", b),
             Expression::If(i) => write!(f, "{}", i),
             Expression::Func(i) => write!(f, "{}", i),
+            Expression::Macro(i) => i.fmt_with_head(f, "macro"),
             Expression::Call(i) => write!(f, "{}", i),
+            Expression::Method(i) => write!(f, "{}", i),
             Expression::Array(i) => write!(f, "{}", i),
             Expression::Index(i) => write!(f, "{}", i),
+            Expression::Slice(i) => write!(f, "{}", i),
+            Expression::Range(i) => write!(f, "{}", i),
+            Expression::Template(i) => write!(f, "{}", i),
             Expression::Hash(i) => write!(f, "{}", i),
+            Expression::Match(i) => write!(f, "{}", i),
+            Expression::Try(i) => write!(f, "{}", i),
+            Expression::For(i) => write!(f, "{}", i),
+            Expression::Spread(i) => write!(f, "...{}", i),
         }
     }
 }
@@ -109,6 +199,8 @@ pub struct InfixExpr {
     pub left: Box<Expression>,
     pub operator: TokenType,
     pub right: Box<Expression>,
+    /// Line of the operator, reported for errors of the operation
+    pub line: usize,
 }
 
 impl Display for InfixExpr {
@@ -143,17 +235,101 @@ impl Display for IfExpr {
     }
 }
 
+/// `try { } catch (e) { } finally { }`, with at least one of `catch` and `finally`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TryExpr {
+    pub body: Vec<Statement>,
+    pub catch: Option<CatchClause>,
+    pub finally: Option<Vec<Statement>>,
+    /// Reported as the location of runtime errors caught by this `try`
+    pub line: usize,
+}
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CatchClause {
+    pub ident: Ident,
+    pub body: Vec<Statement>,
+}
+
+impl Display for TryExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "try {{")?;
+        for s in &self.body {
+            writeln!(f, "  {}", s)?;
+        }
+        write!(f, "}}")?;
+        if let Some(catch) = &self.catch {
+            writeln!(f, " catch ({}) {{", catch.ident)?;
+            for s in &catch.body {
+                writeln!(f, "  {}", s)?;
+            }
+            write!(f, "}}")?;
+        }
+        if let Some(finally) = &self.finally {
+            writeln!(f, " finally {{")?;
+            for s in finally {
+                writeln!(f, "  {}", s)?;
+            }
+            write!(f, "}}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// `for (ident in iterable) { body }`, evaluating to `null`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ForExpr {
+    pub ident: Ident,
+    pub iterable: Box<Expression>,
+    pub body: Vec<Statement>,
+}
+
+impl Display for ForExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "for ({} in {}) {{", self.ident, self.iterable)?;
+        for s in &self.body {
+            writeln!(f, "  {}", s)?;
+        }
+        write!(f, "}}")
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct FuncExpr {
     pub params: Vec<Ident>,
+    /// Default values of the last `defaults.len()` params
+    pub defaults: Vec<Expression>,
+    /// Collects the arguments past `params` into an array
+    pub rest: Option<Ident>,
     pub body: Vec<Statement>,
 }
 
-impl Display for FuncExpr {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "fn (")?;
-        for p in &self.params {
+impl FuncExpr {
+    /// Number of arguments that must be given in a call
+    pub fn required(&self) -> usize {
+        self.params.len() - self.defaults.len()
+    }
+}
+
+impl FuncExpr {
+    /// Writes `head(params) { body }`
+    fn fmt_with_head(&self, f: &mut std::fmt::Formatter<'_>, head: &str) -> std::fmt::Result {
+        write!(f, "{}(", head)?;
+        let required = self.required();
+        for (idx, p) in self.params.iter().enumerate() {
+            if idx != 0 {
+                write!(f, ", ")?;
+            }
             write!(f, "{}", p)?;
+            if idx >= required {
+                write!(f, " = {}", self.defaults[idx - required])?;
+            }
+        }
+        if let Some(rest) = &self.rest {
+            if !self.params.is_empty() {
+                write!(f, ", ")?;
+            }
+            write!(f, "...{}", rest)?;
         }
         writeln!(f, ") {{")?;
         for s in &self.body {
@@ -165,11 +341,19 @@ impl Display for FuncExpr {
     }
 }
 
+impl Display for FuncExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.fmt_with_head(f, "fn ")
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct CallExpr {
     /// `Expression::Func` or `Expression::Ident`
     pub func: Box<Expression>,
     pub arguments: Vec<Expression>,
+    /// Line of the `(`, reported for errors of the call
+    pub line: usize,
 }
 
 impl Display for CallExpr {
@@ -188,6 +372,30 @@ impl Display for CallExpr {
     }
 }
 
+/// `object.method(arguments)`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct MethodExpr {
+    pub object: Box<Expression>,
+    pub method: Ident,
+    pub arguments: Vec<Expression>,
+    /// Line of the method name, reported for errors of the call
+    pub line: usize,
+}
+
+impl Display for MethodExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}(", self.object, self.method)?;
+        for (idx, s) in self.arguments.iter().enumerate() {
+            if idx != self.arguments.len() - 1 {
+                write!(f, "{}, ", s)?;
+            } else {
+                write!(f, "{}", s)?;
+            }
+        }
+        write!(f, ")")
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct ArrayExpr {
     pub elements: Vec<Expression>,
@@ -211,6 +419,8 @@ impl Display for ArrayExpr {
 pub struct IndexExpr {
     pub left: Box<Expression>,
     pub index: Box<Expression>,
+    /// Line of the `[` or `.`, reported for errors of the lookup
+    pub line: usize,
 }
 
 impl Display for IndexExpr {
@@ -219,6 +429,67 @@ impl Display for IndexExpr {
     }
 }
 
+/// `left[start:end]`, either bound may be omitted
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SliceExpr {
+    pub left: Box<Expression>,
+    pub start: Option<Box<Expression>>,
+    pub end: Option<Box<Expression>>,
+}
+
+impl Display for SliceExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "({}[", self.left)?;
+        if let Some(start) = &self.start {
+            write!(f, "{}", start)?;
+        }
+        write!(f, ":")?;
+        if let Some(end) = &self.end {
+            write!(f, "{}", end)?;
+        }
+        write!(f, "])")
+    }
+}
+
+/// `start..end` or `start..=end`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct RangeExpr {
+    pub start: Box<Expression>,
+    pub end: Box<Expression>,
+    pub inclusive: bool,
+}
+
+impl Display for RangeExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let op = if self.inclusive { "..=" } else { ".." };
+        write!(f, "({}{}{})", self.start, op, self.end)
+    }
+}
+
+/// String literal with interpolated expressions, `"a ${b} c"`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TemplateExpr {
+    pub parts: Vec<TemplateExprPart>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum TemplateExprPart {
+    Str(String),
+    Expr(Expression),
+}
+
+impl Display for TemplateExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for part in &self.parts {
+            match part {
+                TemplateExprPart::Str(s) => write!(f, "{}", s)?,
+                TemplateExprPart::Expr(e) => write!(f, "${{{}}}", e)?,
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct HashExpr {
     pub pairs: Vec<(Expression, Expression)>,
@@ -238,5 +509,128 @@ impl Display for HashExpr {
     }
 }
 
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct MatchExpr {
+    pub value: Box<Expression>,
+    pub arms: Vec<MatchArm>,
+}
+
+impl Display for MatchExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "match ({}) {{", self.value)?;
+        for arm in &self.arms {
+            writeln!(f, "  {},", arm)?;
+        }
+        write!(f, "}}")
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<Expression>,
+    pub body: Expression,
+}
+
+impl Display for MatchArm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.pattern)?;
+        if let Some(guard) = &self.guard {
+            write!(f, " if {}", guard)?;
+        }
+        write!(f, " => {}", self.body)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Pattern {
+    /// `_`, matches anything without binding it
+    Wildcard,
+    /// Matches anything and binds it to the identifier
+    Ident(Ident),
+    /// `Expression::Number`, `Expression::String` or `Expression::Bool`
+    Literal(Expression),
+    Array(ArrayPattern),
+    Hash(HashPattern),
+}
+
+impl Pattern {
+    /// Identifiers bound by the pattern, in order
+    pub fn idents(&self) -> Vec<&Ident> {
+        match self {
+            Pattern::Wildcard | Pattern::Literal(_) => vec![],
+            Pattern::Ident(i) => vec![i],
+            Pattern::Array(a) => a
+                .elements
+                .iter()
+                .chain(a.rest.as_deref())
+                .flat_map(Pattern::idents)
+                .collect(),
+            Pattern::Hash(h) => h.pairs.iter().flat_map(|(_, p)| p.idents()).collect(),
+        }
+    }
+}
+
+impl Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Pattern::Wildcard => write!(f, "_"),
+            Pattern::Ident(i) => write!(f, "{}", i),
+            Pattern::Literal(l) => write!(f, "{}", l),
+            Pattern::Array(a) => write!(f, "{}", a),
+            Pattern::Hash(h) => write!(f, "{}", h),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ArrayPattern {
+    pub elements: Vec<Pattern>,
+    /// Pattern for the remaining elements, bound as an array
+    pub rest: Option<Box<Pattern>>,
+}
+
+impl Display for ArrayPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[")?;
+        for (idx, p) in self.elements.iter().enumerate() {
+            if idx != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", p)?;
+        }
+        if let Some(rest) = &self.rest {
+            if !self.elements.is_empty() {
+                write!(f, ", ")?;
+            }
+            match **rest {
+                Pattern::Wildcard => write!(f, "..")?,
+                _ => write!(f, "..{}", rest)?,
+            }
+        }
+        write!(f, "]")
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct HashPattern {
+    /// Keys are literal expressions
+    pub pairs: Vec<(Expression, Pattern)>,
+}
+
+impl Display for HashPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{{")?;
+        for (idx, (k, v)) in self.pairs.iter().enumerate() {
+            if idx != self.pairs.len() - 1 {
+                write!(f, "{}: {}, ", k, v)?;
+            } else {
+                write!(f, "{}: {}", k, v)?;
+            }
+        }
+        write!(f, "}}")
+    }
+}
+
 #[cfg(test)]
 mod test;
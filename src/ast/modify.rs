@@ -0,0 +1,185 @@
+use super::*;
+
+/// Rewrites every expression of the program, children before their parent
+pub fn modify_program<F>(program: Program, f: &mut F) -> Result<Program, String>
+where
+    F: FnMut(Expression) -> Result<Expression, String>,
+{
+    Ok(Program {
+        statements: modify_block(program.statements, f)?,
+    })
+}
+
+pub fn modify_stmt<F>(stmt: Statement, f: &mut F) -> Result<Statement, String>
+where
+    F: FnMut(Expression) -> Result<Expression, String>,
+{
+    Ok(match stmt {
+        Statement::Let(s) => Statement::Let(LetStmt {
+            ident: s.ident,
+            expr: modify_expr(s.expr, f)?,
+        }),
+        Statement::Destructure(s) => Statement::Destructure(DestructureStmt {
+            pattern: s.pattern,
+            expr: modify_expr(s.expr, f)?,
+        }),
+        Statement::Func(s) => Statement::Func(FuncStmt {
+            name: s.name,
+            func: modify_func(s.func, f)?,
+            line: s.line,
+        }),
+        Statement::Return(s) => Statement::Return(ReturnStmt {
+            expr: modify_expr(s.expr, f)?,
+        }),
+        Statement::Import(s) => Statement::Import(s),
+        Statement::Export(s) => Statement::Export(s),
+        Statement::Throw(s) => Statement::Throw(ThrowStmt {
+            expr: modify_expr(s.expr, f)?,
+            line: s.line,
+        }),
+        Statement::Expression(e) => Statement::Expression(modify_expr(e, f)?),
+    })
+}
+
+pub fn modify_expr<F>(expr: Expression, f: &mut F) -> Result<Expression, String>
+where
+    F: FnMut(Expression) -> Result<Expression, String>,
+{
+    let expr = match expr {
+        Expression::Ident(_)
+        | Expression::Number(_)
+        | Expression::String(_)
+        | Expression::Bool(_) => expr,
+        Expression::Prefix(p) => Expression::Prefix(PrefixExpr {
+            operator: p.operator,
+            right: Box::new(modify_expr(*p.right, f)?),
+        }),
+        Expression::Infix(i) => Expression::Infix(InfixExpr {
+            left: Box::new(modify_expr(*i.left, f)?),
+            operator: i.operator,
+            right: Box::new(modify_expr(*i.right, f)?),
+            line: i.line,
+        }),
+        Expression::If(i) => Expression::If(IfExpr {
+            condition: Box::new(modify_expr(*i.condition, f)?),
+            if_branch: modify_block(i.if_branch, f)?,
+            else_branch: i.else_branch.map(|b| modify_block(b, f)).transpose()?,
+        }),
+        Expression::Func(func) => Expression::Func(modify_func(func, f)?),
+        Expression::Macro(func) => Expression::Macro(modify_func(func, f)?),
+        Expression::Call(c) => Expression::Call(CallExpr {
+            func: Box::new(modify_expr(*c.func, f)?),
+            arguments: modify_exprs(c.arguments, f)?,
+            line: c.line,
+        }),
+        Expression::Method(m) => Expression::Method(MethodExpr {
+            object: Box::new(modify_expr(*m.object, f)?),
+            method: m.method,
+            arguments: modify_exprs(m.arguments, f)?,
+            line: m.line,
+        }),
+        Expression::Array(a) => Expression::Array(ArrayExpr {
+            elements: modify_exprs(a.elements, f)?,
+        }),
+        Expression::Index(i) => Expression::Index(IndexExpr {
+            left: Box::new(modify_expr(*i.left, f)?),
+            index: Box::new(modify_expr(*i.index, f)?),
+            line: i.line,
+        }),
+        Expression::Slice(s) => Expression::Slice(SliceExpr {
+            left: Box::new(modify_expr(*s.left, f)?),
+            start: s
+                .start
+                .map(|e| modify_expr(*e, f).map(Box::new))
+                .transpose()?,
+            end: s
+                .end
+                .map(|e| modify_expr(*e, f).map(Box::new))
+                .transpose()?,
+        }),
+        Expression::Range(r) => Expression::Range(RangeExpr {
+            start: Box::new(modify_expr(*r.start, f)?),
+            end: Box::new(modify_expr(*r.end, f)?),
+            inclusive: r.inclusive,
+        }),
+        Expression::Template(t) => Expression::Template(TemplateExpr {
+            parts: t
+                .parts
+                .into_iter()
+                .map(|p| match p {
+                    TemplateExprPart::Expr(e) => modify_expr(e, f).map(TemplateExprPart::Expr),
+                    p => Ok(p),
+                })
+                .collect::<Result<_, _>>()?,
+        }),
+        Expression::Hash(h) => Expression::Hash(HashExpr {
+            pairs: h
+                .pairs
+                .into_iter()
+                .map(|(k, v)| Ok((modify_expr(k, f)?, modify_expr(v, f)?)))
+                .collect::<Result<_, String>>()?,
+        }),
+        Expression::Match(m) => Expression::Match(MatchExpr {
+            value: Box::new(modify_expr(*m.value, f)?),
+            arms: m
+                .arms
+                .into_iter()
+                .map(|arm| {
+                    Ok(MatchArm {
+                        pattern: arm.pattern,
+                        guard: arm.guard.map(|g| modify_expr(g, f)).transpose()?,
+                        body: modify_expr(arm.body, f)?,
+                    })
+                })
+                .collect::<Result<_, String>>()?,
+        }),
+        Expression::Try(t) => Expression::Try(TryExpr {
+            body: modify_block(t.body, f)?,
+            catch: t
+                .catch
+                .map(|c| {
+                    Ok::<_, String>(CatchClause {
+                        ident: c.ident,
+                        body: modify_block(c.body, f)?,
+                    })
+                })
+                .transpose()?,
+            finally: t.finally.map(|b| modify_block(b, f)).transpose()?,
+            line: t.line,
+        }),
+        Expression::For(l) => Expression::For(ForExpr {
+            ident: l.ident,
+            iterable: Box::new(modify_expr(*l.iterable, f)?),
+            body: modify_block(l.body, f)?,
+        }),
+        Expression::Spread(e) => Expression::Spread(Box::new(modify_expr(*e, f)?)),
+    };
+
+    f(expr)
+}
+
+fn modify_func<F>(func: FuncExpr, f: &mut F) -> Result<FuncExpr, String>
+where
+    F: FnMut(Expression) -> Result<Expression, String>,
+{
+    Ok(FuncExpr {
+        params: func.params,
+        defaults: modify_exprs(func.defaults, f)?,
+        rest: func.rest,
+        body: modify_block(func.body, f)?,
+    })
+}
+
+fn modify_block<F>(block: Vec<Statement>, f: &mut F) -> Result<Vec<Statement>, String>
+where
+    F: FnMut(Expression) -> Result<Expression, String>,
+{
+    block.into_iter().map(|s| modify_stmt(s, f)).collect()
+}
+
+fn modify_exprs<F>(exprs: Vec<Expression>, f: &mut F) -> Result<Vec<Expression>, String>
+where
+    F: FnMut(Expression) -> Result<Expression, String>,
+{
+    exprs.into_iter().map(|e| modify_expr(e, f)).collect()
+}
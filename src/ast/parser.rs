@@ -1,5 +1,5 @@
 use super::*;
-use crate::lexer::{Lexer, Token, TokenType};
+use crate::lexer::{Lexer, TemplatePart, Token, TokenType};
 
 pub struct Parser {
     lexer: Lexer,
@@ -44,7 +44,11 @@ impl Parser {
     fn parse_stmt(&mut self) -> ParseResult<Statement> {
         match self.cur_token.ty {
             TokenType::Let => self.parse_let(),
+            TokenType::Fn if self.peek_token_is(TokenType::Ident) => self.parse_func_stmt(),
             TokenType::Return => self.parse_return(),
+            TokenType::Import => self.parse_import(),
+            TokenType::Export => self.parse_export(),
+            TokenType::Throw => self.parse_throw(),
             _ => self.parse_expr_stmt(),
         }
     }
@@ -70,7 +74,22 @@ impl Parser {
         Ok(Statement::Return(ReturnStmt { expr }))
     }
 
+    fn parse_throw(&mut self) -> ParseResult<Statement> {
+        let line = self.cur_token.line;
+        self.next();
+
+        let expr = self.parse_expr(Precedence::Lowest)?;
+        if self.peek_token_is(TokenType::Semicolon) {
+            self.next();
+        }
+
+        Ok(Statement::Throw(ThrowStmt { expr, line }))
+    }
+
     fn parse_let(&mut self) -> ParseResult<Statement> {
+        if self.peek_token_is(TokenType::LBracket) || self.peek_token_is(TokenType::LBrace) {
+            return self.parse_destructure();
+        }
         self.expect_peek(TokenType::Ident)?;
         let ident: String = self.cur_token.literal.ident().unwrap().into();
 
@@ -85,6 +104,66 @@ impl Parser {
         Ok(Statement::Let(LetStmt { ident, expr }))
     }
 
+    fn parse_import(&mut self) -> ParseResult<Statement> {
+        self.expect_peek(TokenType::String)?;
+        let path: String = self.cur_token.literal.string().unwrap().into();
+        self.expect_peek(TokenType::As)?;
+        self.expect_peek(TokenType::Ident)?;
+        let name: Ident = self.cur_token.literal.ident().unwrap().into();
+
+        if self.peek_token_is(TokenType::Semicolon) {
+            self.next();
+        }
+
+        Ok(Statement::Import(ImportStmt { path, name }))
+    }
+
+    fn parse_export(&mut self) -> ParseResult<Statement> {
+        let mut names = vec![];
+        loop {
+            self.expect_peek(TokenType::Ident)?;
+            names.push(self.cur_token.literal.ident().unwrap().into());
+            if !self.peek_token_is(TokenType::Comma) {
+                break;
+            }
+            self.next();
+        }
+
+        if self.peek_token_is(TokenType::Semicolon) {
+            self.next();
+        }
+
+        Ok(Statement::Export(ExportStmt { names }))
+    }
+
+    fn parse_func_stmt(&mut self) -> ParseResult<Statement> {
+        let line = self.cur_token.line;
+        self.next();
+        let name: Ident = self.cur_token.literal.ident().unwrap().into();
+
+        let func = self.parse_func_literal()?;
+        if self.peek_token_is(TokenType::Semicolon) {
+            self.next();
+        }
+
+        Ok(Statement::Func(FuncStmt { name, func, line }))
+    }
+
+    fn parse_destructure(&mut self) -> ParseResult<Statement> {
+        self.next();
+        let pattern = self.parse_pattern()?;
+
+        self.expect_peek(TokenType::Assign)?;
+        self.next();
+
+        let expr = self.parse_expr(Precedence::Lowest)?;
+        if self.peek_token_is(TokenType::Semicolon) {
+            self.next();
+        }
+
+        Ok(Statement::Destructure(DestructureStmt { pattern, expr }))
+    }
+
     fn parse_expr(&mut self, prec: Precedence) -> ParseResult<Expression> {
         let mut left = self.prefix()?;
         while !self.peek_token_is(TokenType::Semicolon) && prec < self.peek_precedence() {
@@ -108,6 +187,18 @@ impl Parser {
                     self.next();
                     left = self.parse_index(left)?;
                 }
+                TokenType::Dot => {
+                    self.next();
+                    left = self.parse_member(left)?;
+                }
+                TokenType::Pipe => {
+                    self.next();
+                    left = self.parse_pipe(left)?;
+                }
+                TokenType::DotDot | TokenType::DotDotEq => {
+                    self.next();
+                    left = self.parse_range(left)?;
+                }
                 _ => return Ok(left),
             }
         }
@@ -120,13 +211,18 @@ impl Parser {
             TokenType::Ident => self.parse_ident(),
             TokenType::Number => self.parse_number(),
             TokenType::String => self.parse_string(),
+            TokenType::Template => self.parse_template(),
             TokenType::True | TokenType::False => self.parse_bool(),
             TokenType::Bang | TokenType::Minus => self.parse_prefix(),
             TokenType::LParen => self.parse_group(),
             TokenType::LBracket => self.parse_arr(),
             TokenType::If => self.parse_if(),
             TokenType::Fn => self.parse_func(),
+            TokenType::Macro => self.parse_func_literal().map(Expression::Macro),
             TokenType::LBrace => self.parse_hash(),
+            TokenType::Match => self.parse_match(),
+            TokenType::Try => self.parse_try(),
+            TokenType::For => self.parse_for(),
             _ => Err(vec![ParseErrorKind::UnknownPrefixExpr(self.cur_token.ty)]),
         }
     }
@@ -149,6 +245,18 @@ impl Parser {
         token_precedence(self.peek_token.ty)
     }
 
+    /// Moves past the separator following a list item, leaving `cur_token`
+    /// on the next item or on `end`
+    fn next_list_item(&mut self, end: TokenType) -> ParseResult<()> {
+        if self.peek_token_is(TokenType::Comma) {
+            self.next();
+            self.next();
+            Ok(())
+        } else {
+            self.expect_peek(end)
+        }
+    }
+
     fn expect_peek(&mut self, ty: TokenType) -> ParseResult<()> {
         if self.peek_token_is(ty) {
             self.next();
@@ -190,6 +298,29 @@ impl Parser {
         Ok(Expression::String(s.into()))
     }
 
+    fn parse_template(&mut self) -> ParseResult<Expression> {
+        let parts = self
+            .cur_token
+            .literal
+            .template()
+            .ok_or(vec![ParseErrorKind::InvalidParseFn])?
+            .to_vec();
+
+        let mut res = vec![];
+        for part in parts {
+            match part {
+                TemplatePart::Str(s) => res.push(TemplateExprPart::Str(s)),
+                TemplatePart::Expr(src) => {
+                    let mut parser = Parser::new(Lexer::new(src));
+                    let expr = parser.parse_expr(Precedence::Lowest)?;
+                    parser.expect_peek(TokenType::Eof)?;
+                    res.push(TemplateExprPart::Expr(expr));
+                }
+            }
+        }
+        Ok(Expression::Template(TemplateExpr { parts: res }))
+    }
+
     fn parse_bool(&mut self) -> ParseResult<Expression> {
         match self.cur_token.ty {
             TokenType::True => Ok(Expression::Bool(true)),
@@ -211,6 +342,7 @@ impl Parser {
 
     fn parse_infix(&mut self, left: Expression) -> ParseResult<Expression> {
         let operator = self.cur_token.ty;
+        let line = self.cur_token.line;
         let prec = self.cur_precedence();
         self.next();
         let right = Box::new(self.parse_expr(prec)?);
@@ -219,6 +351,42 @@ impl Parser {
             left: Box::new(left),
             operator,
             right,
+            line,
+        }))
+    }
+
+    /// `x |> f(y)` is sugar for `f(x, y)` and `x |> f` for `f(x)`
+    fn parse_pipe(&mut self, left: Expression) -> ParseResult<Expression> {
+        let line = self.cur_token.line;
+        self.next();
+        let right = self.parse_expr(Precedence::Pipe)?;
+
+        Ok(match right {
+            Expression::Call(mut c) => {
+                c.arguments.insert(0, left);
+                Expression::Call(c)
+            }
+            Expression::Method(mut m) => {
+                m.arguments.insert(0, left);
+                Expression::Method(m)
+            }
+            func => Expression::Call(CallExpr {
+                func: Box::new(func),
+                arguments: vec![left],
+                line,
+            }),
+        })
+    }
+
+    fn parse_range(&mut self, start: Expression) -> ParseResult<Expression> {
+        let inclusive = self.cur_token_is(TokenType::DotDotEq);
+        self.next();
+        let end = self.parse_expr(Precedence::Range)?;
+
+        Ok(Expression::Range(RangeExpr {
+            start: Box::new(start),
+            end: Box::new(end),
+            inclusive,
         }))
     }
 
@@ -254,16 +422,26 @@ impl Parser {
     }
 
     fn parse_func(&mut self) -> ParseResult<Expression> {
+        self.parse_func_literal().map(Expression::Func)
+    }
+
+    /// Parses params and body, starting at the token before `(`
+    fn parse_func_literal(&mut self) -> ParseResult<FuncExpr> {
         self.expect_peek(TokenType::LParen)?;
         self.next();
 
-        let params = self.parse_params()?;
+        let (params, defaults, rest) = self.parse_params()?;
 
         self.expect_peek(TokenType::LBrace)?;
         self.next();
         let body = self.parse_block()?;
 
-        Ok(Expression::Func(FuncExpr { params, body }))
+        Ok(FuncExpr {
+            params,
+            defaults,
+            rest,
+            body,
+        })
     }
 
     fn parse_hash(&mut self) -> ParseResult<Expression> {
@@ -293,38 +471,99 @@ impl Parser {
         Ok(Expression::Hash(HashExpr { pairs: res }))
     }
 
-    fn parse_params(&mut self) -> ParseResult<Vec<Ident>> {
-        if self.cur_token_is(TokenType::RParen) {
-            return Ok(vec![]);
-        }
+    fn parse_params(&mut self) -> ParseResult<(Vec<Ident>, Vec<Expression>, Option<Ident>)> {
+        let mut params = vec![];
+        let mut defaults = vec![];
+        let mut rest = None;
 
-        let mut res: Vec<Ident> = vec![];
-        while self.peek_token_is(TokenType::Comma) {
-            let ident =
-                self.cur_token
-                    .literal
-                    .ident()
-                    .ok_or(vec![ParseErrorKind::UnexpectedToken(UnexpectedErr::new(
-                        TokenType::Ident,
-                        self.cur_token.ty,
-                    ))])?;
-            res.push(ident.into());
-
-            self.expect_peek(TokenType::Comma)?;
-            self.next();
+        while !self.cur_token_is(TokenType::RParen) {
+            if self.cur_token_is(TokenType::Ellipsis) {
+                self.expect_peek(TokenType::Ident)?;
+                rest = self.cur_token.literal.ident().map(Into::into);
+                self.expect_peek(TokenType::RParen)?;
+                break;
+            }
+
+            let ident: Ident = self
+                .cur_token
+                .literal
+                .ident()
+                .ok_or(vec![ParseErrorKind::UnexpectedToken(UnexpectedErr::new(
+                    TokenType::Ident,
+                    self.cur_token.ty,
+                ))])?
+                .into();
+
+            if self.peek_token_is(TokenType::Assign) {
+                self.next();
+                self.next();
+                defaults.push(self.parse_expr(Precedence::Lowest)?);
+            } else if !defaults.is_empty() {
+                return Err(vec![ParseErrorKind::ParamAfterDefault(ident)]);
+            }
+
+            params.push(ident);
+            self.next_list_item(TokenType::RParen)?;
         }
-        let ident = self
-            .cur_token
-            .literal
-            .ident()
-            .ok_or(vec![ParseErrorKind::UnexpectedToken(UnexpectedErr::new(
-                TokenType::Ident,
-                self.cur_token.ty,
-            ))])?;
-        res.push(ident.into());
+
+        Ok((params, defaults, rest))
+    }
+
+    fn parse_for(&mut self) -> ParseResult<Expression> {
+        self.expect_peek(TokenType::LParen)?;
+        self.expect_peek(TokenType::Ident)?;
+        let ident: Ident = self.cur_token.literal.ident().unwrap().into();
+        self.expect_peek(TokenType::In)?;
+        self.next();
+        let iterable = self.parse_expr(Precedence::Lowest)?;
         self.expect_peek(TokenType::RParen)?;
+        self.expect_peek(TokenType::LBrace)?;
+        self.next();
 
-        Ok(res)
+        Ok(Expression::For(ForExpr {
+            ident,
+            iterable: Box::new(iterable),
+            body: self.parse_block()?,
+        }))
+    }
+
+    fn parse_try(&mut self) -> ParseResult<Expression> {
+        let line = self.cur_token.line;
+        self.expect_peek(TokenType::LBrace)?;
+        self.next();
+        let body = self.parse_block()?;
+
+        let catch = if self.peek_token_is(TokenType::Catch) {
+            self.next();
+            self.expect_peek(TokenType::LParen)?;
+            self.expect_peek(TokenType::Ident)?;
+            let ident: Ident = self.cur_token.literal.ident().unwrap().into();
+            self.expect_peek(TokenType::RParen)?;
+            self.expect_peek(TokenType::LBrace)?;
+            self.next();
+            Some(CatchClause {
+                ident,
+                body: self.parse_block()?,
+            })
+        } else {
+            None
+        };
+
+        let finally = if catch.is_none() || self.peek_token_is(TokenType::Finally) {
+            self.expect_peek(TokenType::Finally)?;
+            self.expect_peek(TokenType::LBrace)?;
+            self.next();
+            Some(self.parse_block()?)
+        } else {
+            None
+        };
+
+        Ok(Expression::Try(TryExpr {
+            body,
+            catch,
+            finally,
+            line,
+        }))
     }
 
     fn parse_block(&mut self) -> ParseResult<Vec<Statement>> {
@@ -340,22 +579,86 @@ impl Parser {
     }
 
     fn parse_call(&mut self, func: Expression) -> ParseResult<Expression> {
-        self.next();
-        let args = self.parse_expr_list(TokenType::RParen)?;
+        let line = self.cur_token.line;
         Ok(Expression::Call(CallExpr {
             func: Box::new(func),
-            arguments: args,
+            arguments: self.parse_args()?,
+            line,
+        }))
+    }
+
+    /// Arguments of a call, with the current token being `(`
+    fn parse_args(&mut self) -> ParseResult<Vec<Expression>> {
+        self.next();
+
+        let mut args = vec![];
+        while !self.cur_token_is(TokenType::RParen) {
+            if self.cur_token_is(TokenType::Ellipsis) {
+                self.next();
+                let expr = self.parse_expr(Precedence::Lowest)?;
+                args.push(Expression::Spread(Box::new(expr)));
+            } else {
+                args.push(self.parse_expr(Precedence::Lowest)?);
+            }
+            self.next_list_item(TokenType::RParen)?;
+        }
+        Ok(args)
+    }
+
+    /// `object.field` is sugar for `object["field"]`
+    fn parse_member(&mut self, object: Expression) -> ParseResult<Expression> {
+        self.expect_peek(TokenType::Ident)?;
+        let name: Ident = self.cur_token.literal.ident().unwrap().into();
+        let line = self.cur_token.line;
+
+        if self.peek_token_is(TokenType::LParen) {
+            self.next();
+            return Ok(Expression::Method(MethodExpr {
+                object: Box::new(object),
+                method: name,
+                arguments: self.parse_args()?,
+                line,
+            }));
+        }
+
+        Ok(Expression::Index(IndexExpr {
+            left: Box::new(object),
+            index: Box::new(Expression::String(name)),
+            line,
         }))
     }
 
     fn parse_index(&mut self, left: Expression) -> ParseResult<Expression> {
+        let line = self.cur_token.line;
         self.next();
-        let index = self.parse_expr(Precedence::Lowest)?;
+        let start = if self.cur_token_is(TokenType::Colon) {
+            None
+        } else {
+            let index = self.parse_expr(Precedence::Lowest)?;
+            if !self.peek_token_is(TokenType::Colon) {
+                self.expect_peek(TokenType::RBracket)?;
+                return Ok(Expression::Index(IndexExpr {
+                    left: Box::new(left),
+                    index: Box::new(index),
+                    line,
+                }));
+            }
+            self.next();
+            Some(Box::new(index))
+        };
+
+        let end = if self.peek_token_is(TokenType::RBracket) {
+            None
+        } else {
+            self.next();
+            Some(Box::new(self.parse_expr(Precedence::Lowest)?))
+        };
         self.expect_peek(TokenType::RBracket)?;
 
-        Ok(Expression::Index(IndexExpr {
+        Ok(Expression::Slice(SliceExpr {
             left: Box::new(left),
-            index: Box::new(index),
+            start,
+            end,
         }))
     }
 
@@ -384,6 +687,123 @@ impl Parser {
         Ok(res)
     }
 
+    fn parse_match(&mut self) -> ParseResult<Expression> {
+        self.expect_peek(TokenType::LParen)?;
+        self.next();
+        let value = self.parse_expr(Precedence::Lowest)?;
+        self.expect_peek(TokenType::RParen)?;
+        self.expect_peek(TokenType::LBrace)?;
+        self.next();
+
+        let mut arms = vec![];
+        while !self.cur_token_is(TokenType::RBrace) {
+            let pattern = self.parse_pattern()?;
+            let guard = if self.peek_token_is(TokenType::If) {
+                self.next();
+                self.next();
+                Some(self.parse_expr(Precedence::Lowest)?)
+            } else {
+                None
+            };
+            self.expect_peek(TokenType::FatArrow)?;
+            self.next();
+            let body = self.parse_expr(Precedence::Lowest)?;
+
+            arms.push(MatchArm {
+                pattern,
+                guard,
+                body,
+            });
+            self.next_list_item(TokenType::RBrace)?;
+        }
+
+        Ok(Expression::Match(MatchExpr {
+            value: Box::new(value),
+            arms,
+        }))
+    }
+
+    fn parse_pattern(&mut self) -> ParseResult<Pattern> {
+        match self.cur_token.ty {
+            TokenType::Ident => match self.cur_token.literal.ident() {
+                Some("_") => Ok(Pattern::Wildcard),
+                Some(i) => Ok(Pattern::Ident(i.into())),
+                None => Err(vec![ParseErrorKind::InvalidParseFn]),
+            },
+            TokenType::Minus if self.peek_token_is(TokenType::Number) => {
+                self.next();
+                let num = self
+                    .cur_token
+                    .literal
+                    .num()
+                    .ok_or(vec![ParseErrorKind::InvalidParseFn])?;
+                Ok(Pattern::Literal(Expression::Number(-num)))
+            }
+            TokenType::LBracket => self.parse_array_pattern(),
+            TokenType::LBrace => self.parse_hash_pattern(),
+            _ => Ok(Pattern::Literal(self.parse_pattern_literal()?)),
+        }
+    }
+
+    fn parse_pattern_literal(&mut self) -> ParseResult<Expression> {
+        match self.cur_token.ty {
+            TokenType::Number => self.parse_number(),
+            TokenType::String => self.parse_string(),
+            TokenType::True | TokenType::False => self.parse_bool(),
+            ty => Err(vec![ParseErrorKind::InvalidPattern(ty)]),
+        }
+    }
+
+    fn parse_array_pattern(&mut self) -> ParseResult<Pattern> {
+        self.next();
+
+        let mut elements = vec![];
+        let mut rest = None;
+        while !self.cur_token_is(TokenType::RBracket) {
+            if self.cur_token_is(TokenType::DotDot) {
+                let pattern = if self.peek_token_is(TokenType::RBracket) {
+                    Pattern::Wildcard
+                } else {
+                    self.next();
+                    self.parse_pattern()?
+                };
+                rest = Some(Box::new(pattern));
+                self.expect_peek(TokenType::RBracket)?;
+                break;
+            }
+
+            elements.push(self.parse_pattern()?);
+            self.next_list_item(TokenType::RBracket)?;
+        }
+
+        Ok(Pattern::Array(ArrayPattern { elements, rest }))
+    }
+
+    fn parse_hash_pattern(&mut self) -> ParseResult<Pattern> {
+        self.next();
+
+        let mut pairs = vec![];
+        while !self.cur_token_is(TokenType::RBrace) {
+            // `{name}` is shorthand for `{"name": name}`
+            if let Some(ident) = self.cur_token.literal.ident() {
+                let key = Expression::String(ident.into());
+                pairs.push((key, self.parse_pattern()?));
+                self.next_list_item(TokenType::RBrace)?;
+                continue;
+            }
+
+            let key = self.parse_pattern_literal()?;
+            self.expect_peek(TokenType::Colon)?;
+            self.next();
+            let value = self.parse_pattern()?;
+
+            pairs.push((key, value));
+            self.next_list_item(TokenType::RBrace)?;
+        }
+
+        Ok(Pattern::Hash(HashPattern { pairs }))
+    }
+
     fn parse_group(&mut self) -> ParseResult<Expression> {
         self.next();
 
@@ -401,6 +821,8 @@ type ParseResult<T> = Result<T, ParseError>;
 pub enum ParseErrorKind {
     UnexpectedToken(UnexpectedErr),
     UnknownPrefixExpr(TokenType),
+    InvalidPattern(TokenType),
+    ParamAfterDefault(Ident),
     InvalidParseFn,
 }
 
@@ -420,23 +842,29 @@ impl UnexpectedErr {
 #[derive(Debug, PartialEq, PartialOrd, Eq, Ord)]
 pub enum Precedence {
     Lowest,
+    Pipe,
     Equals,
     Ltgt,
+    Range,
     Sum,
     Prodcut,
     Prefix,
     Call,
     Index,
+    Member,
 }
 
 fn token_precedence(ty: TokenType) -> Precedence {
     match ty {
+        TokenType::Pipe => Precedence::Pipe,
         TokenType::Eq | TokenType::NotEq => Precedence::Equals,
         TokenType::Lt | TokenType::Gt => Precedence::Ltgt,
+        TokenType::DotDot | TokenType::DotDotEq => Precedence::Range,
         TokenType::Plus | TokenType::Minus => Precedence::Sum,
         TokenType::Star | TokenType::Slash => Precedence::Prodcut,
         TokenType::LParen => Precedence::Call,
         TokenType::LBracket => Precedence::Index,
+        TokenType::Dot => Precedence::Member,
         _ => Precedence::Lowest,
     }
 }
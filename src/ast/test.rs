@@ -45,6 +45,78 @@ fn let_stmt() {
     }
 }
 
+#[test]
+fn destructure_stmt() {
+    let inputs = vec![
+        (
+            "let [a, b, ..rest] = arr;",
+            Statement::Destructure(DestructureStmt {
+                pattern: Pattern::Array(ArrayPattern {
+                    elements: vec![Pattern::Ident("a".into()), Pattern::Ident("b".into())],
+                    rest: Some(Box::new(Pattern::Ident("rest".into()))),
+                }),
+                expr: Expression::Ident("arr".into()),
+            }),
+        ),
+        (
+            r#"let {name, "years": age} = person;"#,
+            Statement::Destructure(DestructureStmt {
+                pattern: Pattern::Hash(HashPattern {
+                    pairs: vec![
+                        (
+                            Expression::String("name".into()),
+                            Pattern::Ident("name".into()),
+                        ),
+                        (
+                            Expression::String("years".into()),
+                            Pattern::Ident("age".into()),
+                        ),
+                    ],
+                }),
+                expr: Expression::Ident("person".into()),
+            }),
+        ),
+    ];
+
+    for (inp, expect) in inputs {
+        let lexer = Lexer::new(inp.into());
+        let mut parser = Parser::new(lexer);
+
+        let Program { statements } = parser.parse().unwrap();
+
+        assert_eq!(1, statements.len());
+        assert_eq!(statements[0], expect);
+    }
+}
+
+#[test]
+fn func_stmt() {
+    let input = "fn add(x, y) { x + y; }";
+    let expected = Statement::Func(FuncStmt {
+        name: "add".into(),
+        func: FuncExpr {
+            params: vec!["x".into(), "y".into()],
+            defaults: vec![],
+            rest: None,
+            body: vec![Statement::Expression(Expression::Infix(InfixExpr {
+                left: Box::new(Expression::Ident("x".into())),
+                operator: TokenType::Plus,
+                right: Box::new(Expression::Ident("y".into())),
+                line: 1,
+            }))],
+        },
+        line: 1,
+    });
+
+    let lexer = Lexer::new(input.into());
+    let mut parser = Parser::new(lexer);
+
+    let Program { statements } = parser.parse().unwrap();
+
+    assert_eq!(1, statements.len());
+    assert_eq!(statements[0], expected);
+}
+
 #[test]
 fn return_stmt() {
     let inputs = vec![
@@ -189,6 +261,7 @@ fn infix_expr() {
                 left: Box::new(Expression::Number(5)),
                 operator: TokenType::Plus,
                 right: Box::new(Expression::Number(5)),
+                line: 1,
             },
         ),
         (
@@ -197,6 +270,7 @@ fn infix_expr() {
                 left: Box::new(Expression::Number(5)),
                 operator: TokenType::Minus,
                 right: Box::new(Expression::Number(5)),
+                line: 1,
             },
         ),
         (
@@ -205,6 +279,7 @@ fn infix_expr() {
                 left: Box::new(Expression::Number(5)),
                 operator: TokenType::Star,
                 right: Box::new(Expression::Number(5)),
+                line: 1,
             },
         ),
         (
@@ -213,6 +288,7 @@ fn infix_expr() {
                 left: Box::new(Expression::Number(5)),
                 operator: TokenType::Slash,
                 right: Box::new(Expression::Number(5)),
+                line: 1,
             },
         ),
         (
@@ -221,6 +297,7 @@ fn infix_expr() {
                 left: Box::new(Expression::Number(5)),
                 operator: TokenType::Gt,
                 right: Box::new(Expression::Number(5)),
+                line: 1,
             },
         ),
         (
@@ -229,6 +306,7 @@ fn infix_expr() {
                 left: Box::new(Expression::Number(5)),
                 operator: TokenType::Lt,
                 right: Box::new(Expression::Number(5)),
+                line: 1,
             },
         ),
         (
@@ -237,6 +315,7 @@ fn infix_expr() {
                 left: Box::new(Expression::Number(5)),
                 operator: TokenType::Eq,
                 right: Box::new(Expression::Number(5)),
+                line: 1,
             },
         ),
         (
@@ -245,6 +324,7 @@ fn infix_expr() {
                 left: Box::new(Expression::Number(5)),
                 operator: TokenType::NotEq,
                 right: Box::new(Expression::Number(5)),
+                line: 1,
             },
         ),
     ];
@@ -300,6 +380,7 @@ fn if_else_expr() {
                     left: Box::new(Expression::Ident("x".into())),
                     operator: TokenType::Lt,
                     right: Box::new(Expression::Ident("y".into())),
+                    line: 1,
                 })),
                 if_branch: vec![Statement::Expression(Expression::Ident("x".into()))],
                 else_branch: None,
@@ -312,6 +393,7 @@ fn if_else_expr() {
                     left: Box::new(Expression::Ident("x".into())),
                     operator: TokenType::Lt,
                     right: Box::new(Expression::Ident("y".into())),
+                    line: 1,
                 })),
                 if_branch: vec![Statement::Expression(Expression::Ident("x".into()))],
                 else_branch: Some(vec![Statement::Expression(Expression::Ident("y".into()))]),
@@ -343,10 +425,13 @@ fn func_expr() {
     let input = "fn(x, y) { x * y; }";
     let expected = FuncExpr {
         params: vec!["x".into(), "y".into()],
+        defaults: vec![],
+        rest: None,
         body: vec![Statement::Expression(Expression::Infix(InfixExpr {
             left: Box::new(Expression::Ident("x".into())),
             operator: TokenType::Star,
             right: Box::new(Expression::Ident("y".into())),
+            line: 1,
         }))],
     };
 
@@ -395,6 +480,54 @@ fn func_params() {
     }
 }
 
+#[test]
+fn func_default_and_rest_params() {
+    let inputs = [
+        (
+            "fn(x, y = 10) {}",
+            (
+                vec!["x".to_string(), "y".into()],
+                vec![Expression::Number(10)],
+                None,
+            ),
+        ),
+        (
+            "fn(first, ...others) {}",
+            (
+                vec!["first".to_string()],
+                vec![],
+                Some("others".to_string()),
+            ),
+        ),
+        ("fn(...all) {}", (vec![], vec![], Some("all".to_string()))),
+    ];
+
+    for (inp, (params, defaults, rest)) in inputs {
+        let lexer = Lexer::new(inp.into());
+        let mut parser = Parser::new(lexer);
+
+        let Program { statements } = parser.parse().unwrap();
+
+        assert_eq!(1, statements.len());
+        let expr = match statements[0] {
+            Statement::Expression(ref e) => e,
+            _ => panic!("expected ExpressionStatement, got {:?}", statements[0]),
+        };
+        match &expr {
+            Expression::Func(i) => {
+                assert_eq!(i.params, params);
+                assert_eq!(i.defaults, defaults);
+                assert_eq!(i.rest, rest);
+            }
+            e => panic!("expected Func expression, got {:?}", e),
+        }
+    }
+
+    let lexer = Lexer::new("fn(x = 1, y) {}".into());
+    let mut parser = Parser::new(lexer);
+    assert!(parser.parse().is_err());
+}
+
 #[test]
 fn call_expr() {
     let input = "add(1, 2+3, x*y)";
@@ -406,13 +539,16 @@ fn call_expr() {
                 left: Box::new(Expression::Number(2)),
                 operator: TokenType::Plus,
                 right: Box::new(Expression::Number(3)),
+                line: 1,
             }),
             Expression::Infix(InfixExpr {
                 left: Box::new(Expression::Ident("x".into())),
                 operator: TokenType::Star,
                 right: Box::new(Expression::Ident("y".into())),
+                line: 1,
             }),
         ],
+        line: 1,
     };
 
     let lexer = Lexer::new(input.into());
@@ -477,11 +613,13 @@ fn array_expr() {
                         left: Box::new(Expression::Number(2)),
                         operator: TokenType::Star,
                         right: Box::new(Expression::Number(2)),
+                        line: 1,
                     }),
                     Expression::Infix(InfixExpr {
                         left: Box::new(Expression::Number(3)),
                         operator: TokenType::Plus,
                         right: Box::new(Expression::Number(3)),
+                        line: 1,
                     }),
                 ],
             }),
@@ -512,7 +650,79 @@ fn index_expr() {
             left: Box::new(Expression::Number(1)),
             operator: TokenType::Plus,
             right: Box::new(Expression::Number(3)),
+            line: 1,
         })),
+        line: 1,
+    });
+
+    let lexer = Lexer::new(input.into());
+    let mut parser = Parser::new(lexer);
+
+    let Program { statements } = parser.parse().unwrap();
+
+    assert_eq!(1, statements.len());
+    let expr = match statements[0] {
+        Statement::Expression(ref e) => e,
+        _ => panic!("expected ExpressionStatement, got {:?}", statements[0]),
+    };
+    assert_eq!(expr, &expect);
+}
+
+#[test]
+fn member_expr() {
+    let inputs = [
+        (
+            "h.name",
+            Expression::Index(IndexExpr {
+                left: Box::new(Expression::Ident("h".into())),
+                index: Box::new(Expression::String("name".into())),
+                line: 1,
+            }),
+        ),
+        (
+            "arr.push(1, ...xs)",
+            Expression::Method(MethodExpr {
+                object: Box::new(Expression::Ident("arr".into())),
+                method: "push".into(),
+                arguments: vec![
+                    Expression::Number(1),
+                    Expression::Spread(Box::new(Expression::Ident("xs".into()))),
+                ],
+                line: 1,
+            }),
+        ),
+    ];
+
+    for (inp, expect) in inputs {
+        let lexer = Lexer::new(inp.into());
+        let mut parser = Parser::new(lexer);
+
+        let Program { statements } = parser.parse().unwrap();
+
+        assert_eq!(1, statements.len());
+        let expr = match statements[0] {
+            Statement::Expression(ref e) => e,
+            _ => panic!("expected ExpressionStatement, got {:?}", statements[0]),
+        };
+        assert_eq!(expr, &expect);
+    }
+}
+
+#[test]
+fn template_expr() {
+    let input = r#""Hi ${name}, ${1 + n}""#;
+    let expect = Expression::Template(TemplateExpr {
+        parts: vec![
+            TemplateExprPart::Str("Hi ".into()),
+            TemplateExprPart::Expr(Expression::Ident("name".into())),
+            TemplateExprPart::Str(", ".into()),
+            TemplateExprPart::Expr(Expression::Infix(InfixExpr {
+                left: Box::new(Expression::Number(1)),
+                operator: TokenType::Plus,
+                right: Box::new(Expression::Ident("n".into())),
+                line: 1,
+            })),
+        ],
     });
 
     let lexer = Lexer::new(input.into());
@@ -526,6 +736,11 @@ fn index_expr() {
         _ => panic!("expected ExpressionStatement, got {:?}", statements[0]),
     };
     assert_eq!(expr, &expect);
+
+    for input in [r#""${}""#, r#""${a b}""#] {
+        let mut parser = Parser::new(Lexer::new(input.into()));
+        assert!(parser.parse().is_err(), "expected error for {}", input);
+    }
 }
 
 #[test]
@@ -543,6 +758,7 @@ fn hash_expr() {
                             left: Box::new(Expression::Number(5)),
                             operator: TokenType::Minus,
                             right: Box::new(Expression::Number(3)),
+                            line: 1,
                         }),
                     ),
                     (Expression::String("three".into()), Expression::Number(3)),
@@ -566,6 +782,217 @@ fn hash_expr() {
     }
 }
 
+#[test]
+fn match_expr() {
+    let inputs = [
+        (
+            r#"match (x) { 1 => "one", -2 => "minus two", _ => "other" }"#,
+            Expression::Match(MatchExpr {
+                value: Box::new(Expression::Ident("x".into())),
+                arms: vec![
+                    MatchArm {
+                        pattern: Pattern::Literal(Expression::Number(1)),
+                        guard: None,
+                        body: Expression::String("one".into()),
+                    },
+                    MatchArm {
+                        pattern: Pattern::Literal(Expression::Number(-2)),
+                        guard: None,
+                        body: Expression::String("minus two".into()),
+                    },
+                    MatchArm {
+                        pattern: Pattern::Wildcard,
+                        guard: None,
+                        body: Expression::String("other".into()),
+                    },
+                ],
+            }),
+        ),
+        (
+            r#"match (x) { [head, ..tail] if head > 0 => tail, {"type": t}  => t, }"#,
+            Expression::Match(MatchExpr {
+                value: Box::new(Expression::Ident("x".into())),
+                arms: vec![
+                    MatchArm {
+                        pattern: Pattern::Array(ArrayPattern {
+                            elements: vec![Pattern::Ident("head".into())],
+                            rest: Some(Box::new(Pattern::Ident("tail".into()))),
+                        }),
+                        guard: Some(Expression::Infix(InfixExpr {
+                            left: Box::new(Expression::Ident("head".into())),
+                            operator: TokenType::Gt,
+                            right: Box::new(Expression::Number(0)),
+                            line: 1,
+                        })),
+                        body: Expression::Ident("tail".into()),
+                    },
+                    MatchArm {
+                        pattern: Pattern::Hash(HashPattern {
+                            pairs: vec![(
+                                Expression::String("type".into()),
+                                Pattern::Ident("t".into()),
+                            )],
+                        }),
+                        guard: None,
+                        body: Expression::Ident("t".into()),
+                    },
+                ],
+            }),
+        ),
+        (
+            "match (x) { [_, ..] => true, [] => false }",
+            Expression::Match(MatchExpr {
+                value: Box::new(Expression::Ident("x".into())),
+                arms: vec![
+                    MatchArm {
+                        pattern: Pattern::Array(ArrayPattern {
+                            elements: vec![Pattern::Wildcard],
+                            rest: Some(Box::new(Pattern::Wildcard)),
+                        }),
+                        guard: None,
+                        body: Expression::Bool(true),
+                    },
+                    MatchArm {
+                        pattern: Pattern::Array(ArrayPattern {
+                            elements: vec![],
+                            rest: None,
+                        }),
+                        guard: None,
+                        body: Expression::Bool(false),
+                    },
+                ],
+            }),
+        ),
+    ];
+
+    for (inp, expect) in inputs {
+        let lexer = Lexer::new(inp.into());
+        let mut parser = Parser::new(lexer);
+
+        let Program { statements } = parser.parse().unwrap();
+
+        assert_eq!(1, statements.len());
+        let expr = match statements[0] {
+            Statement::Expression(ref e) => e,
+            _ => panic!("expected ExpressionStatement, got {:?}", statements[0]),
+        };
+        assert_eq!(expr, &expect);
+    }
+}
+
+#[test]
+fn try_expr() {
+    let input =
+        "let x = 1;\ntry {\n  throw x;\n} catch (e) { e } finally { x }\ntry { x } finally { }";
+    let throw = Statement::Throw(ThrowStmt {
+        expr: Expression::Ident("x".into()),
+        line: 3,
+    });
+    let expect = [
+        Expression::Try(TryExpr {
+            body: vec![throw],
+            catch: Some(CatchClause {
+                ident: "e".into(),
+                body: vec![Statement::Expression(Expression::Ident("e".into()))],
+            }),
+            finally: Some(vec![Statement::Expression(Expression::Ident("x".into()))]),
+            line: 2,
+        }),
+        Expression::Try(TryExpr {
+            body: vec![Statement::Expression(Expression::Ident("x".into()))],
+            catch: None,
+            finally: Some(vec![]),
+            line: 5,
+        }),
+    ];
+
+    let Program { statements } = Parser::new(Lexer::new(input.into())).parse().unwrap();
+    assert_eq!(3, statements.len());
+    for (stmt, expect) in statements[1..].iter().zip(expect) {
+        assert_eq!(stmt, &Statement::Expression(expect));
+    }
+
+    assert!(Parser::new(Lexer::new("try { x }".into())).parse().is_err());
+}
+
+#[test]
+fn for_expr() {
+    let input = "for (x in 1..3) { puts(x); }";
+    let expect = Expression::For(ForExpr {
+        ident: "x".into(),
+        iterable: Box::new(Expression::Range(RangeExpr {
+            start: Box::new(Expression::Number(1)),
+            end: Box::new(Expression::Number(3)),
+            inclusive: false,
+        })),
+        body: vec![Statement::Expression(Expression::Call(CallExpr {
+            func: Box::new(Expression::Ident("puts".into())),
+            arguments: vec![Expression::Ident("x".into())],
+            line: 1,
+        }))],
+    });
+
+    let Program { statements } = Parser::new(Lexer::new(input.into())).parse().unwrap();
+    assert_eq!(statements, vec![Statement::Expression(expect)]);
+
+    assert!(Parser::new(Lexer::new("for (x 1..3) { x }".into()))
+        .parse()
+        .is_err());
+}
+
+#[test]
+fn macro_literal() {
+    let input = "macro(x, y) { x + y; }";
+    let expect = Expression::Macro(FuncExpr {
+        params: vec!["x".into(), "y".into()],
+        defaults: vec![],
+        rest: None,
+        body: vec![Statement::Expression(Expression::Infix(InfixExpr {
+            left: Box::new(Expression::Ident("x".into())),
+            operator: TokenType::Plus,
+            right: Box::new(Expression::Ident("y".into())),
+            line: 1,
+        }))],
+    });
+
+    let lexer = Lexer::new(input.into());
+    let mut parser = Parser::new(lexer);
+
+    let Program { statements } = parser.parse().unwrap();
+
+    assert_eq!(1, statements.len());
+    assert_eq!(statements[0], Statement::Expression(expect));
+}
+
+#[test]
+fn modify() {
+    let inputs = [
+        ("1 + 2", "(2 + 2)\n"),
+        ("let x = [1, {1: -1}[1]];", "let x = [2, ({2: (-2)}[2])];\n"),
+        (
+            "fn f(a = 1) { return f(1)[1:1]; }",
+            "fn f(a = 2) {\n  return (f(2)[2:2]);\n}\n",
+        ),
+        (
+            r#"match (1) { x if x == 1 => "${1}" }"#,
+            "match (2) {\n  x if (x == 2) => ${2},\n}\n",
+        ),
+    ];
+
+    for (inp, exp) in inputs {
+        let lexer = Lexer::new(inp.into());
+        let mut parser = Parser::new(lexer);
+
+        let program = parser.parse().unwrap();
+        let program = modify_program(program, &mut |e| match e {
+            Expression::Number(1) => Ok(Expression::Number(2)),
+            e => Ok(e),
+        })
+        .unwrap();
+        assert_eq!(program.to_string(), exp);
+    }
+}
+
 #[test]
 fn operator_precedence() {
     let inputs = [
@@ -606,6 +1033,16 @@ fn operator_precedence() {
             "add(a * b[2], b[1], 2 * [1, 2][1])",
             "add((a * (b[2])), (b[1]), (2 * ([1, 2][1])))\n",
         ),
+        ("add(a, ...b + c)", "add(a, ...(b + c))\n"),
+        ("-a.b * c.d(e)", "((-(a[b])) * c.d(e))\n"),
+        ("a.b.c(d)[0]", "((a[b]).c(d)[0])\n"),
+        ("a + 1 |> f(b) |> g", "g(f((a + 1), b))\n"),
+        ("a |> b.c(d)", "b.c(a, d)\n"),
+        ("a[1:b + 1][:-1][2:]", "(((a[1:(b + 1)])[:(-1)])[2:])\n"),
+        ("{a[:1]: a[1]}", "{(a[:1]): (a[1])}\n"),
+        ("0..n + 1 == r", "((0..(n + 1)) == r)\n"),
+        ("a * 2..=-b", "((a * 2)..=(-b))\n"),
+        ("a |> fn(x) { x }", "fn (x) {\n  x\n}(a)\n"),
     ];
 
     for (inp, exp) in inputs {
@@ -1,8 +1,25 @@
 use crate::{
     ast::Ident,
-    eval::{ArrayObj, Object},
+    diff,
+    eval::{resolve_index, ArrayObj, HashObj, Object},
+    json,
+    policy::{self, Capability},
+    random, script, time,
 };
-use std::{fmt::Display, ops::Deref, rc::Rc};
+use std::{
+    cmp::Ordering,
+    collections::HashSet,
+    fmt::Display,
+    fs,
+    io::{self, BufRead, Read, Write},
+    path::PathBuf,
+    rc::Rc,
+};
+
+/// Engine running a builtin, which higher-order builtins use to call functions
+pub trait Caller {
+    fn call_func(&mut self, func: &Object, args: Vec<Rc<Object>>) -> Result<Rc<Object>, String>;
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum Builtin {
@@ -12,34 +29,344 @@ pub enum Builtin {
     Rest,
     Push,
     Puts,
+    Contains,
+    Array,
+    Split,
+    Join,
+    Trim,
+    Upper,
+    Lower,
+    Replace,
+    StartsWith,
+    EndsWith,
+    IndexOf,
+    Chars,
+    Repeat,
+    PadLeft,
+    PadRight,
+    Keys,
+    Values,
+    Entries,
+    Has,
+    Delete,
+    Merge,
+    Type,
+    Str,
+    Int,
+    Bool,
+    Float,
+    Repr,
+    Map,
+    Filter,
+    Reduce,
+    Sort,
+    Each,
+    Reverse,
+    Concat,
+    Flatten,
+    Unique,
+    Min,
+    Max,
+    Sum,
+    Slice,
+    Insert,
+    RemoveAt,
+    Abs,
+    Pow,
+    Sqrt,
+    Floor,
+    Ceil,
+    Round,
+    Clamp,
+    Gcd,
+    Sin,
+    Cos,
+    Tan,
+    Asin,
+    Acos,
+    Atan,
+    Atan2,
+    Exp,
+    Log,
+    Random,
+    RandomInt,
+    Shuffle,
+    Choice,
+    JsonParse,
+    JsonStringify,
+    ReadFile,
+    WriteFile,
+    AppendFile,
+    ListDir,
+    Exists,
+    RemoveFile,
+    Args,
+    ReadLine,
+    ReadAll,
+    Print,
+    Eprint,
+    Exit,
+    EnvGet,
+    EnvSet,
+    Now,
+    Monotonic,
+    Sleep,
+    FormatTime,
+    Run,
+    Assert,
+    AssertEq,
 }
 
 impl Builtin {
+    /// Every builtin, in the order of their variants
+    pub const ALL: [Builtin; std::mem::variant_count::<Builtin>()] = [
+        Builtin::Len,
+        Builtin::First,
+        Builtin::Last,
+        Builtin::Rest,
+        Builtin::Push,
+        Builtin::Puts,
+        Builtin::Contains,
+        Builtin::Array,
+        Builtin::Split,
+        Builtin::Join,
+        Builtin::Trim,
+        Builtin::Upper,
+        Builtin::Lower,
+        Builtin::Replace,
+        Builtin::StartsWith,
+        Builtin::EndsWith,
+        Builtin::IndexOf,
+        Builtin::Chars,
+        Builtin::Repeat,
+        Builtin::PadLeft,
+        Builtin::PadRight,
+        Builtin::Keys,
+        Builtin::Values,
+        Builtin::Entries,
+        Builtin::Has,
+        Builtin::Delete,
+        Builtin::Merge,
+        Builtin::Type,
+        Builtin::Str,
+        Builtin::Int,
+        Builtin::Bool,
+        Builtin::Float,
+        Builtin::Repr,
+        Builtin::Map,
+        Builtin::Filter,
+        Builtin::Reduce,
+        Builtin::Sort,
+        Builtin::Each,
+        Builtin::Reverse,
+        Builtin::Concat,
+        Builtin::Flatten,
+        Builtin::Unique,
+        Builtin::Min,
+        Builtin::Max,
+        Builtin::Sum,
+        Builtin::Slice,
+        Builtin::Insert,
+        Builtin::RemoveAt,
+        Builtin::Abs,
+        Builtin::Pow,
+        Builtin::Sqrt,
+        Builtin::Floor,
+        Builtin::Ceil,
+        Builtin::Round,
+        Builtin::Clamp,
+        Builtin::Gcd,
+        Builtin::Sin,
+        Builtin::Cos,
+        Builtin::Tan,
+        Builtin::Asin,
+        Builtin::Acos,
+        Builtin::Atan,
+        Builtin::Atan2,
+        Builtin::Exp,
+        Builtin::Log,
+        Builtin::Random,
+        Builtin::RandomInt,
+        Builtin::Shuffle,
+        Builtin::Choice,
+        Builtin::JsonParse,
+        Builtin::JsonStringify,
+        Builtin::ReadFile,
+        Builtin::WriteFile,
+        Builtin::AppendFile,
+        Builtin::ListDir,
+        Builtin::Exists,
+        Builtin::RemoveFile,
+        Builtin::Args,
+        Builtin::ReadLine,
+        Builtin::ReadAll,
+        Builtin::Print,
+        Builtin::Eprint,
+        Builtin::Exit,
+        Builtin::EnvGet,
+        Builtin::EnvSet,
+        Builtin::Now,
+        Builtin::Monotonic,
+        Builtin::Sleep,
+        Builtin::FormatTime,
+        Builtin::Run,
+        Builtin::Assert,
+        Builtin::AssertEq,
+    ];
+
+    /// Named values resolved after the builtins, unless shadowed by a definition
+    pub fn constant(ident: &str) -> Option<Object> {
+        match ident {
+            "PI" => Some(Object::Float(std::f64::consts::PI)),
+            "E" => Some(Object::Float(std::f64::consts::E)),
+            _ => None,
+        }
+    }
+
     pub fn from_ident_obj(ident: &Ident) -> Option<Rc<Object>> {
         Self::from_ident(ident).map(|s| Rc::new(Object::Builtin(s)))
     }
 
     pub fn from_u8(value: u8) -> Option<Self> {
-        if value > std::mem::variant_count::<Self>() as u8 {
+        if value >= std::mem::variant_count::<Self>() as u8 {
             None
         } else {
-            unsafe { Some(std::mem::transmute(value)) }
+            unsafe { Some(std::mem::transmute::<u8, Self>(value)) }
+        }
+    }
+
+    pub fn from_ident(ident: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|b| b.name() == ident)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Builtin::Len => "len",
+            Builtin::First => "first",
+            Builtin::Last => "last",
+            Builtin::Rest => "rest",
+            Builtin::Push => "push",
+            Builtin::Puts => "puts",
+            Builtin::Contains => "contains",
+            Builtin::Array => "array",
+            Builtin::Split => "split",
+            Builtin::Join => "join",
+            Builtin::Trim => "trim",
+            Builtin::Upper => "upper",
+            Builtin::Lower => "lower",
+            Builtin::Replace => "replace",
+            Builtin::StartsWith => "starts_with",
+            Builtin::EndsWith => "ends_with",
+            Builtin::IndexOf => "index_of",
+            Builtin::Chars => "chars",
+            Builtin::Repeat => "repeat",
+            Builtin::PadLeft => "pad_left",
+            Builtin::PadRight => "pad_right",
+            Builtin::Keys => "keys",
+            Builtin::Values => "values",
+            Builtin::Entries => "entries",
+            Builtin::Has => "has",
+            Builtin::Delete => "delete",
+            Builtin::Merge => "merge",
+            Builtin::Type => "type",
+            Builtin::Str => "str",
+            Builtin::Int => "int",
+            Builtin::Bool => "bool",
+            Builtin::Float => "float",
+            Builtin::Repr => "repr",
+            Builtin::Map => "map",
+            Builtin::Filter => "filter",
+            Builtin::Reduce => "reduce",
+            Builtin::Sort => "sort",
+            Builtin::Each => "each",
+            Builtin::Reverse => "reverse",
+            Builtin::Concat => "concat",
+            Builtin::Flatten => "flatten",
+            Builtin::Unique => "unique",
+            Builtin::Min => "min",
+            Builtin::Max => "max",
+            Builtin::Sum => "sum",
+            Builtin::Slice => "slice",
+            Builtin::Insert => "insert",
+            Builtin::RemoveAt => "remove_at",
+            Builtin::Abs => "abs",
+            Builtin::Pow => "pow",
+            Builtin::Sqrt => "sqrt",
+            Builtin::Floor => "floor",
+            Builtin::Ceil => "ceil",
+            Builtin::Round => "round",
+            Builtin::Clamp => "clamp",
+            Builtin::Gcd => "gcd",
+            Builtin::Sin => "sin",
+            Builtin::Cos => "cos",
+            Builtin::Tan => "tan",
+            Builtin::Asin => "asin",
+            Builtin::Acos => "acos",
+            Builtin::Atan => "atan",
+            Builtin::Atan2 => "atan2",
+            Builtin::Exp => "exp",
+            Builtin::Log => "log",
+            Builtin::Random => "random",
+            Builtin::RandomInt => "random_int",
+            Builtin::Shuffle => "shuffle",
+            Builtin::Choice => "choice",
+            Builtin::JsonParse => "json_parse",
+            Builtin::JsonStringify => "json_stringify",
+            Builtin::ReadFile => "read_file",
+            Builtin::WriteFile => "write_file",
+            Builtin::AppendFile => "append_file",
+            Builtin::ListDir => "list_dir",
+            Builtin::Exists => "exists",
+            Builtin::RemoveFile => "remove_file",
+            Builtin::Args => "args",
+            Builtin::ReadLine => "read_line",
+            Builtin::ReadAll => "read_all",
+            Builtin::Print => "print",
+            Builtin::Eprint => "eprint",
+            Builtin::Exit => "exit",
+            Builtin::EnvGet => "env_get",
+            Builtin::EnvSet => "env_set",
+            Builtin::Now => "now",
+            Builtin::Monotonic => "monotonic",
+            Builtin::Sleep => "sleep",
+            Builtin::FormatTime => "format_time",
+            Builtin::Run => "run",
+            Builtin::Assert => "assert",
+            Builtin::AssertEq => "assert_eq",
         }
     }
 
-    pub fn from_ident(ident: &Ident) -> Option<Self> {
-        match ident.as_str() {
-            "len" => Some(Builtin::Len),
-            "first" => Some(Builtin::First),
-            "last" => Some(Builtin::Last),
-            "rest" => Some(Builtin::Rest),
-            "push" => Some(Builtin::Push),
-            "puts" => Some(Builtin::Puts),
+    /// Builtin called by `receiver.name(..)`, with the receiver as its first argument
+    pub fn method(receiver: &Object, name: &str) -> Option<Self> {
+        match (receiver, name) {
+            (
+                Object::Array(_),
+                "len" | "first" | "last" | "rest" | "push" | "contains" | "index_of" | "concat"
+                | "flatten" | "slice" | "insert" | "remove_at" | "join",
+            )
+            | (Object::Range(_), "len" | "contains" | "array" | "slice")
+            | (
+                Object::Array(_) | Object::Range(_),
+                "map" | "filter" | "reduce" | "sort" | "each" | "reverse" | "unique" | "min"
+                | "max" | "sum" | "shuffle" | "choice",
+            )
+            | (
+                Object::String(_),
+                "len" | "split" | "trim" | "upper" | "lower" | "replace" | "contains"
+                | "starts_with" | "ends_with" | "index_of" | "chars" | "repeat" | "pad_left"
+                | "pad_right",
+            )
+            | (Object::Hash(_), "keys" | "values" | "entries" | "has" | "delete" | "merge")
+            | (_, "type" | "str" | "int" | "bool" | "float" | "repr") => Self::from_ident(name),
             _ => None,
         }
     }
 
-    pub fn call<T: From<Object> + Display>(&self, args: Vec<&Object>) -> Result<T, String> {
+    pub fn call<T: From<Object> + Display>(
+        &self,
+        args: Vec<&Object>,
+        caller: &mut dyn Caller,
+    ) -> Result<T, String> {
         match self {
             Builtin::Len => len(args).map(Into::into),
             Builtin::First => first(args).map(Into::into),
@@ -47,21 +374,124 @@ impl Builtin {
             Builtin::Rest => rest(args).map(Into::into),
             Builtin::Push => push(args).map(Into::into),
             Builtin::Puts => puts(args).map(Into::into),
+            Builtin::Contains => contains(args).map(Into::into),
+            Builtin::Array => array(args).map(Into::into),
+            Builtin::Split => split(args).map(Into::into),
+            Builtin::Join => join(args).map(Into::into),
+            Builtin::Trim => trim(args).map(Into::into),
+            Builtin::Upper => upper(args).map(Into::into),
+            Builtin::Lower => lower(args).map(Into::into),
+            Builtin::Replace => replace(args).map(Into::into),
+            Builtin::StartsWith => starts_with(args).map(Into::into),
+            Builtin::EndsWith => ends_with(args).map(Into::into),
+            Builtin::IndexOf => index_of(args).map(Into::into),
+            Builtin::Chars => chars(args).map(Into::into),
+            Builtin::Repeat => repeat(args).map(Into::into),
+            Builtin::PadLeft => pad_left(args).map(Into::into),
+            Builtin::PadRight => pad_right(args).map(Into::into),
+            Builtin::Keys => keys(args).map(Into::into),
+            Builtin::Values => values(args).map(Into::into),
+            Builtin::Entries => entries(args).map(Into::into),
+            Builtin::Has => has(args).map(Into::into),
+            Builtin::Delete => delete(args).map(Into::into),
+            Builtin::Merge => merge(args).map(Into::into),
+            Builtin::Type => type_of(args).map(Into::into),
+            Builtin::Str => to_str(args).map(Into::into),
+            Builtin::Int => to_int(args).map(Into::into),
+            Builtin::Bool => to_bool(args).map(Into::into),
+            Builtin::Float => to_float(args).map(Into::into),
+            Builtin::Repr => repr(args).map(Into::into),
+            Builtin::Map => map(args, caller).map(Into::into),
+            Builtin::Filter => filter(args, caller).map(Into::into),
+            Builtin::Reduce => reduce(args, caller).map(Into::into),
+            Builtin::Sort => sort(args, caller).map(Into::into),
+            Builtin::Each => each(args, caller).map(Into::into),
+            Builtin::Reverse => reverse(args).map(Into::into),
+            Builtin::Concat => concat(args).map(Into::into),
+            Builtin::Flatten => flatten(args).map(Into::into),
+            Builtin::Unique => unique(args).map(Into::into),
+            Builtin::Min => min(args).map(Into::into),
+            Builtin::Max => max(args).map(Into::into),
+            Builtin::Sum => sum(args).map(Into::into),
+            Builtin::Slice => slice(args).map(Into::into),
+            Builtin::Insert => insert(args).map(Into::into),
+            Builtin::RemoveAt => remove_at(args).map(Into::into),
+            Builtin::Abs => abs(args).map(Into::into),
+            Builtin::Pow => pow(args).map(Into::into),
+            Builtin::Sqrt => sqrt(args).map(Into::into),
+            Builtin::Floor => floor(args).map(Into::into),
+            Builtin::Ceil => ceil(args).map(Into::into),
+            Builtin::Round => round(args).map(Into::into),
+            Builtin::Clamp => clamp(args).map(Into::into),
+            Builtin::Gcd => gcd(args).map(Into::into),
+            Builtin::Sin => sin(args).map(Into::into),
+            Builtin::Cos => cos(args).map(Into::into),
+            Builtin::Tan => tan(args).map(Into::into),
+            Builtin::Asin => asin(args).map(Into::into),
+            Builtin::Acos => acos(args).map(Into::into),
+            Builtin::Atan => atan(args).map(Into::into),
+            Builtin::Atan2 => atan2(args).map(Into::into),
+            Builtin::Exp => exp(args).map(Into::into),
+            Builtin::Log => log(args).map(Into::into),
+            Builtin::Random => random(args).map(Into::into),
+            Builtin::RandomInt => random_int(args).map(Into::into),
+            Builtin::Shuffle => shuffle(args).map(Into::into),
+            Builtin::Choice => choice(args).map(Into::into),
+            Builtin::JsonParse => json_parse(args).map(Into::into),
+            Builtin::JsonStringify => json_stringify(args).map(Into::into),
+            Builtin::ReadFile => read_file(args).map(Into::into),
+            Builtin::WriteFile => write_file(args, false).map(Into::into),
+            Builtin::AppendFile => write_file(args, true).map(Into::into),
+            Builtin::ListDir => list_dir(args).map(Into::into),
+            Builtin::Exists => exists(args).map(Into::into),
+            Builtin::RemoveFile => remove_file(args).map(Into::into),
+            Builtin::Args => script_args(args).map(Into::into),
+            Builtin::ReadLine => read_line(args).map(Into::into),
+            Builtin::ReadAll => read_all(args).map(Into::into),
+            Builtin::Print => print(args).map(Into::into),
+            Builtin::Eprint => eprint(args).map(Into::into),
+            Builtin::Exit => exit(args).map(Into::into),
+            Builtin::EnvGet => env_get(args).map(Into::into),
+            Builtin::EnvSet => env_set(args).map(Into::into),
+            Builtin::Now => now(args).map(Into::into),
+            Builtin::Monotonic => monotonic(args).map(Into::into),
+            Builtin::Sleep => sleep(args).map(Into::into),
+            Builtin::FormatTime => format_time(args).map(Into::into),
+            Builtin::Run => run(args).map(Into::into),
+            Builtin::Assert => assert(args).map(Into::into),
+            Builtin::AssertEq => assert_eq(args).map(Into::into),
         }
     }
 }
 
-fn len(args: Vec<&Object>) -> Result<Object, String> {
-    if args.len() != 1 {
+fn check_args(args: &[&Object], expected: usize) -> Result<(), String> {
+    if args.len() != expected {
         return Err(format!(
-            "wrong number of arguments. expected 1, got {}",
+            "wrong number of arguments. expected {}, got {}",
+            expected,
             args.len()
         ));
     }
+    Ok(())
+}
+
+/// Error for arguments of the wrong kinds, listing all of them
+fn unsupported(name: &str, args: &[&Object]) -> String {
+    let kinds: Vec<_> = args.iter().map(|a| a.kind()).collect();
+    format!(
+        "arguments to `{}` not supported, got {}",
+        name,
+        kinds.join(", ")
+    )
+}
 
-    match &*args[0] {
-        Object::String(s) => Ok(Object::Integer(s.len() as i64).into()),
-        Object::Array(a) => Ok(Object::Integer(a.elements.len() as i64).into()),
+fn len(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 1)?;
+
+    match args[0] {
+        Object::String(s) => Ok(Object::Integer(s.chars().count() as i64)),
+        Object::Array(a) => Ok(Object::Integer(a.elements.len() as i64)),
+        Object::Range(r) => Ok(Object::Integer(r.len())),
         _ => Err(format!(
             "argument to `len` not supported, got {}",
             args[0].kind()
@@ -70,14 +500,9 @@ fn len(args: Vec<&Object>) -> Result<Object, String> {
 }
 
 fn first(args: Vec<&Object>) -> Result<Object, String> {
-    if args.len() != 1 {
-        return Err(format!(
-            "wrong number of arguments. expected 1, got {}",
-            args.len()
-        ));
-    }
+    check_args(&args, 1)?;
 
-    match &*args[0] {
+    match args[0] {
         Object::Array(a) => {
             let f = a
                 .elements
@@ -85,7 +510,7 @@ fn first(args: Vec<&Object>) -> Result<Object, String> {
                 .cloned()
                 .map(|r| (*r).clone())
                 .unwrap_or(Object::Null);
-            Ok(f.into())
+            Ok(f)
         }
         _ => Err(format!(
             "argument to `first` not supported, got {}",
@@ -95,14 +520,9 @@ fn first(args: Vec<&Object>) -> Result<Object, String> {
 }
 
 fn last(args: Vec<&Object>) -> Result<Object, String> {
-    if args.len() != 1 {
-        return Err(format!(
-            "wrong number of arguments. expected 1, got {}",
-            args.len()
-        ));
-    }
+    check_args(&args, 1)?;
 
-    match &*args[0] {
+    match args[0] {
         Object::Array(a) => {
             let l = a
                 .elements
@@ -110,7 +530,7 @@ fn last(args: Vec<&Object>) -> Result<Object, String> {
                 .cloned()
                 .map(|r| (*r).clone())
                 .unwrap_or(Object::Null);
-            Ok(l.into())
+            Ok(l)
         }
         _ => Err(format!(
             "argument to `last` not supported, got {}",
@@ -120,17 +540,12 @@ fn last(args: Vec<&Object>) -> Result<Object, String> {
 }
 
 fn rest(args: Vec<&Object>) -> Result<Object, String> {
-    if args.len() != 1 {
-        return Err(format!(
-            "wrong number of arguments. expected 1, got {}",
-            args.len()
-        ));
-    }
+    check_args(&args, 1)?;
 
-    match &*args[0] {
+    match args[0] {
         Object::Array(a) => {
-            let elements = a.elements.clone().into_iter().skip(1).collect();
-            Ok(Object::Array(ArrayObj { elements }).into())
+            let elements = a.elements.get(1..).unwrap_or_default().to_vec();
+            Ok(Object::Array(ArrayObj { elements }))
         }
         _ => Err(format!(
             "argument to `rest` not supported, got {}",
@@ -140,18 +555,14 @@ fn rest(args: Vec<&Object>) -> Result<Object, String> {
 }
 
 fn push(args: Vec<&Object>) -> Result<Object, String> {
-    if args.len() != 2 {
-        return Err(format!(
-            "wrong number of arguments. expected 2, got {}",
-            args.len()
-        ));
-    }
+    check_args(&args, 2)?;
 
-    match &*args[0] {
+    match args[0] {
         Object::Array(a) => {
-            let mut elements = a.elements.clone();
+            let mut elements = Vec::with_capacity(a.elements.len() + 1);
+            elements.extend_from_slice(&a.elements);
             elements.push(args[1].clone().into());
-            Ok(Object::Array(ArrayObj { elements }).into())
+            Ok(Object::Array(ArrayObj { elements }))
         }
         _ => Err(format!(
             "argument to `push` not supported, got {}",
@@ -164,5 +575,1185 @@ fn puts(args: Vec<&Object>) -> Result<Object, String> {
     for arg in args {
         println!("{}", arg);
     }
-    Ok(Object::Null.into())
+    Ok(Object::Null)
+}
+
+/// Writes the arguments to stdout without separators or a trailing newline
+fn print(args: Vec<&Object>) -> Result<Object, String> {
+    write_args(io::stdout().lock(), "stdout", args)
+}
+
+/// Like `print`, but to stderr
+fn eprint(args: Vec<&Object>) -> Result<Object, String> {
+    write_args(io::stderr().lock(), "stderr", args)
+}
+
+fn write_args(mut out: impl Write, stream: &str, args: Vec<&Object>) -> Result<Object, String> {
+    for arg in args {
+        write!(out, "{}", arg).map_err(|e| format!("cannot write to {}: {}", stream, e))?;
+    }
+    out.flush()
+        .map(|_| Object::Null)
+        .map_err(|e| format!("cannot write to {}: {}", stream, e))
+}
+
+fn contains(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 2)?;
+
+    match (args[0], args[1]) {
+        (Object::Array(a), x) => Ok(Object::Bool(a.elements.iter().any(|e| **e == *x))),
+        (Object::Range(r), Object::Integer(x)) => Ok(Object::Bool(r.contains(*x))),
+        (Object::Range(_), _) => Ok(Object::Bool(false)),
+        (Object::String(s), Object::String(x)) => Ok(Object::Bool(s.contains(x.as_str()))),
+        _ => Err(format!(
+            "argument to `contains` not supported, got {}",
+            args[0].kind()
+        )),
+    }
+}
+
+fn array(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 1)?;
+
+    match args[0] {
+        Object::Array(a) => Ok(Object::Array(a.clone())),
+        Object::Range(r) => Ok(Object::Array(ArrayObj {
+            elements: r.collect()?,
+        })),
+        _ => Err(format!(
+            "argument to `array` not supported, got {}",
+            args[0].kind()
+        )),
+    }
+}
+
+fn split(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 2)?;
+
+    match (args[0], args[1]) {
+        (Object::String(s), Object::String(sep)) => {
+            let parts: Vec<String> = if sep.is_empty() {
+                s.chars().map(String::from).collect()
+            } else {
+                s.split(sep.as_str()).map(String::from).collect()
+            };
+            Ok(Object::Array(ArrayObj {
+                elements: parts
+                    .into_iter()
+                    .map(|p| Rc::new(Object::String(p)))
+                    .collect(),
+            }))
+        }
+        _ => Err(unsupported("split", &args)),
+    }
+}
+
+fn join(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 2)?;
+
+    match (args[0], args[1]) {
+        (Object::Array(a), Object::String(sep)) => {
+            let parts: Vec<_> = a.elements.iter().map(|e| e.to_string()).collect();
+            Ok(Object::String(parts.join(sep)))
+        }
+        _ => Err(unsupported("join", &args)),
+    }
+}
+
+/// Builtin taking a single string and returning a new one
+fn map_string(name: &str, args: Vec<&Object>, f: fn(&str) -> String) -> Result<Object, String> {
+    check_args(&args, 1)?;
+
+    match args[0] {
+        Object::String(s) => Ok(Object::String(f(s))),
+        _ => Err(format!(
+            "argument to `{}` not supported, got {}",
+            name,
+            args[0].kind()
+        )),
+    }
+}
+
+fn trim(args: Vec<&Object>) -> Result<Object, String> {
+    map_string("trim", args, |s| s.trim().to_string())
+}
+
+fn upper(args: Vec<&Object>) -> Result<Object, String> {
+    map_string("upper", args, str::to_uppercase)
+}
+
+fn lower(args: Vec<&Object>) -> Result<Object, String> {
+    map_string("lower", args, str::to_lowercase)
+}
+
+fn replace(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 3)?;
+
+    match (args[0], args[1], args[2]) {
+        (Object::String(s), Object::String(from), Object::String(to)) => {
+            Ok(Object::String(s.replace(from.as_str(), to)))
+        }
+        _ => Err(unsupported("replace", &args)),
+    }
+}
+
+fn starts_with(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 2)?;
+
+    match (args[0], args[1]) {
+        (Object::String(s), Object::String(x)) => Ok(Object::Bool(s.starts_with(x.as_str()))),
+        _ => Err(unsupported("starts_with", &args)),
+    }
+}
+
+fn ends_with(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 2)?;
+
+    match (args[0], args[1]) {
+        (Object::String(s), Object::String(x)) => Ok(Object::Bool(s.ends_with(x.as_str()))),
+        _ => Err(unsupported("ends_with", &args)),
+    }
+}
+
+/// Character index of the first occurrence, or -1
+fn index_of(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 2)?;
+
+    match (args[0], args[1]) {
+        (Object::String(s), Object::String(x)) => Ok(Object::Integer(
+            s.find(x.as_str())
+                .map_or(-1, |i| s[..i].chars().count() as i64),
+        )),
+        (Object::Array(a), x) => Ok(Object::Integer(
+            a.elements
+                .iter()
+                .position(|e| **e == *x)
+                .map_or(-1, |i| i as i64),
+        )),
+        _ => Err(unsupported("index_of", &args)),
+    }
+}
+
+fn chars(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 1)?;
+
+    match args[0] {
+        Object::String(s) => Ok(Object::Array(ArrayObj {
+            elements: s
+                .chars()
+                .map(|c| Rc::new(Object::String(c.into())))
+                .collect(),
+        })),
+        _ => Err(format!(
+            "argument to `chars` not supported, got {}",
+            args[0].kind()
+        )),
+    }
+}
+
+fn repeat(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 2)?;
+
+    match (args[0], args[1]) {
+        (Object::String(s), Object::Integer(n)) if *n >= 0 => {
+            Ok(Object::String(repeat_str("repeat", s, *n as usize)?))
+        }
+        (Object::String(_), Object::Integer(n)) => {
+            Err(format!("repeat count must not be negative, got {}", n))
+        }
+        _ => Err(unsupported("repeat", &args)),
+    }
+}
+
+/// Largest string in bytes that `repeat` and the padding builtins build
+const MAX_STRING_LEN: usize = 1 << 30;
+
+/// `s` repeated `n` times, failing instead of aborting when the result is too large
+fn repeat_str(name: &str, s: &str, n: usize) -> Result<String, String> {
+    match s.len().checked_mul(n) {
+        Some(len) if len <= MAX_STRING_LEN => Ok(s.repeat(n)),
+        _ => Err(format!("result of `{}` is too large", name)),
+    }
+}
+
+fn pad_left(args: Vec<&Object>) -> Result<Object, String> {
+    pad("pad_left", args, |s, fill| fill + s)
+}
+
+fn pad_right(args: Vec<&Object>) -> Result<Object, String> {
+    pad("pad_right", args, |s, fill| s.to_string() + &fill)
+}
+
+/// Pads a string to a width in characters, with spaces or the given single character
+fn pad(name: &str, args: Vec<&Object>, join: fn(&str, String) -> String) -> Result<Object, String> {
+    if args.len() != 2 && args.len() != 3 {
+        return Err(format!(
+            "wrong number of arguments. expected 2 or 3, got {}",
+            args.len()
+        ));
+    }
+
+    let fill = match args.get(2) {
+        None => ' ',
+        Some(Object::String(f)) if f.chars().count() == 1 => f.chars().next().unwrap(),
+        Some(Object::String(f)) => {
+            return Err(format!("padding must be a single character, got {:?}", f))
+        }
+        Some(_) => return Err(unsupported(name, &args)),
+    };
+    match (args[0], args[1]) {
+        (Object::String(s), Object::Integer(width)) => {
+            let missing = (*width).max(0) as usize;
+            let missing = missing.saturating_sub(s.chars().count());
+            let fill = repeat_str(name, &fill.to_string(), missing)?;
+            Ok(Object::String(join(s, fill)))
+        }
+        _ => Err(unsupported(name, &args)),
+    }
+}
+
+/// Array built from the pairs of a hash, in insertion order
+fn hash_pairs(
+    name: &str,
+    args: Vec<&Object>,
+    f: fn(&Rc<Object>, &Rc<Object>) -> Rc<Object>,
+) -> Result<Object, String> {
+    check_args(&args, 1)?;
+
+    match args[0] {
+        Object::Hash(h) => Ok(Object::Array(ArrayObj {
+            elements: h.iter().map(|(k, v)| f(k, v)).collect(),
+        })),
+        _ => Err(format!(
+            "argument to `{}` not supported, got {}",
+            name,
+            args[0].kind()
+        )),
+    }
+}
+
+fn keys(args: Vec<&Object>) -> Result<Object, String> {
+    hash_pairs("keys", args, |k, _| k.clone())
+}
+
+fn values(args: Vec<&Object>) -> Result<Object, String> {
+    hash_pairs("values", args, |_, v| v.clone())
+}
+
+fn entries(args: Vec<&Object>) -> Result<Object, String> {
+    hash_pairs("entries", args, |k, v| {
+        Rc::new(Object::Array(ArrayObj {
+            elements: vec![k.clone(), v.clone()],
+        }))
+    })
+}
+
+fn has(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 2)?;
+
+    match (args[0], args[1]) {
+        (Object::Hash(h), key) if key.is_hashable() => Ok(Object::Bool(h.contains_key(key))),
+        (Object::Hash(_), key) => Err(format!("unusable as hash key: {}", key.kind())),
+        _ => Err(unsupported("has", &args)),
+    }
+}
+
+/// Copy of the hash without the key
+fn delete(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 2)?;
+
+    match (args[0], args[1]) {
+        (Object::Hash(h), key) if key.is_hashable() => {
+            let mut h = h.clone();
+            h.remove(key);
+            Ok(Object::Hash(h))
+        }
+        (Object::Hash(_), key) => Err(format!("unusable as hash key: {}", key.kind())),
+        _ => Err(unsupported("delete", &args)),
+    }
+}
+
+/// Copy of the first hash with the pairs of the second added, replacing existing keys
+fn merge(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 2)?;
+
+    match (args[0], args[1]) {
+        (Object::Hash(a), Object::Hash(b)) => {
+            let mut merged: HashObj = a.clone();
+            for (k, v) in b.iter() {
+                merged.insert(k.clone(), v.clone());
+            }
+            Ok(Object::Hash(merged))
+        }
+        _ => Err(unsupported("merge", &args)),
+    }
+}
+
+fn type_of(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 1)?;
+    Ok(Object::String(args[0].kind().into()))
+}
+
+fn to_str(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 1)?;
+    Ok(Object::String(args[0].to_string()))
+}
+
+/// Integers from floats (truncated), bools and strings
+fn to_int(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 1)?;
+
+    match args[0] {
+        Object::Integer(x) => Ok(Object::Integer(*x)),
+        Object::Float(x) => float_to_int(*x).map(Object::Integer),
+        Object::Bool(b) => Ok(Object::Integer(*b as i64)),
+        Object::String(s) => s
+            .trim()
+            .parse()
+            .map(Object::Integer)
+            .map_err(|_| format!("could not parse {:?} as INTEGER", s)),
+        _ => Err(format!(
+            "argument to `int` not supported, got {}",
+            args[0].kind()
+        )),
+    }
+}
+
+fn to_bool(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 1)?;
+    Ok(Object::Bool(args[0].is_truthy()))
+}
+
+fn to_float(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 1)?;
+
+    match args[0] {
+        Object::Integer(x) => Ok(Object::Float(*x as f64)),
+        Object::Float(x) => Ok(Object::Float(*x)),
+        Object::String(s) => s
+            .trim()
+            .parse()
+            .map(Object::Float)
+            .map_err(|_| format!("could not parse {:?} as FLOAT", s)),
+        _ => Err(format!(
+            "argument to `float` not supported, got {}",
+            args[0].kind()
+        )),
+    }
+}
+
+fn repr(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 1)?;
+    Ok(Object::String(args[0].repr()))
+}
+
+/// Elements of an array or range, for builtins iterating over either
+fn iter_items<'a>(
+    name: &str,
+    obj: &'a Object,
+) -> Result<Box<dyn Iterator<Item = Rc<Object>> + 'a>, String> {
+    match obj {
+        Object::Array(a) => Ok(Box::new(a.elements.iter().cloned())),
+        Object::Range(r) => Ok(Box::new(
+            (r.start..r.end).map(|i| Rc::new(Object::Integer(i))),
+        )),
+        _ => Err(format!(
+            "argument to `{}` not supported, got {}",
+            name,
+            obj.kind()
+        )),
+    }
+}
+
+/// Elements of an array or range, for builtins needing all of them at once
+fn array_items(name: &str, obj: &Object) -> Result<Vec<Rc<Object>>, String> {
+    match obj {
+        Object::Range(r) => r.collect(),
+        _ => Ok(iter_items(name, obj)?.collect()),
+    }
+}
+
+fn map(args: Vec<&Object>, caller: &mut dyn Caller) -> Result<Object, String> {
+    check_args(&args, 2)?;
+
+    let elements = iter_items("map", args[0])?
+        .map(|e| caller.call_func(args[1], vec![e]))
+        .collect::<Result<_, _>>()?;
+    Ok(Object::Array(ArrayObj { elements }))
+}
+
+fn filter(args: Vec<&Object>, caller: &mut dyn Caller) -> Result<Object, String> {
+    check_args(&args, 2)?;
+
+    let mut elements = vec![];
+    for e in iter_items("filter", args[0])? {
+        if caller.call_func(args[1], vec![e.clone()])?.is_truthy() {
+            elements.push(e);
+        }
+    }
+    Ok(Object::Array(ArrayObj { elements }))
+}
+
+fn reduce(args: Vec<&Object>, caller: &mut dyn Caller) -> Result<Object, String> {
+    check_args(&args, 3)?;
+
+    let mut acc = Rc::new(args[1].clone());
+    for e in iter_items("reduce", args[0])? {
+        acc = caller.call_func(args[2], vec![acc, e])?;
+    }
+    Ok(Rc::unwrap_or_clone(acc))
+}
+
+fn each(args: Vec<&Object>, caller: &mut dyn Caller) -> Result<Object, String> {
+    check_args(&args, 2)?;
+
+    for e in iter_items("each", args[0])? {
+        caller.call_func(args[1], vec![e])?;
+    }
+    Ok(Object::Null)
+}
+
+/// Stable sort in natural order, or where `cmp(a, b)` returns a negative
+/// integer when `a` goes before `b`
+fn sort(args: Vec<&Object>, caller: &mut dyn Caller) -> Result<Object, String> {
+    let items = match args.len() {
+        1 | 2 => array_items("sort", args[0])?,
+        n => {
+            return Err(format!(
+                "wrong number of arguments. expected 1 or 2, got {}",
+                n
+            ))
+        }
+    };
+
+    let elements = match args.get(1) {
+        None => merge_sort(items, &mut |a, b| Ok(a.compare(b)?.is_lt()))?,
+        Some(cmp) => merge_sort(items, &mut |a, b| match &*caller
+            .call_func(cmp, vec![a.clone(), b.clone()])?
+        {
+            Object::Integer(x) => Ok(*x < 0),
+            o => Err(format!(
+                "sort comparator must return INTEGER, got {}",
+                o.kind()
+            )),
+        })?,
+    };
+    Ok(Object::Array(ArrayObj { elements }))
+}
+
+/// Merge sort with a fallible comparison, which `slice::sort_by` does not allow
+fn merge_sort<F>(mut items: Vec<Rc<Object>>, before: &mut F) -> Result<Vec<Rc<Object>>, String>
+where
+    F: FnMut(&Rc<Object>, &Rc<Object>) -> Result<bool, String>,
+{
+    if items.len() < 2 {
+        return Ok(items);
+    }
+
+    let right = items.split_off(items.len() / 2);
+    let left = merge_sort(items, before)?;
+    let right = merge_sort(right, before)?;
+
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let (mut left, mut right) = (left.into_iter().peekable(), right.into_iter().peekable());
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        if before(r, l)? {
+            merged.extend(right.next());
+        } else {
+            merged.extend(left.next());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+fn reverse(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 1)?;
+
+    let mut elements = array_items("reverse", args[0])?;
+    elements.reverse();
+    Ok(Object::Array(ArrayObj { elements }))
+}
+
+fn concat(args: Vec<&Object>) -> Result<Object, String> {
+    let mut elements = vec![];
+    for arg in &args {
+        match arg {
+            Object::Array(a) => elements.extend_from_slice(&a.elements),
+            _ => return Err(unsupported("concat", &args)),
+        }
+    }
+    Ok(Object::Array(ArrayObj { elements }))
+}
+
+/// Flattens nested arrays by one level
+fn flatten(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 1)?;
+
+    match args[0] {
+        Object::Array(a) => {
+            let mut elements = Vec::with_capacity(a.elements.len());
+            for e in &a.elements {
+                match &**e {
+                    Object::Array(inner) => elements.extend_from_slice(&inner.elements),
+                    _ => elements.push(e.clone()),
+                }
+            }
+            Ok(Object::Array(ArrayObj { elements }))
+        }
+        _ => Err(format!(
+            "argument to `flatten` not supported, got {}",
+            args[0].kind()
+        )),
+    }
+}
+
+/// Keeps the first occurrence of each element
+fn unique(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 1)?;
+
+    let mut seen = HashSet::new();
+    let mut elements: Vec<Rc<Object>> = vec![];
+    for e in array_items("unique", args[0])? {
+        let new = if let Some(key) = e.hash_key() {
+            seen.insert(key)
+        } else {
+            !elements.contains(&e)
+        };
+        if new {
+            elements.push(e);
+        }
+    }
+    Ok(Object::Array(ArrayObj { elements }))
+}
+
+/// Values compared by `min` and `max`: a single array or range, or the arguments themselves
+fn extremum(name: &str, args: Vec<&Object>, keep: Ordering) -> Result<Object, String> {
+    let items = match args[..] {
+        [] => return Err("wrong number of arguments. expected at least 1, got 0".to_string()),
+        [Object::Array(_) | Object::Range(_)] => iter_items(name, args[0])?,
+        _ => Box::new(args.iter().map(|a| Rc::new((*a).clone()))),
+    };
+
+    let mut best: Option<Rc<Object>> = None;
+    for item in items {
+        best = match best {
+            Some(b) if item.compare(&b)? != keep => Some(b),
+            _ => Some(item),
+        };
+    }
+    Ok(best.map_or(Object::Null, Rc::unwrap_or_clone))
+}
+
+fn min(args: Vec<&Object>) -> Result<Object, String> {
+    extremum("min", args, Ordering::Less)
+}
+
+fn max(args: Vec<&Object>) -> Result<Object, String> {
+    extremum("max", args, Ordering::Greater)
+}
+
+/// Sum of integers, or a float as soon as one element is a float
+fn sum(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 1)?;
+
+    let mut total = Object::Integer(0);
+    for e in iter_items("sum", args[0])? {
+        total = match (&total, &*e) {
+            (Object::Integer(a), Object::Integer(b)) => {
+                Object::Integer(a.checked_add(*b).ok_or("integer overflow")?)
+            }
+            (a, b) if Object::is_float_op(a, b) => {
+                Object::Float(a.as_float().unwrap() + b.as_float().unwrap())
+            }
+            (_, b) => return Err(format!("cannot sum {}", b.kind())),
+        };
+    }
+    Ok(total)
+}
+
+fn slice(args: Vec<&Object>) -> Result<Object, String> {
+    match args.len() {
+        2 => args[0].slice(args[1], &Object::Null),
+        3 => args[0].slice(args[1], args[2]),
+        n => Err(format!(
+            "wrong number of arguments. expected 2 or 3, got {}",
+            n
+        )),
+    }
+}
+
+/// Copy of the array with the value inserted at the index, where -1 is after the last element
+fn insert(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 3)?;
+
+    match (args[0], args[1]) {
+        (Object::Array(a), Object::Integer(i)) => {
+            let len = a.elements.len();
+            let index = resolve_index(*i, len + 1)
+                .ok_or(format!("index {} out of range for length {}", i, len))?;
+
+            let mut elements = Vec::with_capacity(len + 1);
+            elements.extend_from_slice(&a.elements[..index]);
+            elements.push(Rc::new(args[2].clone()));
+            elements.extend_from_slice(&a.elements[index..]);
+            Ok(Object::Array(ArrayObj { elements }))
+        }
+        _ => Err(unsupported("insert", &args)),
+    }
+}
+
+/// Copy of the array without the element at the index
+fn remove_at(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 2)?;
+
+    match (args[0], args[1]) {
+        (Object::Array(a), Object::Integer(i)) => {
+            let len = a.elements.len();
+            let index = resolve_index(*i, len)
+                .ok_or(format!("index {} out of range for length {}", i, len))?;
+
+            let mut elements = Vec::with_capacity(len - 1);
+            elements.extend_from_slice(&a.elements[..index]);
+            elements.extend_from_slice(&a.elements[index + 1..]);
+            Ok(Object::Array(ArrayObj { elements }))
+        }
+        _ => Err(unsupported("remove_at", &args)),
+    }
+}
+
+/// Numeric value of an integer or float argument
+fn number(name: &str, arg: &Object) -> Result<f64, String> {
+    arg.as_float().ok_or(format!(
+        "argument to `{}` not supported, got {}",
+        name,
+        arg.kind()
+    ))
+}
+
+/// Builtin applying a float function to a single number
+fn float_fn(name: &str, args: Vec<&Object>, f: fn(f64) -> f64) -> Result<Object, String> {
+    check_args(&args, 1)?;
+    Ok(Object::Float(f(number(name, args[0])?)))
+}
+
+/// Builtin rounding a number to an integer
+fn round_fn(name: &str, args: Vec<&Object>, f: fn(f64) -> f64) -> Result<Object, String> {
+    check_args(&args, 1)?;
+
+    match args[0] {
+        Object::Integer(x) => Ok(Object::Integer(*x)),
+        arg => float_to_int(f(number(name, arg)?)).map(Object::Integer),
+    }
+}
+
+/// Integer value of a float, failing for NaN and values out of the integer range
+fn float_to_int(x: f64) -> Result<i64, String> {
+    // i64::MIN is exactly -2^63, while i64::MAX rounds up to 2^63
+    if x.is_nan() || x < i64::MIN as f64 || x >= i64::MAX as f64 {
+        return Err(format!("could not convert {:?} to INTEGER", x));
+    }
+    Ok(x as i64)
+}
+
+fn abs(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 1)?;
+
+    match args[0] {
+        Object::Integer(x) => x
+            .checked_abs()
+            .map(Object::Integer)
+            .ok_or("integer overflow".to_string()),
+        arg => Ok(Object::Float(number("abs", arg)?.abs())),
+    }
+}
+
+/// Integer power for a non-negative integer exponent, float power otherwise
+fn pow(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 2)?;
+
+    match (args[0], args[1]) {
+        (Object::Integer(base), Object::Integer(exp)) if *exp >= 0 => u32::try_from(*exp)
+            .ok()
+            .and_then(|exp| base.checked_pow(exp))
+            .map(Object::Integer)
+            .ok_or("integer overflow".to_string()),
+        (base, exp) => Ok(Object::Float(
+            number("pow", base)?.powf(number("pow", exp)?),
+        )),
+    }
+}
+
+fn sqrt(args: Vec<&Object>) -> Result<Object, String> {
+    float_fn("sqrt", args, f64::sqrt)
+}
+
+fn floor(args: Vec<&Object>) -> Result<Object, String> {
+    round_fn("floor", args, f64::floor)
+}
+
+fn ceil(args: Vec<&Object>) -> Result<Object, String> {
+    round_fn("ceil", args, f64::ceil)
+}
+
+fn round(args: Vec<&Object>) -> Result<Object, String> {
+    round_fn("round", args, f64::round)
+}
+
+fn clamp(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 3)?;
+
+    let (x, lo, hi) = (args[0], args[1], args[2]);
+    if lo.compare(hi)?.is_gt() {
+        return Err(format!("clamp bounds are reversed: {} > {}", lo, hi));
+    }
+    if x.compare(lo)?.is_lt() {
+        Ok(lo.clone())
+    } else if x.compare(hi)?.is_gt() {
+        Ok(hi.clone())
+    } else {
+        Ok(x.clone())
+    }
+}
+
+fn gcd(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 2)?;
+
+    match (args[0], args[1]) {
+        (Object::Integer(a), Object::Integer(b)) => {
+            let (mut a, mut b) = (a.unsigned_abs(), b.unsigned_abs());
+            while b != 0 {
+                (a, b) = (b, a % b);
+            }
+            i64::try_from(a)
+                .map(Object::Integer)
+                .map_err(|_| "integer overflow".to_string())
+        }
+        _ => Err(unsupported("gcd", &args)),
+    }
+}
+
+fn sin(args: Vec<&Object>) -> Result<Object, String> {
+    float_fn("sin", args, f64::sin)
+}
+
+fn cos(args: Vec<&Object>) -> Result<Object, String> {
+    float_fn("cos", args, f64::cos)
+}
+
+fn tan(args: Vec<&Object>) -> Result<Object, String> {
+    float_fn("tan", args, f64::tan)
+}
+
+fn asin(args: Vec<&Object>) -> Result<Object, String> {
+    float_fn("asin", args, f64::asin)
+}
+
+fn acos(args: Vec<&Object>) -> Result<Object, String> {
+    float_fn("acos", args, f64::acos)
+}
+
+fn atan(args: Vec<&Object>) -> Result<Object, String> {
+    float_fn("atan", args, f64::atan)
+}
+
+fn atan2(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 2)?;
+    Ok(Object::Float(
+        number("atan2", args[0])?.atan2(number("atan2", args[1])?),
+    ))
+}
+
+fn exp(args: Vec<&Object>) -> Result<Object, String> {
+    float_fn("exp", args, f64::exp)
+}
+
+/// Natural logarithm, or in the given base
+fn log(args: Vec<&Object>) -> Result<Object, String> {
+    match args[..] {
+        [x] => Ok(Object::Float(number("log", x)?.ln())),
+        [x, base] => Ok(Object::Float(number("log", x)?.log(number("log", base)?))),
+        _ => Err(format!(
+            "wrong number of arguments. expected 1 or 2, got {}",
+            args.len()
+        )),
+    }
+}
+
+fn random(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 0)?;
+    Ok(Object::Float(random::next_float()))
+}
+
+/// Integer between both bounds, inclusive
+fn random_int(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 2)?;
+
+    match (args[0], args[1]) {
+        (Object::Integer(lo), Object::Integer(hi)) if lo > hi => {
+            Err(format!("random_int bounds are reversed: {} > {}", lo, hi))
+        }
+        (Object::Integer(lo), Object::Integer(hi)) => {
+            let span = hi.abs_diff(*lo);
+            let offset = match span.checked_add(1) {
+                Some(n) => random::below(n),
+                None => random::next_u64(),
+            };
+            Ok(Object::Integer(lo.wrapping_add(offset as i64)))
+        }
+        _ => Err(unsupported("random_int", &args)),
+    }
+}
+
+fn shuffle(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 1)?;
+
+    let mut elements = array_items("shuffle", args[0])?;
+    for i in (1..elements.len()).rev() {
+        elements.swap(i, random::below(i as u64 + 1) as usize);
+    }
+    Ok(Object::Array(ArrayObj { elements }))
+}
+
+/// Random element, or `null` when there is none
+fn choice(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 1)?;
+
+    let elements = array_items("choice", args[0])?;
+    if elements.is_empty() {
+        return Ok(Object::Null);
+    }
+    let i = random::below(elements.len() as u64) as usize;
+    Ok(Rc::unwrap_or_clone(elements[i].clone()))
+}
+
+fn json_parse(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 1)?;
+
+    match args[0] {
+        Object::String(s) => json::parse(s),
+        _ => Err(format!(
+            "argument to `json_parse` not supported, got {}",
+            args[0].kind()
+        )),
+    }
+}
+
+/// Largest indent accepted by `json_stringify`
+const MAX_INDENT: i64 = 10;
+
+/// Compact JSON, or indented by the given number of spaces
+fn json_stringify(args: Vec<&Object>) -> Result<Object, String> {
+    let indent = match args[..] {
+        [_] => None,
+        [_, Object::Integer(n)] if (0..=MAX_INDENT).contains(n) => Some(*n as usize),
+        [_, Object::Integer(n)] => {
+            return Err(format!(
+                "indent must be between 0 and {}, got {}",
+                MAX_INDENT, n
+            ))
+        }
+        [_, _] => return Err(unsupported("json_stringify", &args)),
+        _ => {
+            return Err(format!(
+                "wrong number of arguments. expected 1 or 2, got {}",
+                args.len()
+            ))
+        }
+    };
+    json::stringify(args[0], indent).map(Object::String)
+}
+
+/// Path argument of a file builtin, checked against the policy
+fn policy_path(name: &str, args: &[&Object], write: bool) -> Result<PathBuf, String> {
+    match args[0] {
+        Object::String(path) => policy::check_path(path, write),
+        _ => Err(unsupported(name, args)),
+    }
+}
+
+fn read_file(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 1)?;
+
+    let path = policy_path("read_file", &args, false)?;
+    fs::read_to_string(path)
+        .map(Object::String)
+        .map_err(|e| format!("cannot read {}: {}", args[0], e))
+}
+
+/// Writes a string to a file, replacing its contents unless appending
+fn write_file(args: Vec<&Object>, append: bool) -> Result<Object, String> {
+    check_args(&args, 2)?;
+
+    let name = if append { "append_file" } else { "write_file" };
+    let Object::String(contents) = args[1] else {
+        return Err(unsupported(name, &args));
+    };
+    let path = policy_path(name, &args, true)?;
+    fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)
+        .and_then(|mut f| f.write_all(contents.as_bytes()))
+        .map(|_| Object::Null)
+        .map_err(|e| format!("cannot write {}: {}", args[0], e))
+}
+
+/// Sorted names of the entries in a directory
+fn list_dir(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 1)?;
+
+    let path = policy_path("list_dir", &args, false)?;
+    let mut names = fs::read_dir(path)
+        .and_then(|entries| {
+            entries
+                .map(|e| e.map(|e| e.file_name().to_string_lossy().into_owned()))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .map_err(|e| format!("cannot list {}: {}", args[0], e))?;
+    names.sort();
+    let elements = names
+        .into_iter()
+        .map(|n| Rc::new(Object::String(n)))
+        .collect();
+    Ok(Object::Array(ArrayObj { elements }))
+}
+
+fn exists(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 1)?;
+
+    let path = policy_path("exists", &args, false)?;
+    Ok(Object::Bool(path.exists()))
+}
+
+fn remove_file(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 1)?;
+
+    let path = policy_path("remove_file", &args, true)?;
+    fs::remove_file(path)
+        .map(|_| Object::Null)
+        .map_err(|e| format!("cannot remove {}: {}", args[0], e))
+}
+
+fn script_args(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 0)?;
+
+    let elements = script::args()
+        .into_iter()
+        .map(|a| Rc::new(Object::String(a)))
+        .collect();
+    Ok(Object::Array(ArrayObj { elements }))
+}
+
+/// Next line of stdin without its line ending, or `null` at the end of input
+fn read_line(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 0)?;
+
+    let mut line = String::new();
+    match io::stdin().lock().read_line(&mut line) {
+        Ok(0) => Ok(Object::Null),
+        Ok(_) => {
+            let len = line.trim_end_matches(['\n', '\r']).len();
+            line.truncate(len);
+            Ok(Object::String(line))
+        }
+        Err(e) => Err(format!("cannot read from stdin: {}", e)),
+    }
+}
+
+fn read_all(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 0)?;
+
+    let mut input = String::new();
+    io::stdin()
+        .read_to_string(&mut input)
+        .map(|_| Object::String(input))
+        .map_err(|e| format!("cannot read from stdin: {}", e))
+}
+
+/// Ends the process with the given status, 0 by default
+fn exit(args: Vec<&Object>) -> Result<Object, String> {
+    let code = match args[..] {
+        [] => 0,
+        [Object::Integer(code)] if (0..=255).contains(code) => *code as i32,
+        [Object::Integer(code)] => {
+            return Err(format!("exit code must be between 0 and 255, got {}", code))
+        }
+        [_] => return Err(unsupported("exit", &args)),
+        _ => {
+            return Err(format!(
+                "wrong number of arguments. expected 0 or 1, got {}",
+                args.len()
+            ))
+        }
+    };
+    let _ = io::stdout().flush();
+    std::process::exit(code)
+}
+
+/// Value of an environment variable, or `null` when it is not set
+fn env_get(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 1)?;
+    policy::check(Capability::Env)?;
+
+    match args[0] {
+        Object::String(name) => Ok(std::env::var(name).map_or(Object::Null, Object::String)),
+        _ => Err(format!(
+            "argument to `env_get` not supported, got {}",
+            args[0].kind()
+        )),
+    }
+}
+
+fn env_set(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 2)?;
+    policy::check(Capability::Env)?;
+
+    let (Object::String(name), Object::String(value)) = (args[0], args[1]) else {
+        return Err(unsupported("env_set", &args));
+    };
+    if name.is_empty() || name.contains(['=', '\0']) || value.contains('\0') {
+        return Err(format!("invalid environment variable {}={}", name, value));
+    }
+    std::env::set_var(name, value);
+    Ok(Object::Null)
+}
+
+/// Milliseconds since the Unix epoch
+fn now(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 0)?;
+    policy::check(Capability::Time)?;
+
+    Ok(Object::Integer(time::now()))
+}
+
+/// Milliseconds from an arbitrary start, for measuring durations
+fn monotonic(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 0)?;
+    policy::check(Capability::Time)?;
+
+    Ok(Object::Float(time::monotonic()))
+}
+
+fn sleep(args: Vec<&Object>) -> Result<Object, String> {
+    check_args(&args, 1)?;
+    policy::check(Capability::Time)?;
+
+    match args[0] {
+        Object::Integer(ms) if *ms >= 0 => {
+            std::thread::sleep(std::time::Duration::from_millis(*ms as u64));
+            Ok(Object::Null)
+        }
+        Object::Integer(ms) => Err(format!("cannot sleep for {} ms", ms)),
+        _ => Err(format!(
+            "argument to `sleep` not supported, got {}",
+            args[0].kind()
+        )),
+    }
+}
+
+/// UTC time of a `now()` timestamp, in ISO 8601 unless given a format
+fn format_time(args: Vec<&Object>) -> Result<Object, String> {
+    let (ms, fmt) = match args[..] {
+        [Object::Integer(ms)] => (*ms, "%Y-%m-%dT%H:%M:%SZ"),
+        [Object::Integer(ms), Object::String(fmt)] => (*ms, fmt.as_str()),
+        [_] | [_, _] => return Err(unsupported("format_time", &args)),
+        _ => {
+            return Err(format!(
+                "wrong number of arguments. expected 1 or 2, got {}",
+                args.len()
+            ))
+        }
+    };
+    time::format(ms, fmt).map(Object::String)
+}
+
+/// Runs a command to completion, returning its `status`, `stdout` and `stderr`
+fn run(args: Vec<&Object>) -> Result<Object, String> {
+    let (cmd, cmd_args) = match args[..] {
+        [Object::String(cmd)] => (cmd, vec![]),
+        [Object::String(cmd), list] => {
+            let items = array_items("run", list)?;
+            let cmd_args = items
+                .iter()
+                .map(|a| match &**a {
+                    Object::String(s) => Ok(s.clone()),
+                    a => Err(format!(
+                        "command arguments must be STRING, got {}",
+                        a.kind()
+                    )),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            (cmd, cmd_args)
+        }
+        [_] | [_, _] => return Err(unsupported("run", &args)),
+        _ => {
+            return Err(format!(
+                "wrong number of arguments. expected 1 or 2, got {}",
+                args.len()
+            ))
+        }
+    };
+    policy::check(Capability::Run)?;
+
+    let output = std::process::Command::new(cmd)
+        .args(cmd_args)
+        .output()
+        .map_err(|e| format!("cannot run {}: {}", cmd, e))?;
+    let status = output
+        .status
+        .code()
+        .map_or(Object::Null, |c| Object::Integer(c as i64));
+    let text = |bytes: Vec<u8>| Object::String(String::from_utf8_lossy(&bytes).into_owned());
+
+    let mut hash = HashObj::default();
+    for (key, value) in [
+        ("status", status),
+        ("stdout", text(output.stdout)),
+        ("stderr", text(output.stderr)),
+    ] {
+        hash.insert(Rc::new(Object::String(key.into())), Rc::new(value));
+    }
+    Ok(Object::Hash(hash))
+}
+
+/// Optional message argument of the assertion builtins, appended to the failure
+fn assert_message(name: &str, args: &[&Object], required: usize) -> Result<String, String> {
+    match args.get(required) {
+        _ if args.len() < required || args.len() > required + 1 => Err(format!(
+            "wrong number of arguments. expected {} or {}, got {}",
+            required,
+            required + 1,
+            args.len()
+        )),
+        None => Ok(String::new()),
+        Some(Object::String(msg)) => Ok(format!(": {}", msg)),
+        Some(_) => Err(unsupported(name, args)),
+    }
+}
+
+fn assert(args: Vec<&Object>) -> Result<Object, String> {
+    let msg = assert_message("assert", &args, 1)?;
+    if args[0].is_truthy() {
+        Ok(Object::Null)
+    } else {
+        Err(format!("assertion failed{}", msg))
+    }
+}
+
+/// Fails with a line diff of the values when they are not equal
+fn assert_eq(args: Vec<&Object>) -> Result<Object, String> {
+    let msg = assert_message("assert_eq", &args, 2)?;
+    if args[0] == args[1] {
+        Ok(Object::Null)
+    } else {
+        Err(format!(
+            "assertion failed{}\n{}",
+            msg,
+            diff::values(args[0], args[1])
+        ))
+    }
 }
@@ -20,20 +20,52 @@ pub enum OpCode {
 
     Jump,
     JumpNotTrue,
+    JumpHasArg,
 
     SetGlobal,
     GetGlobal,
     SetLocal,
     GetLocal,
     GetBuiltin,
+    GetFree,
+    SetFree,
+    CurrentClosure,
 
     Array,
     Hash,
     Index,
+    Slice,
+    Range,
+    Template,
 
+    MatchArray,
+    MatchKey,
+    ArrayRest,
+    DestructureFail,
+
+    Closure,
     Call,
+    CallSpread,
+    Method,
     ReturnValue,
     Return,
+
+    /// Sets up a handler jumping to its operand with the caught error value
+    Try,
+    EndTry,
+    Throw,
+
+    /// Replaces the value with what `for-in` visits, followed by the next index
+    Iter,
+    /// Pushes the next item, or pops the iteration state and jumps to its operand
+    IterNext,
+
+    /// Like `Try`, but the handler runs a `finally` block and gets the error set aside
+    TryFinally,
+    /// Raises again the error set aside for a `finally` block
+    Rethrow,
+    /// Pushes the quote constant of its first operand, with as many unquoted values spliced
+    Quote,
 }
 
 impl OpCode {
@@ -56,20 +88,46 @@ impl OpCode {
 
             OpCode::Jump => Definition::new("OpJump", &[2]),
             OpCode::JumpNotTrue => Definition::new("OpJumpNotTrue", &[2]),
+            OpCode::JumpHasArg => Definition::new("OpJumpHasArg", &[1, 2]),
 
             OpCode::SetGlobal => Definition::new("OpSetGlobal", &[2]),
             OpCode::GetGlobal => Definition::new("OpGetGlobal", &[2]),
             OpCode::SetLocal => Definition::new("OpSetLocal", &[1]),
             OpCode::GetLocal => Definition::new("OpGetLocal", &[1]),
-            OpCode::GetBuiltin => Definition::new("OpGetLocal", &[1]),
+            OpCode::GetBuiltin => Definition::new("OpGetBuiltin", &[1]),
+            OpCode::GetFree => Definition::new("OpGetFree", &[1]),
+            OpCode::SetFree => Definition::new("OpSetFree", &[1]),
+            OpCode::CurrentClosure => Definition::new("OpCurrentClosure", &[]),
 
             OpCode::Array => Definition::new("OpArray", &[2]),
             OpCode::Hash => Definition::new("OpHash", &[2]),
             OpCode::Index => Definition::new("OpIndex", &[]),
+            OpCode::Slice => Definition::new("OpSlice", &[]),
+            OpCode::Range => Definition::new("OpRange", &[1]),
+            OpCode::Template => Definition::new("OpTemplate", &[2]),
+
+            OpCode::MatchArray => Definition::new("OpMatchArray", &[2, 1]),
+            OpCode::MatchKey => Definition::new("OpMatchKey", &[]),
+            OpCode::ArrayRest => Definition::new("OpArrayRest", &[2]),
+            OpCode::DestructureFail => Definition::new("OpDestructureFail", &[2]),
 
+            OpCode::Closure => Definition::new("OpClosure", &[2, 1]),
             OpCode::Call => Definition::new("OpCall", &[1]),
+            OpCode::CallSpread => Definition::new("OpCallSpread", &[1]),
+            OpCode::Method => Definition::new("OpMethod", &[2, 1, 1]),
             OpCode::ReturnValue => Definition::new("OpReturnValue", &[]),
             OpCode::Return => Definition::new("OpReturn", &[]),
+
+            OpCode::Try => Definition::new("OpTry", &[2]),
+            OpCode::EndTry => Definition::new("OpEndTry", &[]),
+            OpCode::Throw => Definition::new("OpThrow", &[]),
+
+            OpCode::Iter => Definition::new("OpIter", &[]),
+            OpCode::IterNext => Definition::new("OpIterNext", &[2]),
+
+            OpCode::TryFinally => Definition::new("OpTryFinally", &[2]),
+            OpCode::Rethrow => Definition::new("OpRethrow", &[]),
+            OpCode::Quote => Definition::new("OpQuote", &[2, 2]),
         }
     }
 }
@@ -82,10 +140,10 @@ impl Display for OpCode {
 
 impl From<u8> for OpCode {
     fn from(value: u8) -> Self {
-        if value > std::mem::variant_count::<Self>() as u8 {
+        if value >= std::mem::variant_count::<Self>() as u8 {
             panic!("Invalid opcode: {}", value);
         } else {
-            unsafe { std::mem::transmute(value) }
+            unsafe { std::mem::transmute::<u8, Self>(value) }
         }
     }
 }
@@ -1,8 +1,15 @@
 #![allow(dead_code)]
 
-use std::rc::Rc;
+use std::{collections::HashMap, rc::Rc};
 
-use crate::{ast::*, eval::Object, lexer::TokenType};
+use crate::{
+    ast::*,
+    builtin::Builtin,
+    eval::{is_call, splice, Object},
+    lexer::TokenType,
+    module::Module,
+    prelude,
+};
 
 pub use code::Bytes;
 pub use instructions::{Instruction, OpCode};
@@ -10,7 +17,7 @@ pub use symbol_table::*;
 
 mod code;
 mod instructions;
-mod symbol_table;
+pub mod symbol_table;
 
 #[derive(Default)]
 struct Scope {
@@ -18,40 +25,54 @@ struct Scope {
 
     last: Option<Emmited>,
     prev: Option<Emmited>,
+    /// `finally` blocks of the handlers set up at this point, innermost last
+    handlers: Vec<Option<Vec<Statement>>>,
+    /// Source line of the instructions that can fail, by position
+    lines: Vec<(usize, usize)>,
 }
 
 pub struct Compiler {
     constants: Vec<Object>,
     symbol_table: SymbolTableRef,
     scopes: Vec<Scope>,
+    /// Number of hidden symbols defined so far, used to keep their names unique
+    temps: usize,
 }
 
 impl Default for Compiler {
     fn default() -> Self {
         let symbol_table = SymbolTable::empty();
-        let builtins = ["len", "first", "last", "rest", "push", "puts"];
-        for b in builtins {
-            symbol_table.borrow_mut().define_builtin(b);
+        for b in Builtin::ALL {
+            symbol_table.borrow_mut().define_builtin(b.name());
         }
 
         Self {
             constants: vec![Object::Null],
             symbol_table,
             scopes: vec![Scope::default()],
+            temps: 0,
         }
     }
 }
 
+/// Step taken from a matched value to reach one of its parts
+enum PatternAccess {
+    Index(Expression),
+    Rest(usize),
+}
+
 #[derive(Clone, Copy)]
 struct Emmited {
     opcode: OpCode,
     pos: usize,
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct Bytecode {
     pub instructions: Bytes,
     pub constants: Vec<Object>,
+    /// Source line of the instructions that can fail, by position
+    pub lines: Vec<(usize, usize)>,
 }
 
 impl Compiler {
@@ -71,9 +92,41 @@ impl Compiler {
         self.compile_block(program.statements)
     }
 
+    /// Compiles the prelude into globals that later definitions shadow instead of overwriting
+    pub fn compile_prelude(&mut self) -> CompileResult {
+        self.compile(prelude::program())?;
+        self.symbol_table.borrow_mut().seal_prelude();
+        Ok(())
+    }
+
+    /// Compiles each module in its own namespace, storing its exports in a hidden global
+    pub fn compile_modules(&mut self, modules: Vec<Module>) -> CompileResult {
+        for m in modules {
+            let binding = Module::binding(&m.path);
+            if self.symbol_table.borrow_mut().resolve(&binding).is_some() {
+                continue;
+            }
+
+            let namespace = self.symbol_table.borrow().namespace();
+            let main = std::mem::replace(&mut self.symbol_table, namespace);
+            let path = m.path.clone();
+            let res = self.compile_module(m);
+            let namespace = std::mem::replace(&mut self.symbol_table, main);
+            res.map_err(|e| format!("{}: {}", path, e))?;
+
+            self.symbol_table
+                .borrow_mut()
+                .reserve(namespace.borrow().symbols());
+            let sym = self.symbol_table.borrow_mut().define(&binding);
+            self.store_symbol(sym);
+        }
+        Ok(())
+    }
+
     pub fn bytecode(self) -> Bytecode {
         Bytecode {
             instructions: self.current_scope().instructions.clone(),
+            lines: self.current_scope().lines.clone(),
             constants: self.constants,
         }
     }
@@ -83,24 +136,40 @@ impl Compiler {
     fn compile_stmt(&mut self, stmt: Statement) -> CompileResult {
         match stmt {
             Statement::Let(l) => {
-                self.compile_expr(l.expr)?;
+                self.compile_let_value(l.expr, &l.ident)?;
                 let sym = self.symbol_table.borrow_mut().define(&l.ident);
-                match sym.scope {
-                    symbol_table::Scope::Global => {
-                        self.emit(Instruction::new(OpCode::SetGlobal, &[sym.index as u32]))
-                    }
-                    symbol_table::Scope::Local => {
-                        self.emit(Instruction::new(OpCode::SetLocal, &[sym.index as u32]))
-                    }
-                    _ => unreachable!(),
-                };
+                self.store_symbol(sym);
                 Ok(())
             }
+            Statement::Destructure(d) => self.compile_destructure(d),
+            Statement::Func(f) => self.compile_func_decls(vec![f]).map(|_| ()),
             Statement::Return(r) => {
                 self.compile_expr(r.expr)?;
+                self.compile_leave_handlers()?;
                 self.emit(Instruction::new(OpCode::ReturnValue, &[]));
                 Ok(())
             }
+            Statement::Import(i) => {
+                let sym = self
+                    .symbol_table
+                    .borrow_mut()
+                    .resolve(&Module::binding(&i.path))
+                    .ok_or(format!(
+                        "module {} is not loaded, imports are only allowed at the top level",
+                        i.path
+                    ))?;
+                self.load_symbol(sym);
+                let sym = self.symbol_table.borrow_mut().define(&i.name);
+                self.store_symbol(sym);
+                Ok(())
+            }
+            Statement::Export(_) => Ok(()),
+            Statement::Throw(t) => {
+                self.compile_expr(t.expr)?;
+                self.mark_line(t.line);
+                self.emit(Instruction::new(OpCode::Throw, &[]));
+                Ok(())
+            }
             Statement::Expression(e) => {
                 self.compile_expr(e)?;
                 self.emit(Instruction::new(OpCode::Pop, &[]));
@@ -112,23 +181,15 @@ impl Compiler {
     fn compile_expr(&mut self, expr: Expression) -> CompileResult {
         match expr {
             Expression::Ident(i) => {
-                let sym = self
-                    .symbol_table
-                    .borrow()
-                    .resolve(&i)
-                    .ok_or(format!("undefined symbol: {}", i))?;
-
-                match sym.scope {
-                    symbol_table::Scope::Global => {
-                        self.emit(Instruction::new(OpCode::GetGlobal, &[sym.index as u32]));
-                    }
-                    symbol_table::Scope::Local => {
-                        self.emit(Instruction::new(OpCode::GetLocal, &[sym.index as u32]));
+                let sym = self.symbol_table.borrow_mut().resolve(&i);
+                match (sym, Builtin::constant(&i)) {
+                    (Some(sym), _) => self.load_symbol(sym),
+                    (None, Some(c)) => {
+                        let idx = self.add_constant(c) as u32;
+                        self.emit(Instruction::new(OpCode::Constant, &[idx]));
                     }
-                    symbol_table::Scope::Builtin => {
-                        self.emit(Instruction::new(OpCode::GetBuiltin, &[sym.index as u32]));
-                    }
-                };
+                    (None, None) => return Err(format!("undefined symbol: {}", i)),
+                }
             }
             Expression::Number(x) => {
                 let obj = Object::Integer(x);
@@ -181,16 +242,45 @@ impl Compiler {
                 )
             }
             Expression::Func(f) => {
-                let idx = self.compile_func(f)?;
-                self.emit(Instruction::new(OpCode::Constant, &[idx]));
+                self.compile_func(f, None)?;
+            }
+            Expression::Macro(_) => {
+                return Err("macros can only be defined with a top-level let".into())
+            }
+            Expression::Call(c) if is_call(&c, "quote") => {
+                let expr = c.arguments.into_iter().next().unwrap();
+                // Unquoted expressions are evaluated in order, for the VM to splice
+                let mut unquoted = vec![];
+                splice(expr.clone(), |arg| {
+                    unquoted.push(arg.clone());
+                    Ok(Rc::new(Object::Quote(arg.clone())))
+                })?;
+                let count = unquoted.len() as u32;
+                for arg in unquoted {
+                    self.compile_expr(arg)?;
+                }
+                let quote = self.add_constant(Object::Quote(expr)) as u32;
+                self.mark_line(c.line);
+                self.emit(Instruction::new(OpCode::Quote, &[quote, count]));
             }
             Expression::Call(c) => {
                 self.compile_expr(*c.func)?;
-                let args = c.arguments.len();
-                for arg in c.arguments {
-                    self.compile_expr(arg)?;
+                let args = c.arguments.len() as u32;
+                let spread = self.compile_args(c.arguments)?;
+                self.mark_line(c.line);
+                if spread {
+                    self.emit(Instruction::new(OpCode::CallSpread, &[args]));
+                } else {
+                    self.emit(Instruction::new(OpCode::Call, &[args]));
                 }
-                self.emit(Instruction::new(OpCode::Call, &[args as u32]));
+            }
+            Expression::Method(m) => {
+                self.compile_expr(*m.object)?;
+                let args = m.arguments.len() as u32;
+                let spread = self.compile_args(m.arguments)? as u32;
+                let name = self.add_constant(Object::String(m.method)) as u32;
+                self.mark_line(m.line);
+                self.emit(Instruction::new(OpCode::Method, &[name, args, spread]));
             }
             Expression::Array(a) => {
                 let len = a.elements.len();
@@ -202,8 +292,39 @@ impl Compiler {
             Expression::Index(i) => {
                 self.compile_expr(*i.left)?;
                 self.compile_expr(*i.index)?;
+                self.mark_line(i.line);
                 self.emit(Instruction::new(OpCode::Index, &[]));
             }
+            Expression::Slice(s) => {
+                self.compile_expr(*s.left)?;
+                for bound in [s.start, s.end] {
+                    match bound {
+                        Some(e) => self.compile_expr(*e)?,
+                        None => {
+                            self.emit(Instruction::null());
+                        }
+                    }
+                }
+                self.emit(Instruction::new(OpCode::Slice, &[]));
+            }
+            Expression::Template(t) => {
+                let len = t.parts.len();
+                for part in t.parts {
+                    match part {
+                        TemplateExprPart::Str(s) => {
+                            let idx = self.add_constant(Object::String(s)) as u32;
+                            self.emit(Instruction::new(OpCode::Constant, &[idx]));
+                        }
+                        TemplateExprPart::Expr(e) => self.compile_expr(e)?,
+                    }
+                }
+                self.emit(Instruction::new(OpCode::Template, &[len as u32]));
+            }
+            Expression::Range(r) => {
+                self.compile_expr(*r.start)?;
+                self.compile_expr(*r.end)?;
+                self.emit(Instruction::new(OpCode::Range, &[r.inclusive as u32]));
+            }
             Expression::Hash(h) => {
                 let len = h.pairs.len();
                 for (k, v) in h.pairs {
@@ -212,6 +333,12 @@ impl Compiler {
                 }
                 self.emit(Instruction::new(OpCode::Hash, &[len as u32]));
             }
+            Expression::Match(m) => self.compile_match(m)?,
+            Expression::Try(t) => self.compile_try(t)?,
+            Expression::For(f) => self.compile_for(f)?,
+            Expression::Spread(_) => {
+                return Err("spread is only allowed in call arguments".into());
+            }
         }
 
         Ok(())
@@ -219,18 +346,165 @@ impl Compiler {
 }
 
 impl Compiler {
+    /// Compiles the module and leaves a hash of its exports on the stack
+    fn compile_module(&mut self, m: Module) -> CompileResult {
+        self.compile_block(m.program.statements)?;
+
+        let len = m.exports.len();
+        for name in m.exports {
+            let sym = self
+                .symbol_table
+                .borrow_mut()
+                .resolve(&name)
+                .ok_or(format!("exported name {} is not defined", name))?;
+            let idx = self.add_constant(Object::String(name)) as u32;
+            self.emit(Instruction::new(OpCode::Constant, &[idx]));
+            self.load_symbol(sym);
+        }
+        self.emit(Instruction::new(OpCode::Hash, &[len as u32]));
+        Ok(())
+    }
+
     fn compile_block(&mut self, block: Vec<Statement>) -> CompileResult {
+        let mut decls = vec![];
+        let mut stmts = vec![];
         for stmt in block {
-            self.compile_stmt(stmt)?;
+            match stmt {
+                Statement::Func(f) => decls.push(f),
+                s => stmts.push(s),
+            }
+        }
+        if decls.is_empty() {
+            return stmts.into_iter().try_for_each(|s| self.compile_stmt(s));
+        }
+
+        // Lets hoisted functions refer to the names bound later in the block.
+        // Globals are read when called, while locals are captured when the
+        // closure is created, so they get patched in once they are set
+        let mut hidden = HashMap::new();
+        for name in stmts.iter().flat_map(bound_names) {
+            let mut table = self.symbol_table.borrow_mut();
+            let prev = table.resolve_local(name);
+            let sym = table.define(name);
+            if prev != Some(sym) {
+                hidden.entry(name.clone()).or_insert(prev);
+            }
+        }
+
+        let captures = self.compile_func_decls(decls)?;
+        for stmt in stmts {
+            let names: Vec<_> = bound_names(&stmt).into_iter().cloned().collect();
+            match stmt {
+                // The value still sees what the name referred to before the block
+                Statement::Let(l) if hidden.contains_key(&l.ident) => {
+                    let prev = hidden.remove(&l.ident).unwrap();
+                    let sym = self.symbol_table.borrow_mut().resolve(&l.ident).unwrap();
+                    self.symbol_table.borrow_mut().restore(&l.ident, prev);
+                    self.compile_let_value(l.expr, &l.ident)?;
+                    self.symbol_table.borrow_mut().restore(&l.ident, Some(sym));
+                    self.store_symbol(sym);
+                }
+                s => self.compile_stmt(s)?,
+            }
+
+            for name in names {
+                let sym = self.symbol_table.borrow_mut().resolve(&name).unwrap();
+                for (closure, free_idx, _) in captures.iter().filter(|c| c.2 == sym) {
+                    self.load_symbol(*closure);
+                    self.load_symbol(sym);
+                    self.emit(Instruction::new(OpCode::SetFree, &[*free_idx as u32]));
+                }
+            }
         }
         Ok(())
     }
 
-    fn compile_func(&mut self, FuncExpr { params, body }: FuncExpr) -> Result<u32, String> {
+    fn compile_let_value(&mut self, expr: Expression, name: &str) -> CompileResult {
+        match expr {
+            // Lets the function refer to itself through its name
+            Expression::Func(f) => self.compile_func(f, Some(name)).map(|_| ()),
+            e => self.compile_expr(e),
+        }
+    }
+
+    /// Hoists function declarations: all names are defined before any body is
+    /// compiled, then closures capturing a sibling declaration get it patched
+    /// in once every declaration has been created. Returns the other locals
+    /// captured, as the closure, the index in its free variables and the local
+    fn compile_func_decls(
+        &mut self,
+        decls: Vec<FuncStmt>,
+    ) -> Result<Vec<(Symbol, usize, Symbol)>, String> {
+        let syms: Vec<_> = decls
+            .iter()
+            .map(|d| self.symbol_table.borrow_mut().define(&d.name))
+            .collect();
+
+        let mut patches = vec![];
+        let mut captures = vec![];
+        for (decl, sym) in decls.into_iter().zip(&syms) {
+            let free = self.compile_func(decl.func, Some(&decl.name))?;
+            self.store_symbol(*sym);
+
+            for (free_idx, captured) in free.iter().enumerate() {
+                if syms.contains(captured) {
+                    patches.push((*sym, free_idx, *captured));
+                } else if captured.scope == symbol_table::Scope::Local {
+                    captures.push((*sym, free_idx, *captured));
+                }
+            }
+        }
+
+        for (sym, free_idx, captured) in patches {
+            self.load_symbol(sym);
+            self.load_symbol(captured);
+            self.emit(Instruction::new(OpCode::SetFree, &[free_idx as u32]));
+        }
+        Ok(captures)
+    }
+
+    /// Emits the instructions that push the function and returns the symbols
+    /// it captures
+    fn compile_func(
+        &mut self,
+        FuncExpr {
+            params,
+            defaults,
+            rest,
+            body,
+        }: FuncExpr,
+        name: Option<&str>,
+    ) -> Result<Vec<Symbol>, String> {
         self.enter_scope();
 
-        for p in &params {
-            self.symbol_table.borrow_mut().define(p);
+        if let Some(name) = name {
+            self.symbol_table.borrow_mut().define_function_name(name);
+        }
+
+        let syms: Vec<_> = params
+            .iter()
+            .map(|p| self.symbol_table.borrow_mut().define(p))
+            .collect();
+        if let Some(rest) = &rest {
+            self.symbol_table.borrow_mut().define(rest);
+        }
+
+        // Defaults are only evaluated when the argument is missing
+        let required = params.len() - defaults.len();
+        for (sym, default) in syms[required..].iter().zip(defaults) {
+            let jmp = self.emit(Instruction::new(
+                OpCode::JumpHasArg,
+                &[sym.index as u32, 9999],
+            ));
+            self.compile_expr(default)?;
+            self.store_symbol(*sym);
+            self.patch(
+                jmp,
+                Instruction::new(
+                    OpCode::JumpHasArg,
+                    &[sym.index as u32, self.instructions().len() as u32],
+                ),
+            );
         }
 
         self.compile_block(body)?;
@@ -241,16 +515,364 @@ impl Compiler {
         if !self.last_is(OpCode::ReturnValue) {
             self.emit(Instruction::new(OpCode::Return, &[]));
         }
+        let free_symbols = self.symbol_table.borrow().free_symbols.clone();
         let locals = self.symbol_table.borrow().symbols();
-        let body = self.leave_scope().instructions;
+        let body = self.leave_scope();
 
-        Ok(self.add_constant(Object::CompiledFunc(Rc::new(
+        let idx = self.add_constant(Object::CompiledFunc(Rc::new(
             crate::eval::CompiledFuncObj {
-                instructions: body,
+                instructions: body.instructions,
+                lines: body.lines,
                 locals,
                 params: params.len(),
+                required,
+                rest: rest.is_some(),
             },
-        ))) as u32)
+        ))) as u32;
+
+        if free_symbols.is_empty() {
+            self.emit(Instruction::new(OpCode::Constant, &[idx]));
+        } else {
+            for sym in &free_symbols {
+                self.load_symbol(*sym);
+            }
+            self.emit(Instruction::new(
+                OpCode::Closure,
+                &[idx, free_symbols.len() as u32],
+            ));
+        }
+        Ok(free_symbols)
+    }
+
+    /// Returns whether the arguments contain a spread, in which case
+    /// every argument is passed as an array and flattened by the vm
+    fn compile_args(&mut self, args: Vec<Expression>) -> Result<bool, String> {
+        let spread = args.iter().any(|a| matches!(a, Expression::Spread(_)));
+        for arg in args {
+            match arg {
+                Expression::Spread(e) => self.compile_expr(*e)?,
+                e => {
+                    self.compile_expr(e)?;
+                    if spread {
+                        self.emit(Instruction::new(OpCode::Array, &[1]));
+                    }
+                }
+            }
+        }
+        Ok(spread)
+    }
+
+    fn compile_match(&mut self, MatchExpr { value, arms }: MatchExpr) -> CompileResult {
+        self.compile_expr(*value)?;
+        let subject = self.define_temp();
+        self.store_symbol(subject);
+
+        let mut jmps_end = vec![];
+        for arm in arms {
+            // Bindings only live in their arm, in slots that leave outer names untouched
+            let shadowed: Vec<_> = arm
+                .pattern
+                .idents()
+                .into_iter()
+                .map(|i| (i.clone(), self.symbol_table.borrow_mut().shadow(i)))
+                .collect();
+
+            let mut jmps_fail = vec![];
+            self.compile_pattern(arm.pattern, subject, &mut vec![], &mut jmps_fail)?;
+            if let Some(guard) = arm.guard {
+                self.compile_expr(guard)?;
+                jmps_fail.push(self.emit(Instruction::new(OpCode::JumpNotTrue, &[9999])));
+            }
+
+            self.compile_expr(arm.body)?;
+            jmps_end.push(self.emit(Instruction::new(OpCode::Jump, &[9999])));
+            for (name, prev) in shadowed.into_iter().rev() {
+                self.symbol_table.borrow_mut().restore(&name, prev);
+            }
+
+            let next_arm = self.instructions().len() as u32;
+            for jmp in jmps_fail {
+                self.patch(jmp, Instruction::new(OpCode::JumpNotTrue, &[next_arm]));
+            }
+        }
+        self.emit(Instruction::null());
+
+        let end = self.instructions().len() as u32;
+        for jmp in jmps_end {
+            self.patch(jmp, Instruction::new(OpCode::Jump, &[end]));
+        }
+        Ok(())
+    }
+
+    fn compile_destructure(
+        &mut self,
+        DestructureStmt { pattern, expr }: DestructureStmt,
+    ) -> CompileResult {
+        self.compile_expr(expr)?;
+        let subject = self.define_temp();
+        self.store_symbol(subject);
+
+        let pattern_str = pattern.to_string();
+        let mut jmps_fail = vec![];
+        self.compile_pattern(pattern, subject, &mut vec![], &mut jmps_fail)?;
+        if jmps_fail.is_empty() {
+            return Ok(());
+        }
+        let jmp_end = self.emit(Instruction::new(OpCode::Jump, &[9999]));
+
+        let fail = self.instructions().len() as u32;
+        for jmp in jmps_fail {
+            self.patch(jmp, Instruction::new(OpCode::JumpNotTrue, &[fail]));
+        }
+        self.load_symbol(subject);
+        let idx = self.add_constant(Object::String(pattern_str)) as u32;
+        self.emit(Instruction::new(OpCode::DestructureFail, &[idx]));
+
+        self.patch(
+            jmp_end,
+            Instruction::new(OpCode::Jump, &[self.instructions().len() as u32]),
+        );
+        Ok(())
+    }
+
+    /// Emits the checks and bindings for `pattern` applied to the part of
+    /// `subject` reached through `path`. Every failed check jumps to a
+    /// `JumpNotTrue` recorded in `jmps_fail`
+    fn compile_pattern(
+        &mut self,
+        pattern: Pattern,
+        subject: Symbol,
+        path: &mut Vec<PatternAccess>,
+        jmps_fail: &mut Vec<usize>,
+    ) -> CompileResult {
+        match pattern {
+            Pattern::Wildcard => {}
+            Pattern::Ident(i) => {
+                self.load_pattern_path(subject, path)?;
+                let sym = self.symbol_table.borrow_mut().define(&i);
+                self.store_symbol(sym);
+            }
+            Pattern::Literal(l) => {
+                self.load_pattern_path(subject, path)?;
+                self.compile_expr(l)?;
+                self.emit(Instruction::new(OpCode::Eq, &[]));
+                jmps_fail.push(self.emit(Instruction::new(OpCode::JumpNotTrue, &[9999])));
+            }
+            Pattern::Array(ArrayPattern { elements, rest }) => {
+                let len = elements.len();
+                self.load_pattern_path(subject, path)?;
+                self.emit(Instruction::new(
+                    OpCode::MatchArray,
+                    &[len as u32, rest.is_some() as u32],
+                ));
+                jmps_fail.push(self.emit(Instruction::new(OpCode::JumpNotTrue, &[9999])));
+
+                for (idx, p) in elements.into_iter().enumerate() {
+                    path.push(PatternAccess::Index(Expression::Number(idx as i64)));
+                    self.compile_pattern(p, subject, path, jmps_fail)?;
+                    path.pop();
+                }
+                if let Some(rest) = rest {
+                    path.push(PatternAccess::Rest(len));
+                    self.compile_pattern(*rest, subject, path, jmps_fail)?;
+                    path.pop();
+                }
+            }
+            Pattern::Hash(HashPattern { pairs }) => {
+                for (k, p) in pairs {
+                    self.load_pattern_path(subject, path)?;
+                    self.compile_expr(k.clone())?;
+                    self.emit(Instruction::new(OpCode::MatchKey, &[]));
+                    jmps_fail.push(self.emit(Instruction::new(OpCode::JumpNotTrue, &[9999])));
+
+                    path.push(PatternAccess::Index(k));
+                    self.compile_pattern(p, subject, path, jmps_fail)?;
+                    path.pop();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn load_pattern_path(&mut self, subject: Symbol, path: &[PatternAccess]) -> CompileResult {
+        self.load_symbol(subject);
+        for access in path {
+            match access {
+                PatternAccess::Index(e) => {
+                    self.compile_expr(e.clone())?;
+                    self.emit(Instruction::new(OpCode::Index, &[]));
+                }
+                PatternAccess::Rest(start) => {
+                    self.emit(Instruction::new(OpCode::ArrayRest, &[*start as u32]));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Loops with the iterable and the next index kept on the stack, binding
+    /// each item to a slot that only lives in the body
+    fn compile_for(
+        &mut self,
+        ForExpr {
+            ident,
+            iterable,
+            body,
+        }: ForExpr,
+    ) -> CompileResult {
+        self.compile_expr(*iterable)?;
+        self.emit(Instruction::new(OpCode::Iter, &[]));
+        let next = self.emit(Instruction::new(OpCode::IterNext, &[9999]));
+
+        let prev = self.symbol_table.borrow_mut().shadow(&ident);
+        let sym = self.symbol_table.borrow().resolve_local(&ident).unwrap();
+        self.store_symbol(sym);
+        self.compile_block(body)?;
+        self.symbol_table.borrow_mut().restore(&ident, prev);
+        self.emit(Instruction::new(OpCode::Jump, &[next as u32]));
+
+        self.patch(
+            next,
+            Instruction::new(OpCode::IterNext, &[self.instructions().len() as u32]),
+        );
+        self.emit(Instruction::null());
+        Ok(())
+    }
+
+    /// Runs the body under a handler that jumps to `catch`, then to `finally`
+    /// which rethrows the error when it was not caught
+    fn compile_try(&mut self, t: TryExpr) -> CompileResult {
+        // The handler of the body goes to `finally` when there is no `catch`
+        let try_op = match t.catch {
+            Some(_) => OpCode::Try,
+            None => OpCode::TryFinally,
+        };
+        self.mark_line(t.line);
+        let try_pos = self.emit(Instruction::new(try_op, &[9999]));
+        self.current_scope_mut().handlers.push(t.finally.clone());
+        self.compile_block_value(t.body)?;
+        self.current_scope_mut().handlers.pop();
+        self.emit(Instruction::new(OpCode::EndTry, &[]));
+        let mut jmps_done = vec![self.emit(Instruction::new(OpCode::Jump, &[9999]))];
+        self.patch(
+            try_pos,
+            Instruction::new(try_op, &[self.instructions().len() as u32]),
+        );
+
+        // The VM pushes the error value before jumping to a handler
+        if let Some(catch) = t.catch {
+            let finally_pos = t.finally.is_some().then(|| {
+                self.mark_line(t.line);
+                self.emit(Instruction::new(OpCode::TryFinally, &[9999]))
+            });
+            if finally_pos.is_some() {
+                self.current_scope_mut().handlers.push(t.finally.clone());
+            }
+
+            let prev = self.symbol_table.borrow_mut().shadow(&catch.ident);
+            let sym = self
+                .symbol_table
+                .borrow()
+                .resolve_local(&catch.ident)
+                .unwrap();
+            self.store_symbol(sym);
+            self.compile_block_value(catch.body)?;
+            self.symbol_table.borrow_mut().restore(&catch.ident, prev);
+
+            if let Some(pos) = finally_pos {
+                self.current_scope_mut().handlers.pop();
+                self.emit(Instruction::new(OpCode::EndTry, &[]));
+                jmps_done.push(self.emit(Instruction::new(OpCode::Jump, &[9999])));
+                self.patch(
+                    pos,
+                    Instruction::new(OpCode::TryFinally, &[self.instructions().len() as u32]),
+                );
+            }
+        }
+
+        if let Some(finally) = &t.finally {
+            self.compile_block(finally.clone())?;
+            self.emit(Instruction::new(OpCode::Rethrow, &[]));
+        }
+        for jmp in jmps_done {
+            self.patch(
+                jmp,
+                Instruction::new(OpCode::Jump, &[self.instructions().len() as u32]),
+            );
+        }
+        if let Some(finally) = t.finally {
+            self.compile_block(finally)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the handlers a `return` jumps out of, running their `finally` blocks
+    fn compile_leave_handlers(&mut self) -> CompileResult {
+        let handlers = std::mem::take(&mut self.current_scope_mut().handlers);
+        for (idx, finally) in handlers.iter().enumerate().rev() {
+            self.emit(Instruction::new(OpCode::EndTry, &[]));
+            if let Some(finally) = finally {
+                // A `return` in the block only leaves the handlers around it
+                self.current_scope_mut().handlers = handlers[..idx].to_vec();
+                self.compile_block(finally.clone())?;
+            }
+        }
+        self.current_scope_mut().handlers = handlers;
+        Ok(())
+    }
+
+    /// Compiles a block leaving its value, or `null`, on the stack
+    fn compile_block_value(&mut self, block: Vec<Statement>) -> CompileResult {
+        let start = self.instructions().len();
+        self.compile_block(block)?;
+        match self.current_scope().last {
+            Some(last) if last.opcode == OpCode::Pop && last.pos >= start => self.remove_last(),
+            _ => {
+                self.emit(Instruction::null());
+            }
+        }
+        Ok(())
+    }
+
+    /// Defines a symbol that cannot clash with any identifier
+    fn define_temp(&mut self) -> Symbol {
+        self.temps += 1;
+        self.symbol_table
+            .borrow_mut()
+            .define(&format!("${}", self.temps))
+    }
+
+    fn load_symbol(&mut self, sym: Symbol) {
+        match sym.scope {
+            symbol_table::Scope::Global => {
+                self.emit(Instruction::new(OpCode::GetGlobal, &[sym.index as u32]))
+            }
+            symbol_table::Scope::Local => {
+                self.emit(Instruction::new(OpCode::GetLocal, &[sym.index as u32]))
+            }
+            symbol_table::Scope::Builtin => {
+                self.emit(Instruction::new(OpCode::GetBuiltin, &[sym.index as u32]))
+            }
+            symbol_table::Scope::Free => {
+                self.emit(Instruction::new(OpCode::GetFree, &[sym.index as u32]))
+            }
+            symbol_table::Scope::Function => {
+                self.emit(Instruction::new(OpCode::CurrentClosure, &[]))
+            }
+        };
+    }
+
+    fn store_symbol(&mut self, sym: Symbol) {
+        match sym.scope {
+            symbol_table::Scope::Global => {
+                self.emit(Instruction::new(OpCode::SetGlobal, &[sym.index as u32]))
+            }
+            symbol_table::Scope::Local => {
+                self.emit(Instruction::new(OpCode::SetLocal, &[sym.index as u32]))
+            }
+            _ => unreachable!(),
+        };
     }
 
     fn add_constant(&mut self, obj: Object) -> usize {
@@ -290,6 +912,7 @@ impl Compiler {
         self.compile_expr(*i.left)?;
         self.compile_expr(*i.right)?;
 
+        self.mark_line(i.line);
         match i.operator {
             TokenType::Plus => self.emit(Instruction::new(OpCode::Add, &[])),
             TokenType::Minus => self.emit(Instruction::new(OpCode::Sub, &[])),
@@ -307,6 +930,7 @@ impl Compiler {
         self.compile_expr(*i.right)?;
         self.compile_expr(*i.left)?;
 
+        self.mark_line(i.line);
         match i.operator {
             TokenType::Lt => self.emit(Instruction::new(OpCode::Greater, &[])),
             _ => unreachable!(),
@@ -321,6 +945,12 @@ impl Compiler {
             .unwrap_or(false)
     }
 
+    /// Reports errors of the next instruction at `line`
+    fn mark_line(&mut self, line: usize) {
+        let pos = self.instructions().len();
+        self.current_scope_mut().lines.push((pos, line));
+    }
+
     fn remove_last(&mut self) {
         let last = self.current_scope().last.expect("No instruction to remove");
         self.instructions_mut().remove(last.pos);
@@ -368,5 +998,15 @@ impl Compiler {
 
 type CompileResult = Result<(), String>;
 
+/// Names a statement binds in its block
+fn bound_names(stmt: &Statement) -> Vec<&Ident> {
+    match stmt {
+        Statement::Let(l) => vec![&l.ident],
+        Statement::Destructure(d) => d.pattern.idents(),
+        Statement::Import(i) => vec![&i.name],
+        _ => vec![],
+    }
+}
+
 #[cfg(test)]
 mod test;
@@ -1,4 +1,8 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Symbol {
@@ -11,6 +15,10 @@ pub enum Scope {
     Global,
     Local,
     Builtin,
+    /// Captured from an enclosing function
+    Free,
+    /// The function currently being compiled, used for self recursion
+    Function,
 }
 
 pub type SymbolTableRef = Rc<RefCell<SymbolTable>>;
@@ -18,24 +26,37 @@ pub type SymbolTableRef = Rc<RefCell<SymbolTable>>;
 #[derive(Clone)]
 pub struct SymbolTable {
     pub outer: Option<SymbolTableRef>,
+    /// Symbols of the enclosing scope captured by this one, by free index
+    pub free_symbols: Vec<Symbol>,
     store: HashMap<String, Symbol>,
     stored: usize,
+    /// Global slots below this one hold the prelude, which is never overwritten
+    prelude: usize,
+    /// Global slots bound by `shadow`, which closures capture like locals since
+    /// a loop can set them again
+    shadowed: HashSet<u16>,
 }
 
 impl SymbolTable {
     pub fn empty() -> SymbolTableRef {
         Rc::new(RefCell::new(Self {
             outer: None,
+            free_symbols: vec![],
             store: HashMap::default(),
             stored: 0,
+            prelude: 0,
+            shadowed: HashSet::new(),
         }))
     }
 
     pub fn new_enclosed(outer: &SymbolTableRef) -> SymbolTableRef {
         Rc::new(RefCell::new(Self {
             outer: Some(outer.clone()),
+            free_symbols: vec![],
             store: HashMap::default(),
             stored: 0,
+            prelude: 0,
+            shadowed: HashSet::new(),
         }))
     }
 
@@ -45,6 +66,15 @@ impl SymbolTable {
         } else {
             Scope::Global
         };
+        // Redefining a name reuses its slot, like the evaluator's environments,
+        // so closures that read the name see the new value
+        if let Some(sym) = self
+            .store
+            .get(name)
+            .filter(|s| s.scope == scope && !self.is_prelude(s))
+        {
+            return *sym;
+        }
 
         let sym = Symbol {
             scope,
@@ -55,6 +85,29 @@ impl SymbolTable {
         self.store[name]
     }
 
+    /// Binds `name` to a new slot, returning the symbol it hides so `restore`
+    /// can bring it back once the binding goes out of scope
+    pub fn shadow(&mut self, name: &str) -> Option<Symbol> {
+        let prev = self.store.remove(name);
+        let sym = self.define(name);
+        if sym.scope == Scope::Global {
+            self.shadowed.insert(sym.index);
+        }
+        prev
+    }
+
+    /// Symbol `name` has in this table, without capturing it from an outer one
+    pub fn resolve_local(&self, name: &str) -> Option<Symbol> {
+        self.store.get(name).copied()
+    }
+
+    pub fn restore(&mut self, name: &str, prev: Option<Symbol>) {
+        match prev {
+            Some(sym) => self.store.insert(name.to_string(), sym),
+            None => self.store.remove(name),
+        };
+    }
+
     pub fn define_builtin(&mut self, name: &str) -> Symbol {
         let sym = Symbol {
             scope: Scope::Builtin,
@@ -64,15 +117,84 @@ impl SymbolTable {
         self.store[name]
     }
 
-    pub fn resolve(&self, name: &str) -> Option<Symbol> {
-        self.store
-            .get(name)
-            .cloned()
-            .or_else(|| self.outer.as_ref().and_then(|o| o.borrow().resolve(name)))
+    pub fn define_function_name(&mut self, name: &str) -> Symbol {
+        let sym = Symbol {
+            scope: Scope::Function,
+            index: 0,
+        };
+        self.store.insert(name.to_string(), sym);
+        sym
+    }
+
+    fn define_free(&mut self, name: &str, original: Symbol) -> Symbol {
+        self.free_symbols.push(original);
+        let sym = Symbol {
+            scope: Scope::Free,
+            index: (self.free_symbols.len() - 1) as u16,
+        };
+        self.store.insert(name.to_string(), sym);
+        sym
+    }
+
+    pub fn resolve(&mut self, name: &str) -> Option<Symbol> {
+        if let Some(sym) = self.store.get(name) {
+            return Some(*sym);
+        }
+
+        let outer = self.outer.clone()?;
+        let sym = outer.borrow_mut().resolve(name)?;
+        match sym.scope {
+            Scope::Global if !outer.borrow().is_shadowed(sym) => Some(sym),
+            Scope::Builtin => Some(sym),
+            _ => Some(self.define_free(name, sym)),
+        }
+    }
+
+    fn is_shadowed(&self, sym: Symbol) -> bool {
+        match &self.outer {
+            Some(outer) => outer.borrow().is_shadowed(sym),
+            None => self.shadowed.contains(&sym.index),
+        }
     }
 
     pub fn symbols(&self) -> usize {
-        self.store.len()
+        self.stored
+    }
+
+    /// Marks every global defined so far as part of the prelude
+    pub fn seal_prelude(&mut self) {
+        self.prelude = self.stored;
+    }
+
+    fn is_prelude(&self, sym: &Symbol) -> bool {
+        sym.scope == Scope::Global && (sym.index as usize) < self.prelude
+    }
+
+    /// Global scope sharing the builtins, the prelude, the loaded modules and
+    /// the global slots of this one, but none of its other names
+    pub fn namespace(&self) -> SymbolTableRef {
+        let store = self
+            .store
+            .iter()
+            .filter(|(name, sym)| {
+                sym.scope == Scope::Builtin || self.is_prelude(sym) || name.starts_with('@')
+            })
+            .map(|(name, sym)| (name.clone(), *sym))
+            .collect();
+
+        Rc::new(RefCell::new(Self {
+            outer: None,
+            free_symbols: vec![],
+            store,
+            stored: self.stored,
+            prelude: self.prelude,
+            shadowed: HashSet::new(),
+        }))
+    }
+
+    /// Marks the first `count` slots as used, after they were taken by a namespace
+    pub fn reserve(&mut self, count: usize) {
+        self.stored = self.stored.max(count);
     }
 }
 
@@ -94,7 +216,8 @@ mod test {
         local2.borrow_mut().define("e");
         local2.borrow_mut().define("f");
 
-        let expected: &[(SymbolTableRef, &[(&'static str, Scope, u16)])] = &[
+        type Expected<'a> = &'a [(&'static str, Scope, u16)];
+        let expected: &[(SymbolTableRef, Expected)] = &[
             (
                 local1,
                 &[
@@ -118,9 +241,9 @@ mod test {
         for (l, e) in expected {
             for e in *e {
                 let r = l
-                    .borrow()
+                    .borrow_mut()
                     .resolve(e.0)
-                    .expect(&format!("Symbol {} not found", e.0));
+                    .unwrap_or_else(|| panic!("Symbol {} not found", e.0));
                 assert_eq!(
                     r,
                     Symbol {
@@ -133,4 +256,66 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn redefine() {
+        let glob = SymbolTable::empty();
+        glob.borrow_mut().define("a");
+        glob.borrow_mut().define("b");
+        assert_eq!(
+            glob.borrow_mut().define("a"),
+            Symbol {
+                scope: Scope::Global,
+                index: 0
+            }
+        );
+        assert_eq!(glob.borrow().symbols(), 2);
+
+        let local = SymbolTable::new_enclosed(&glob);
+        local.borrow_mut().resolve("a");
+        assert_eq!(
+            local.borrow_mut().define("a"),
+            Symbol {
+                scope: Scope::Local,
+                index: 0
+            }
+        );
+        assert_eq!(local.borrow_mut().define("a").index, 0);
+    }
+
+    #[test]
+    fn resolve_free() {
+        let glob = SymbolTable::empty();
+        glob.borrow_mut().define("a");
+
+        let local1 = SymbolTable::new_enclosed(&glob);
+        local1.borrow_mut().define("b");
+
+        let local2 = SymbolTable::new_enclosed(&local1);
+        local2.borrow_mut().define("c");
+        local2.borrow_mut().define_function_name("f");
+
+        let expected = [
+            ("a", Scope::Global, 0),
+            ("b", Scope::Free, 0),
+            ("c", Scope::Local, 0),
+            ("f", Scope::Function, 0),
+        ];
+        for (name, scope, index) in expected {
+            let r = local2
+                .borrow_mut()
+                .resolve(name)
+                .unwrap_or_else(|| panic!("Symbol {} not found", name));
+            assert_eq!(r, Symbol { scope, index }, "Symbol {} is wrong", name);
+        }
+        assert!(local2.borrow_mut().resolve("d").is_none());
+
+        assert_eq!(
+            local2.borrow().free_symbols,
+            vec![Symbol {
+                scope: Scope::Local,
+                index: 0
+            }]
+        );
+    }
 }
@@ -391,6 +391,87 @@ fn index() {
     )
 }
 
+#[test]
+fn slices() {
+    test!((
+        "[1][:1]",
+        &[Object::Integer(1), Object::Integer(1)],
+        &[
+            Instruction::new(OpCode::Constant, &[1]),
+            Instruction::new(OpCode::Array, &[1]),
+            Instruction::null(),
+            Instruction::new(OpCode::Constant, &[2]),
+            Instruction::new(OpCode::Slice, &[]),
+            Instruction::new(OpCode::Pop, &[]),
+        ],
+    ),)
+}
+
+#[test]
+fn templates() {
+    test!((
+        r#""a${1}b""#,
+        &[
+            Object::String("a".into()),
+            Object::Integer(1),
+            Object::String("b".into()),
+        ],
+        &[
+            Instruction::new(OpCode::Constant, &[1]),
+            Instruction::new(OpCode::Constant, &[2]),
+            Instruction::new(OpCode::Constant, &[3]),
+            Instruction::new(OpCode::Template, &[3]),
+            Instruction::new(OpCode::Pop, &[]),
+        ],
+    ),)
+}
+
+#[test]
+fn ranges() {
+    test!((
+        "1..=2",
+        &[Object::Integer(1), Object::Integer(2)],
+        &[
+            Instruction::new(OpCode::Constant, &[1]),
+            Instruction::new(OpCode::Constant, &[2]),
+            Instruction::new(OpCode::Range, &[1]),
+            Instruction::new(OpCode::Pop, &[]),
+        ],
+    ),)
+}
+
+#[test]
+fn methods() {
+    test!(
+        (
+            "[1].push(2)",
+            &[
+                Object::Integer(1),
+                Object::Integer(2),
+                Object::String("push".into()),
+            ],
+            &[
+                Instruction::new(OpCode::Constant, &[1]),
+                Instruction::new(OpCode::Array, &[1]),
+                Instruction::new(OpCode::Constant, &[2]),
+                Instruction::new(OpCode::Method, &[3, 1, 0]),
+                Instruction::new(OpCode::Pop, &[]),
+            ],
+        ),
+        (
+            "[].push(...[1])",
+            &[Object::Integer(1), Object::String("push".into())],
+            &[
+                Instruction::new(OpCode::Array, &[0]),
+                Instruction::new(OpCode::Constant, &[1]),
+                Instruction::new(OpCode::Array, &[1]),
+                Instruction::new(OpCode::Method, &[2, 1, 1]),
+                Instruction::new(OpCode::Pop, &[]),
+            ],
+        ),
+    )
+}
+
 #[test]
 fn functions() {
     test!(
@@ -490,6 +571,35 @@ fn functions() {
                 Instruction::new(OpCode::Pop, &[]),
             ]
         ),
+        (
+            "fn(a = 1) { a }",
+            &[
+                Object::Integer(1),
+                Object::CompiledFunc(Rc::new(CompiledFuncObj {
+                    required: 0,
+                    ..CompiledFuncObj::new(
+                        [
+                            Instruction::new(OpCode::JumpHasArg, &[0, 9]),
+                            Instruction::new(OpCode::Constant, &[1]),
+                            Instruction::new(OpCode::SetLocal, &[0]),
+                            Instruction::new(OpCode::GetLocal, &[0]),
+                            Instruction::new(OpCode::ReturnValue, &[]),
+                        ]
+                        .into_iter()
+                        .fold(Bytes::default(), |mut b, i| {
+                            b.push(i);
+                            b
+                        }),
+                        1,
+                        1,
+                    )
+                })),
+            ],
+            &[
+                Instruction::new(OpCode::Constant, &[2]),
+                Instruction::new(OpCode::Pop, &[]),
+            ]
+        ),
     )
 }
 
@@ -728,6 +838,205 @@ fn builtins() {
     )
 }
 
+#[test]
+fn closures() {
+    test!((
+        "fn(a) { fn(b) { a + b } }",
+        &[
+            Object::CompiledFunc(Rc::new(CompiledFuncObj::new(
+                [
+                    Instruction::new(OpCode::GetFree, &[0]),
+                    Instruction::new(OpCode::GetLocal, &[0]),
+                    Instruction::new(OpCode::Add, &[]),
+                    Instruction::new(OpCode::ReturnValue, &[]),
+                ]
+                .into_iter()
+                .fold(Bytes::default(), |mut b, i| {
+                    b.push(i);
+                    b
+                }),
+                1,
+                1,
+            ))),
+            Object::CompiledFunc(Rc::new(CompiledFuncObj::new(
+                [
+                    Instruction::new(OpCode::GetLocal, &[0]),
+                    Instruction::new(OpCode::Closure, &[1, 1]),
+                    Instruction::new(OpCode::ReturnValue, &[]),
+                ]
+                .into_iter()
+                .fold(Bytes::default(), |mut b, i| {
+                    b.push(i);
+                    b
+                }),
+                1,
+                1,
+            ))),
+        ],
+        &[
+            Instruction::new(OpCode::Constant, &[2]),
+            Instruction::new(OpCode::Pop, &[]),
+        ]
+    ),)
+}
+
+#[test]
+fn func_decls() {
+    test!((
+        "a(); fn a() { b() } fn b() { 1 }",
+        &[
+            Object::CompiledFunc(Rc::new(CompiledFuncObj::new(
+                [
+                    Instruction::new(OpCode::GetGlobal, &[1]),
+                    Instruction::new(OpCode::Call, &[0]),
+                    Instruction::new(OpCode::ReturnValue, &[]),
+                ]
+                .into_iter()
+                .fold(Bytes::default(), |mut b, i| {
+                    b.push(i);
+                    b
+                }),
+                0,
+                0,
+            ))),
+            Object::Integer(1),
+            Object::CompiledFunc(Rc::new(CompiledFuncObj::new(
+                [
+                    Instruction::new(OpCode::Constant, &[2]),
+                    Instruction::new(OpCode::ReturnValue, &[]),
+                ]
+                .into_iter()
+                .fold(Bytes::default(), |mut b, i| {
+                    b.push(i);
+                    b
+                }),
+                0,
+                0,
+            ))),
+        ],
+        &[
+            Instruction::new(OpCode::Constant, &[1]),
+            Instruction::new(OpCode::SetGlobal, &[0]),
+            Instruction::new(OpCode::Constant, &[3]),
+            Instruction::new(OpCode::SetGlobal, &[1]),
+            Instruction::new(OpCode::GetGlobal, &[0]),
+            Instruction::new(OpCode::Call, &[0]),
+            Instruction::new(OpCode::Pop, &[]),
+        ]
+    ),)
+}
+
+#[test]
+fn match_expr() {
+    test!(
+        (
+            "match (1) { 1 => 10, _ => 20 }",
+            &[
+                Object::Integer(1),
+                Object::Integer(1),
+                Object::Integer(10),
+                Object::Integer(20)
+            ],
+            &[
+                Instruction::new(OpCode::Constant, &[1]),
+                Instruction::new(OpCode::SetGlobal, &[0]),
+                Instruction::new(OpCode::GetGlobal, &[0]),
+                Instruction::new(OpCode::Constant, &[2]),
+                Instruction::new(OpCode::Eq, &[]),
+                Instruction::new(OpCode::JumpNotTrue, &[22]),
+                Instruction::new(OpCode::Constant, &[3]),
+                Instruction::new(OpCode::Jump, &[31]),
+                Instruction::new(OpCode::Constant, &[4]),
+                Instruction::new(OpCode::Jump, &[31]),
+                Instruction::null(),
+                Instruction::new(OpCode::Pop, &[]),
+            ]
+        ),
+        (
+            "match ([]) { [a, ..r] => a }",
+            &[Object::Integer(0)],
+            &[
+                Instruction::new(OpCode::Array, &[0]),
+                Instruction::new(OpCode::SetGlobal, &[0]),
+                Instruction::new(OpCode::GetGlobal, &[0]),
+                Instruction::new(OpCode::MatchArray, &[1, 1]),
+                Instruction::new(OpCode::JumpNotTrue, &[41]),
+                Instruction::new(OpCode::GetGlobal, &[0]),
+                Instruction::new(OpCode::Constant, &[1]),
+                Instruction::new(OpCode::Index, &[]),
+                Instruction::new(OpCode::SetGlobal, &[1]),
+                Instruction::new(OpCode::GetGlobal, &[0]),
+                Instruction::new(OpCode::ArrayRest, &[1]),
+                Instruction::new(OpCode::SetGlobal, &[2]),
+                Instruction::new(OpCode::GetGlobal, &[1]),
+                Instruction::new(OpCode::Jump, &[44]),
+                Instruction::null(),
+                Instruction::new(OpCode::Pop, &[]),
+            ]
+        ),
+    )
+}
+
+#[test]
+fn try_expr() {
+    test!(
+        (
+            "try { 1 } catch (e) { e } finally { 2 }",
+            &[Object::Integer(1), Object::Integer(2), Object::Integer(2)],
+            &[
+                Instruction::new(OpCode::Try, &[10]),        // 0
+                Instruction::new(OpCode::Constant, &[1]),    // 3
+                Instruction::new(OpCode::EndTry, &[]),       // 6
+                Instruction::new(OpCode::Jump, &[28]),       // 7
+                Instruction::new(OpCode::TryFinally, &[23]), // 10
+                Instruction::new(OpCode::SetGlobal, &[0]),   // 13
+                Instruction::new(OpCode::GetGlobal, &[0]),   // 16
+                Instruction::new(OpCode::EndTry, &[]),       // 19
+                Instruction::new(OpCode::Jump, &[28]),       // 20
+                Instruction::new(OpCode::Constant, &[2]),    // 23
+                Instruction::new(OpCode::Pop, &[]),          // 26
+                Instruction::new(OpCode::Rethrow, &[]),      // 27
+                Instruction::new(OpCode::Constant, &[3]),    // 28
+                Instruction::new(OpCode::Pop, &[]),          // 31
+                Instruction::new(OpCode::Pop, &[]),          // 32
+            ]
+        ),
+        (
+            "fn() { try { return 1 } catch (e) { 2 } }",
+            &[
+                Object::Integer(1),
+                Object::Integer(2),
+                Object::CompiledFunc(Rc::new(CompiledFuncObj::new(
+                    [
+                        Instruction::new(OpCode::Try, &[15]),
+                        Instruction::new(OpCode::Constant, &[1]),
+                        // Leaves the handler before returning
+                        Instruction::new(OpCode::EndTry, &[]),
+                        Instruction::new(OpCode::ReturnValue, &[]),
+                        Instruction::null(),
+                        Instruction::new(OpCode::EndTry, &[]),
+                        Instruction::new(OpCode::Jump, &[20]),
+                        Instruction::new(OpCode::SetLocal, &[0]),
+                        Instruction::new(OpCode::Constant, &[2]),
+                        Instruction::new(OpCode::ReturnValue, &[]),
+                    ]
+                    .into_iter()
+                    .fold(Bytes::default(), |mut b, i| {
+                        b.push(i);
+                        b
+                    }),
+                    1,
+                    0,
+                )))
+            ],
+            &[
+                Instruction::new(OpCode::Constant, &[3]),
+                Instruction::new(OpCode::Pop, &[]),
+            ]
+        ),
+    )
+}
+
 fn test(cases: &[(&str, &[Object], &[Instruction])]) {
     for (input, consts, instrs) in cases {
         let lexer = Lexer::new(input.to_string());
@@ -738,7 +1047,7 @@ fn test(cases: &[(&str, &[Object], &[Instruction])]) {
         compiler.compile(program).unwrap();
         let bytecode = compiler.bytecode();
 
-        let expected_bytes = instrs.into_iter().fold(Bytes::default(), |mut acc, x| {
+        let expected_bytes = instrs.iter().fold(Bytes::default(), |mut acc, x| {
             acc.push(x);
             acc
         });
@@ -0,0 +1,143 @@
+use crate::eval::Object;
+
+/// Largest LCS table built, past which changed lines are shown as all removed then all added
+const MAX_TABLE: usize = 1 << 22;
+
+/// Line diff of two values, with lines only in `left` marked `-` and lines only in `right` `+`
+pub fn values(left: &Object, right: &Object) -> String {
+    let (left, right) = (pretty(left), pretty(right));
+    let (left, right): (Vec<_>, Vec<_>) = (left.lines().collect(), right.lines().collect());
+
+    // Lines shared at both ends are kept out of the table
+    let prefix = left.iter().zip(&right).take_while(|(l, r)| l == r).count();
+    let suffix = left[prefix..]
+        .iter()
+        .rev()
+        .zip(right[prefix..].iter().rev())
+        .take_while(|(l, r)| l == r)
+        .count();
+
+    let mut out: Vec<_> = left[..prefix].iter().map(|l| format!("  {}", l)).collect();
+    out.extend(changes(
+        &left[prefix..left.len() - suffix],
+        &right[prefix..right.len() - suffix],
+    ));
+    out.extend(
+        left[left.len() - suffix..]
+            .iter()
+            .map(|l| format!("  {}", l)),
+    );
+    out.join("\n")
+}
+
+fn changes(left: &[&str], right: &[&str]) -> Vec<String> {
+    if (left.len() + 1).saturating_mul(right.len() + 1) > MAX_TABLE {
+        let removed = left.iter().map(|l| format!("- {}", l));
+        return removed
+            .chain(right.iter().map(|r| format!("+ {}", r)))
+            .collect();
+    }
+
+    // Longest common subsequence of the lines following each position
+    let mut lcs = vec![vec![0; right.len() + 1]; left.len() + 1];
+    for i in (0..left.len()).rev() {
+        for j in (0..right.len()).rev() {
+            lcs[i][j] = if left[i] == right[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut out = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < left.len() || j < right.len() {
+        if i < left.len() && j < right.len() && left[i] == right[j] {
+            out.push(format!("  {}", left[i]));
+            i += 1;
+            j += 1;
+        } else if j == right.len() || (i < left.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            out.push(format!("- {}", left[i]));
+            i += 1;
+        } else {
+            out.push(format!("+ {}", right[j]));
+            j += 1;
+        }
+    }
+    out
+}
+
+/// Source-like representation with one array element or hash pair per line
+fn pretty(value: &Object) -> String {
+    let mut out = String::new();
+    write_pretty(&mut out, value, 0);
+    out
+}
+
+fn write_pretty(out: &mut String, value: &Object, depth: usize) {
+    let items: Vec<_> = match value {
+        Object::Array(a) if !a.elements.is_empty() => {
+            a.elements.iter().map(|e| (None, &**e)).collect()
+        }
+        Object::Hash(h) if !h.is_empty() => h.iter().map(|(k, v)| (Some(k.repr()), &**v)).collect(),
+        v => return out.push_str(&v.repr()),
+    };
+    let (open, close) = match value {
+        Object::Array(_) => ('[', ']'),
+        _ => ('{', '}'),
+    };
+
+    out.push(open);
+    let len = items.len();
+    for (idx, (key, item)) in items.into_iter().enumerate() {
+        out.push('\n');
+        out.push_str(&"  ".repeat(depth + 1));
+        if let Some(key) = key {
+            out.push_str(&key);
+            out.push_str(": ");
+        }
+        write_pretty(out, item, depth + 1);
+        if idx + 1 < len {
+            out.push(',');
+        }
+    }
+    out.push('\n');
+    out.push_str(&"  ".repeat(depth));
+    out.push(close);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::eval::ArrayObj;
+    use std::rc::Rc;
+
+    #[test]
+    fn diff_values() {
+        let array = |xs: &[i64]| {
+            let elements = xs.iter().map(|x| Rc::new(Object::Integer(*x))).collect();
+            Object::Array(ArrayObj { elements })
+        };
+        assert_eq!(
+            values(&array(&[1, 2, 3]), &array(&[1, 4, 3])),
+            "  [\n    1,\n-   2,\n+   4,\n    3\n  ]"
+        );
+        assert_eq!(
+            values(&Object::String("a".into()), &Object::Integer(1)),
+            "- \"a\"\n+ 1"
+        );
+
+        let big: Vec<_> = (0..20000).collect();
+        let mut changed = big.clone();
+        changed[5000] = -1;
+        let diff = values(&array(&big), &array(&changed));
+        assert!(diff.contains("\n-   5000,\n+   -1,\n"));
+        assert_eq!(diff.lines().count(), 20003);
+
+        let other: Vec<_> = (20000..23000).collect();
+        let diff = values(&array(&big[..3000]), &array(&other));
+        assert!(diff.starts_with("  [\n-   0,"));
+        assert!(diff.contains("-   2999\n+   20000,"));
+    }
+}
@@ -2,7 +2,7 @@ use super::Object;
 use crate::ast::Ident;
 use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
-#[derive(Debug, PartialEq, Eq, Clone, Default)]
+#[derive(Debug, PartialEq, Clone, Default)]
 pub struct Environment {
     store: HashMap<Ident, Rc<Object>>,
     outer: Option<Rc<RefCell<Environment>>>,
@@ -39,4 +39,9 @@ impl Environment {
     pub fn set(&mut self, name: &Ident, value: Rc<Object>) {
         self.store.insert(name.into(), value);
     }
+
+    /// Names bound in this environment itself
+    pub fn bindings(&self) -> impl Iterator<Item = (&Ident, &Rc<Object>)> {
+        self.store.iter()
+    }
 }
@@ -0,0 +1,108 @@
+use super::*;
+use crate::ast::{modify_expr, modify_program, CallExpr, LetStmt, RangeExpr};
+
+/// Whether `c` calls `name` with a single argument
+pub fn is_call(c: &CallExpr, name: &str) -> bool {
+    matches!(&*c.func, Expression::Ident(i) if i == name) && c.arguments.len() == 1
+}
+
+/// `quote(expr)`, evaluating and splicing every `unquote(..)` inside it
+pub fn quote(expr: &Expression, env: &Rc<RefCell<Environment>>) -> EvalResult {
+    splice(expr.clone(), |arg| eval_expr(arg, env))
+}
+
+/// Quotes `expr`, replacing every `unquote(arg)` inside it with the value `f` gives for `arg`
+pub fn splice<F>(expr: Expression, mut f: F) -> EvalResult
+where
+    F: FnMut(&Expression) -> EvalResult,
+{
+    let expr = modify_expr(expr, &mut |e| match e {
+        Expression::Call(c) if is_call(&c, "unquote") => object_to_expr(&*f(&c.arguments[0])?),
+        e => Ok(e),
+    })?;
+
+    Ok(Rc::new(Object::Quote(expr)))
+}
+
+fn object_to_expr(obj: &Object) -> Result<Expression, String> {
+    match obj {
+        Object::Integer(i) => Ok(Expression::Number(*i)),
+        Object::Bool(b) => Ok(Expression::Bool(*b)),
+        Object::String(s) => Ok(Expression::String(s.clone())),
+        Object::Quote(e) => Ok(e.clone()),
+        Object::Array(a) => Ok(Expression::Array(ArrayExpr {
+            elements: a
+                .elements
+                .iter()
+                .map(|e| object_to_expr(e))
+                .collect::<Result<_, _>>()?,
+        })),
+        Object::Hash(h) => Ok(Expression::Hash(HashExpr {
+            pairs: h
+                .iter()
+                .map(|(k, v)| Ok((object_to_expr(k)?, object_to_expr(v)?)))
+                .collect::<Result<_, String>>()?,
+        })),
+        Object::Range(r) => Ok(Expression::Range(RangeExpr {
+            start: Box::new(Expression::Number(r.start)),
+            end: Box::new(Expression::Number(r.end)),
+            inclusive: false,
+        })),
+        o => Err(format!("cannot unquote {}", o.kind())),
+    }
+}
+
+/// Moves the top-level `let name = macro(..) { .. }` statements of the program into `env`
+pub fn define_macros(program: &mut Program, env: &Rc<RefCell<Environment>>) {
+    for stmt in std::mem::take(&mut program.statements) {
+        match stmt {
+            Statement::Let(LetStmt {
+                ident,
+                expr: Expression::Macro(expr),
+            }) => {
+                let obj = Object::Macro(FuncObj {
+                    expr,
+                    env: env.clone(),
+                });
+                env.borrow_mut().set(&ident, Rc::new(obj));
+            }
+            s => program.statements.push(s),
+        }
+    }
+}
+
+/// Replaces calls to macros defined in `env` with the quote they return
+pub fn expand_macros(program: Program, env: &Rc<RefCell<Environment>>) -> Result<Program, String> {
+    modify_program(program, &mut |e| {
+        let Expression::Call(c) = &e else {
+            return Ok(e);
+        };
+        let Expression::Ident(name) = &*c.func else {
+            return Ok(e);
+        };
+        let Some(m) = lookup_macro(name, env) else {
+            return Ok(e);
+        };
+
+        let args = c
+            .arguments
+            .iter()
+            .map(|a| Rc::new(Object::Quote(a.clone())))
+            .collect();
+        match &*apply_func(&Object::Func(m), args)? {
+            Object::Quote(e) => Ok(e.clone()),
+            o => Err(format!(
+                "macro {} must return a QUOTE, got {}",
+                name,
+                o.kind()
+            )),
+        }
+    })
+}
+
+fn lookup_macro(name: &Ident, env: &Rc<RefCell<Environment>>) -> Option<FuncObj> {
+    match &*env.borrow().get(name)? {
+        Object::Macro(m) => Some(m.clone()),
+        _ => None,
+    }
+}
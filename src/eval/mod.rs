@@ -1,19 +1,45 @@
 #![allow(dead_code)]
 
 use crate::{
-    ast::{ArrayExpr, Expression, HashExpr, Ident, Program, Statement},
-    builtin::Builtin,
+    ast::{
+        ArrayExpr, Expression, ForExpr, HashExpr, Ident, MatchExpr, Pattern, Program, Statement,
+        TemplateExprPart, TryExpr,
+    },
+    builtin::{Builtin, Caller},
+    exception,
     lexer::TokenType,
+    module::Module,
+    prelude,
 };
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{cell::RefCell, rc::Rc};
 
 pub use env::Environment;
+pub use macros::{define_macros, expand_macros, is_call, splice};
 pub use object::*;
 
 mod env;
+mod macros;
 mod object;
 
+thread_local! {
+    /// Evaluated once, then shared by every environment made by `prelude_env`
+    static PRELUDE: Rc<RefCell<Environment>> = {
+        let env = Environment::new();
+        eval_program(prelude::program(), &env).expect("Prelude should evaluate");
+        env
+    };
+}
+
+/// Global environment enclosed by the prelude, so its definitions override it
+pub fn prelude_env() -> Rc<RefCell<Environment>> {
+    let prelude = PRELUDE.with(|p| p.clone());
+    Rc::new(RefCell::new(Environment::new_enclosed(prelude)))
+}
+
 pub fn eval_program(prog: Program, env: &Rc<RefCell<Environment>>) -> EvalResult {
+    exception::reset();
+    hoist_funcs(&prog.statements, env);
+
     let mut res = Rc::new(Object::Null);
     for stmt in prog.statements {
         res = eval_stmt(&stmt, env)?;
@@ -25,6 +51,39 @@ pub fn eval_program(prog: Program, env: &Rc<RefCell<Environment>>) -> EvalResult
     Ok(res)
 }
 
+/// Runs each module in its own environment, binding its exports in `env`
+pub fn eval_modules(modules: Vec<Module>, env: &Rc<RefCell<Environment>>) -> Result<(), String> {
+    for m in modules {
+        let binding = Module::binding(&m.path);
+        if env.borrow().get(&binding).is_some() {
+            continue;
+        }
+
+        let module_env = prelude_env();
+        for stmt in &m.program.statements {
+            if let Statement::Import(i) = stmt {
+                let dep = Module::binding(&i.path);
+                if let Some(val) = env.borrow().get(&dep) {
+                    module_env.borrow_mut().set(&dep, val);
+                }
+            }
+        }
+        eval_program(m.program, &module_env).map_err(|e| format!("{}: {}", m.path, e))?;
+
+        let mut exports = HashObj::default();
+        for name in m.exports {
+            let val = module_env
+                .borrow()
+                .get(&name)
+                .ok_or(format!("{}: exported name {} is not defined", m.path, name))?;
+            exports.insert(Rc::new(Object::String(name)), val);
+        }
+        env.borrow_mut()
+            .set(&binding, Rc::new(Object::Hash(exports)));
+    }
+    Ok(())
+}
+
 fn eval_stmt(stmt: &Statement, env: &Rc<RefCell<Environment>>) -> EvalResult {
     match stmt {
         Statement::Let(l) => {
@@ -32,10 +91,41 @@ fn eval_stmt(stmt: &Statement, env: &Rc<RefCell<Environment>>) -> EvalResult {
             env.borrow_mut().set(&l.ident, val);
             Ok(Rc::new(Object::Null))
         }
+        Statement::Destructure(d) => {
+            let val = eval_expr(&d.expr, env)?;
+
+            let mut bindings = vec![];
+            if !match_pattern(&d.pattern, &val, &mut bindings, env)? {
+                return Err(format!(
+                    "cannot destructure {} with pattern {}",
+                    val.kind(),
+                    d.pattern
+                ));
+            }
+            for (ident, val) in bindings {
+                env.borrow_mut().set(&ident, val);
+            }
+            Ok(Rc::new(Object::Null))
+        }
+        // Already defined by `hoist_funcs`
+        Statement::Func(_) => Ok(Rc::new(Object::Null)),
         Statement::Return(r) => {
             let val = eval_expr(&r.expr, env)?;
             Ok(Rc::new(Object::Return(val)))
         }
+        Statement::Import(i) => {
+            let module = env.borrow().get(&Module::binding(&i.path)).ok_or(format!(
+                "module {} is not loaded, imports are only allowed at the top level",
+                i.path
+            ))?;
+            env.borrow_mut().set(&i.name, module);
+            Ok(Rc::new(Object::Null))
+        }
+        Statement::Export(_) => Ok(Rc::new(Object::Null)),
+        Statement::Throw(t) => {
+            let val = eval_expr(&t.expr, env)?;
+            Err(exception::throw(val, t.line))
+        }
         Statement::Expression(e) => eval_expr(e, env),
     }
 }
@@ -52,7 +142,7 @@ fn eval_expr(e: &Expression, env: &Rc<RefCell<Environment>>) -> EvalResult {
         Expression::Infix(i) => {
             let left = eval_expr(&i.left, env)?;
             let right = eval_expr(&i.right, env)?;
-            eval_infix(left, i.operator, right)
+            eval_infix(left, i.operator, right).map_err(|e| exception::locate(e, i.line))
         }
         Expression::Bool(b) => Ok(Rc::new(Object::Bool(*b))),
         Expression::If(i) => {
@@ -71,20 +161,65 @@ fn eval_expr(e: &Expression, env: &Rc<RefCell<Environment>>) -> EvalResult {
             expr: f.clone(),
             env: env.clone(),
         }))),
+        Expression::Macro(_) => Err("macros can only be defined with a top-level let".into()),
+        Expression::Call(c) if macros::is_call(c, "quote") => macros::quote(&c.arguments[0], env),
         Expression::Call(c) => {
             let func = eval_expr(&c.func, env)?;
-            let args = eval_exprs(&c.arguments, env)?;
+            let args = eval_args(&c.arguments, env)?;
 
-            apply_func(func, args)
+            apply_func(&func, args).map_err(|e| exception::locate(e, c.line))
+        }
+        Expression::Method(m) => {
+            let object = eval_expr(&m.object, env)?;
+            let args = eval_args(&m.arguments, env)?;
+
+            eval_method(object, &m.method, args).map_err(|e| exception::locate(e, m.line))
         }
         Expression::Array(a) => eval_arr(a, env),
         Expression::Index(i) => {
             let left = eval_expr(&i.left, env)?;
             let index = eval_expr(&i.index, env)?;
 
-            eval_index(left, index)
+            eval_index(left, index).map_err(|e| exception::locate(e, i.line))
+        }
+        Expression::Slice(s) => {
+            let left = eval_expr(&s.left, env)?;
+            let start = match &s.start {
+                Some(e) => eval_expr(e, env)?,
+                None => Rc::new(Object::Null),
+            };
+            let end = match &s.end {
+                Some(e) => eval_expr(e, env)?,
+                None => Rc::new(Object::Null),
+            };
+
+            left.slice(&start, &end).map(Rc::new)
+        }
+        Expression::Template(t) => {
+            let mut res = String::new();
+            for part in &t.parts {
+                match part {
+                    TemplateExprPart::Str(s) => res.push_str(s),
+                    TemplateExprPart::Expr(e) => res.push_str(&eval_expr(e, env)?.to_string()),
+                }
+            }
+            Ok(Rc::new(Object::String(res)))
+        }
+        Expression::Range(r) => {
+            let start = eval_expr(&r.start, env)?;
+            let end = eval_expr(&r.end, env)?;
+
+            Ok(Rc::new(Object::Range(RangeObj::new(
+                &start,
+                &end,
+                r.inclusive,
+            )?)))
         }
         Expression::Hash(h) => eval_hash(h, env),
+        Expression::Match(m) => eval_match(m, env),
+        Expression::Try(t) => eval_try(t, env),
+        Expression::For(f) => eval_for(f, env),
+        Expression::Spread(_) => Err("spread is only allowed in call arguments".into()),
     }
 }
 
@@ -93,6 +228,8 @@ fn eval_ident(ident: &Ident, env: &Rc<RefCell<Environment>>) -> EvalResult {
         Ok(r)
     } else if let Some(b) = Builtin::from_ident_obj(ident) {
         Ok(b)
+    } else if let Some(c) = Builtin::constant(ident) {
+        Ok(Rc::new(c))
     } else {
         Err(format!("identifier not found: {}", ident))
     }
@@ -108,33 +245,32 @@ fn eval_arr(a: &ArrayExpr, env: &Rc<RefCell<Environment>>) -> EvalResult {
 }
 
 fn eval_hash(h: &HashExpr, env: &Rc<RefCell<Environment>>) -> EvalResult {
-    let map = h
-        .pairs
-        .iter()
-        .map(|(k, v)| (eval_expr(k, env), eval_expr(v, env)))
-        .map(|(r1, r2)| r1.map(|r1| r2.map(|r2| (r1, r2))))
-        .collect::<Result<Result<HashMap<_, _>, _>, _>>()??;
+    let mut hash = HashObj::default();
+    for (k, v) in &h.pairs {
+        let key = eval_expr(k, env)?;
+        if !key.is_hashable() {
+            return Err(format!("unusable as hash key: {}", key.kind()));
+        }
+        hash.insert(key, eval_expr(v, env)?);
+    }
 
-    Ok(Rc::new(Object::Hash(HashObj { map })))
+    Ok(Rc::new(Object::Hash(hash)))
 }
 
 fn eval_index(left: Rc<Object>, index: Rc<Object>) -> EvalResult {
     match (&*left, &*index) {
-        (Object::Array(left), Object::Integer(index)) => Ok(left
-            .elements
-            .get(*index as usize)
-            .cloned()
-            .unwrap_or(Rc::new(Object::Null))),
+        (Object::Array(left), Object::Integer(index)) => {
+            Ok(left.get(*index).cloned().unwrap_or(Rc::new(Object::Null)))
+        }
+        (Object::Range(left), Object::Integer(index)) => Ok(Rc::new(
+            left.get(*index).map_or(Object::Null, Object::Integer),
+        )),
+        (Object::String(left), Object::Integer(index)) => Ok(Rc::new(
+            char_at(left, *index).map_or(Object::Null, Object::String),
+        )),
         (Object::Hash(left), _) => {
-            if matches!(
-                *index,
-                Object::Integer(_) | Object::String(_) | Object::Bool(_)
-            ) {
-                Ok(left
-                    .map
-                    .get(&index)
-                    .cloned()
-                    .unwrap_or(Rc::new(Object::Null)))
+            if index.is_hashable() {
+                Ok(left.get(&index).cloned().unwrap_or(Rc::new(Object::Null)))
             } else {
                 Err(format!("unusable as hash key: {}", index.kind()))
             }
@@ -143,14 +279,204 @@ fn eval_index(left: Rc<Object>, index: Rc<Object>) -> EvalResult {
     }
 }
 
-fn eval_exprs(
-    expr: &[Expression],
+/// Calls a function stored in a hash under `name`, or a builtin method of the receiver
+fn eval_method(object: Rc<Object>, name: &str, mut args: Vec<Rc<Object>>) -> EvalResult {
+    if let Object::Hash(h) = &*object {
+        if let Some(f) = h.get(&Object::String(name.into())) {
+            return apply_func(f, args);
+        }
+    }
+
+    match Builtin::method(&object, name) {
+        Some(b) => {
+            args.insert(0, object);
+            apply_func(&Object::Builtin(b), args)
+        }
+        None => Err(format!("unknown method {} for {}", name, object.kind())),
+    }
+}
+
+fn eval_match(m: &MatchExpr, env: &Rc<RefCell<Environment>>) -> EvalResult {
+    let value = eval_expr(&m.value, env)?;
+
+    for arm in &m.arms {
+        let mut bindings = vec![];
+        if !match_pattern(&arm.pattern, &value, &mut bindings, env)? {
+            continue;
+        }
+
+        let arm_env = Rc::new(RefCell::new(Environment::new_enclosed(env.clone())));
+        for (ident, val) in bindings {
+            arm_env.borrow_mut().set(&ident, val);
+        }
+
+        if let Some(guard) = &arm.guard {
+            if !eval_expr(guard, &arm_env)?.is_truthy() {
+                continue;
+            }
+        }
+        return eval_expr(&arm.body, &arm_env);
+    }
+
+    Ok(Rc::new(Object::Null))
+}
+
+/// Runs the body for each value, stopping at the first `return`
+fn eval_for(f: &ForExpr, env: &Rc<RefCell<Environment>>) -> EvalResult {
+    let items = eval_expr(&f.iterable, env)?.iterable()?;
+
+    let mut index = 0;
+    while let Some(item) = items.iter_item(index) {
+        let res = eval_scoped_block(&f.body, &f.ident, item, env)?;
+        if matches!(*res, Object::Return(_)) {
+            return Ok(res);
+        }
+        index += 1;
+    }
+    Ok(Rc::new(Object::Null))
+}
+
+/// Runs `block` with `name` bound in an environment of its own, which
+/// closures keep, while the other names the block binds go to `env`
+fn eval_scoped_block(
+    block: &[Statement],
+    name: &Ident,
+    value: Rc<Object>,
+    env: &Rc<RefCell<Environment>>,
+) -> EvalResult {
+    let block_env = Rc::new(RefCell::new(Environment::new_enclosed(env.clone())));
+    block_env.borrow_mut().set(name, value);
+    let res = eval_block(block, &block_env);
+
+    for (n, v) in block_env.borrow().bindings() {
+        if n != name {
+            env.borrow_mut().set(n, v.clone());
+        }
+    }
+    res
+}
+
+/// Runs `finally` however the body and `catch` end, and lets a `return` in it win
+fn eval_try(t: &TryExpr, env: &Rc<RefCell<Environment>>) -> EvalResult {
+    let mut res = eval_block(&t.body, env);
+    if let (Err(err), Some(catch)) = (&res, &t.catch) {
+        let error = exception::catch(err.clone(), t.line);
+        res = eval_scoped_block(&catch.body, &catch.ident, error, env);
+    }
+
+    if let Some(finally) = &t.finally {
+        let suspended = res.as_ref().err().map(|e| exception::suspend(e.clone()));
+        let done = eval_block(finally, env)?;
+        if matches!(*done, Object::Return(_)) {
+            return Ok(done);
+        }
+        if let Some(suspended) = suspended {
+            return Err(exception::resume(&suspended));
+        }
+    }
+    res
+}
+
+/// Checks `value` against `pattern`, collecting the identifiers it binds
+fn match_pattern(
+    pattern: &Pattern,
+    value: &Rc<Object>,
+    bindings: &mut Vec<(Ident, Rc<Object>)>,
+    env: &Rc<RefCell<Environment>>,
+) -> Result<bool, String> {
+    match pattern {
+        Pattern::Wildcard => Ok(true),
+        Pattern::Ident(i) => {
+            bindings.push((i.clone(), value.clone()));
+            Ok(true)
+        }
+        Pattern::Literal(l) => Ok(eval_expr(l, env)? == *value),
+        Pattern::Array(a) => {
+            let arr = match &**value {
+                Object::Array(arr) => arr,
+                _ => return Ok(false),
+            };
+
+            let len = a.elements.len();
+            let len_matches = match a.rest {
+                Some(_) => arr.elements.len() >= len,
+                None => arr.elements.len() == len,
+            };
+            if !len_matches {
+                return Ok(false);
+            }
+
+            for (p, v) in a.elements.iter().zip(&arr.elements) {
+                if !match_pattern(p, v, bindings, env)? {
+                    return Ok(false);
+                }
+            }
+
+            match &a.rest {
+                Some(rest) => {
+                    let elements = arr.elements[len..].to_vec();
+                    let rest_val = Rc::new(Object::Array(ArrayObj { elements }));
+                    match_pattern(rest, &rest_val, bindings, env)
+                }
+                None => Ok(true),
+            }
+        }
+        Pattern::Hash(h) => {
+            let hash = match &**value {
+                Object::Hash(hash) => hash,
+                _ => return Ok(false),
+            };
+
+            for (k, p) in &h.pairs {
+                let key = eval_expr(k, env)?;
+                match hash.get(&key) {
+                    Some(v) if match_pattern(p, v, bindings, env)? => {}
+                    _ => return Ok(false),
+                }
+            }
+            Ok(true)
+        }
+    }
+}
+
+/// Evaluates call arguments, expanding `...arr` into its elements
+fn eval_args(
+    args: &[Expression],
     env: &Rc<RefCell<Environment>>,
 ) -> Result<Vec<Rc<Object>>, String> {
-    expr.iter().map(|e| eval_expr(e, env)).collect()
+    let mut res = vec![];
+    for arg in args {
+        match arg {
+            Expression::Spread(e) => {
+                let val = eval_expr(e, env)?;
+                match &*val {
+                    Object::Array(a) => res.extend(a.elements.iter().cloned()),
+                    _ => return Err(format!("spread argument must be ARRAY, got {}", val.kind())),
+                }
+            }
+            e => res.push(eval_expr(e, env)?),
+        }
+    }
+    Ok(res)
+}
+
+/// Defines every function declared in `block` before any of its statements
+/// run, so they can be called regardless of order
+fn hoist_funcs(block: &[Statement], env: &Rc<RefCell<Environment>>) {
+    for stmt in block {
+        if let Statement::Func(f) = stmt {
+            let func = Rc::new(Object::Func(FuncObj {
+                expr: f.func.clone(),
+                env: env.clone(),
+            }));
+            env.borrow_mut().set(&f.name, func);
+        }
+    }
 }
 
 fn eval_block(block: &[Statement], env: &Rc<RefCell<Environment>>) -> EvalResult {
+    hoist_funcs(block, env);
+
     let mut res = Rc::new(Object::Null);
     for stmt in block {
         res = eval_stmt(stmt, env)?;
@@ -175,6 +501,9 @@ fn eval_infix(left: Rc<Object>, op: TokenType, right: Rc<Object>) -> EvalResult
         (&Object::Integer(left), _, &Object::Integer(right)) => {
             eval_integer_infix_op(left, op, right)
         }
+        (left, _, right) if Object::is_float_op(left, right) => {
+            eval_float_infix_op(left.as_float().unwrap(), op, right.as_float().unwrap())
+        }
         (Object::String(left), _, Object::String(right)) => eval_string_infix_op(left, op, right),
         (left, TokenType::Eq, right) => Ok(Rc::new(Object::Bool(left == right))),
         (left, TokenType::NotEq, right) => Ok(Rc::new(Object::Bool(left != right))),
@@ -199,23 +528,47 @@ fn eval_bang_op(value: Rc<Object>) -> EvalResult {
 
 fn eval_minus_op(value: Rc<Object>) -> EvalResult {
     match *value {
-        Object::Integer(x) => Ok(Rc::new(Object::Integer(-x))),
+        Object::Integer(x) => integer(x.checked_neg()),
+        Object::Float(x) => Ok(Rc::new(Object::Float(-x))),
         _ => Err(format!("unknown operator: -{}", value.kind())),
     }
 }
 
 fn eval_integer_infix_op(left: i64, op: TokenType, right: i64) -> EvalResult {
     match op {
-        TokenType::Plus => Ok(Rc::new(Object::Integer(left + right))),
-        TokenType::Minus => Ok(Rc::new(Object::Integer(left - right))),
-        TokenType::Star => Ok(Rc::new(Object::Integer(left * right))),
-        TokenType::Slash => Ok(Rc::new(Object::Integer(left / right))),
+        TokenType::Plus => integer(left.checked_add(right)),
+        TokenType::Minus => integer(left.checked_sub(right)),
+        TokenType::Star => integer(left.checked_mul(right)),
+        TokenType::Slash if right == 0 => Err("division by zero".into()),
+        TokenType::Slash => integer(left.checked_div(right)),
 
         TokenType::Lt => Ok(Rc::new(Object::Bool(left < right))),
         TokenType::Gt => Ok(Rc::new(Object::Bool(left > right))),
         TokenType::Eq => Ok(Rc::new(Object::Bool(left == right))),
         TokenType::NotEq => Ok(Rc::new(Object::Bool(left != right))),
-        _ => unreachable!(),
+        _ => Err(format!("unknown operator: INTEGER {} INTEGER", op)),
+    }
+}
+
+/// Result of a checked integer operation, `None` meaning it overflowed
+fn integer(value: Option<i64>) -> EvalResult {
+    value
+        .map(|v| Rc::new(Object::Integer(v)))
+        .ok_or_else(|| "integer overflow".into())
+}
+
+fn eval_float_infix_op(left: f64, op: TokenType, right: f64) -> EvalResult {
+    match op {
+        TokenType::Plus => Ok(Rc::new(Object::Float(left + right))),
+        TokenType::Minus => Ok(Rc::new(Object::Float(left - right))),
+        TokenType::Star => Ok(Rc::new(Object::Float(left * right))),
+        TokenType::Slash => Ok(Rc::new(Object::Float(left / right))),
+
+        TokenType::Lt => Ok(Rc::new(Object::Bool(left < right))),
+        TokenType::Gt => Ok(Rc::new(Object::Bool(left > right))),
+        TokenType::Eq => Ok(Rc::new(Object::Bool(left == right))),
+        TokenType::NotEq => Ok(Rc::new(Object::Bool(left != right))),
+        _ => Err(format!("unknown operator: FLOAT {} FLOAT", op)),
     }
 }
 
@@ -230,27 +583,46 @@ fn eval_string_infix_op(left: &str, op: TokenType, right: &str) -> EvalResult {
     }
 }
 
-fn apply_func(func: Rc<Object>, args: Vec<Rc<Object>>) -> EvalResult {
-    let func = match &*func {
+fn apply_func(func: &Object, args: Vec<Rc<Object>>) -> EvalResult {
+    let func = match func {
         Object::Func(f) => f,
         Object::Builtin(b) => {
             let args: Vec<_> = args.iter().map(|x| &**x).collect();
-            return b.call(args);
+            return b.call(args, &mut Evaluator);
         }
         _ => return Err(format!("not a function: {}", func.kind())),
     };
 
     let env = Rc::new(RefCell::new(Environment::new_enclosed(func.env.clone())));
-    if args.len() != func.expr.params.len() {
+    let params = &func.expr.params;
+    let required = func.expr.required();
+    if args.len() < required || (func.expr.rest.is_none() && args.len() > params.len()) {
+        let expected = match (&func.expr.rest, required == params.len()) {
+            (Some(_), _) => format!("at least {}", required),
+            (None, true) => required.to_string(),
+            (None, false) => format!("{} to {}", required, params.len()),
+        };
         return Err(format!(
             "function expects {} arguments but {} were given",
-            func.expr.params.len(),
+            expected,
             args.len()
         ));
     }
 
-    for (arg, param) in args.iter().zip(func.expr.params.iter()) {
-        env.borrow_mut().set(param, arg.clone())
+    let mut args = args.into_iter();
+    for (idx, param) in params.iter().enumerate() {
+        // Defaults are evaluated in the call's environment so they can refer
+        // to earlier params
+        let val = match args.next() {
+            Some(arg) => arg,
+            None => eval_expr(&func.expr.defaults[idx - required], &env)?,
+        };
+        env.borrow_mut().set(param, val)
+    }
+    if let Some(rest) = &func.expr.rest {
+        let elements = args.collect();
+        env.borrow_mut()
+            .set(rest, Rc::new(Object::Array(ArrayObj { elements })));
     }
     let res = eval_block(&func.expr.body, &env)?;
 
@@ -260,6 +632,15 @@ fn apply_func(func: Rc<Object>, args: Vec<Rc<Object>>) -> EvalResult {
     }
 }
 
+/// Lets builtins call back into evaluated functions
+struct Evaluator;
+
+impl Caller for Evaluator {
+    fn call_func(&mut self, func: &Object, args: Vec<Rc<Object>>) -> EvalResult {
+        apply_func(func, args)
+    }
+}
+
 type EvalResult = Result<Rc<Object>, String>;
 
 #[cfg(test)]
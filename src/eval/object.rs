@@ -1,19 +1,29 @@
 use super::Environment;
-use crate::{ast::FuncExpr, builtin::Builtin, compiler::Bytes};
-use std::{cell::RefCell, collections::HashMap, fmt::Display, hash::Hash, rc::Rc};
+use crate::{
+    ast::{Expression, FuncExpr},
+    builtin::Builtin,
+    compiler::Bytes,
+};
+use std::{cell::RefCell, cmp::Ordering, collections::HashMap, fmt::Display, rc::Rc};
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Object {
     Integer(i64),
+    Float(f64),
     Bool(bool),
     String(String),
 
     Return(Rc<Object>),
     Func(FuncObj),
     CompiledFunc(Rc<CompiledFuncObj>),
+    Closure(Rc<ClosureObj>),
     Builtin(Builtin),
     Array(ArrayObj),
     Hash(HashObj),
+    Range(RangeObj),
+    /// Unevaluated expression produced by `quote`
+    Quote(Expression),
+    Macro(FuncObj),
 
     Null,
 }
@@ -23,6 +33,7 @@ impl Object {
         match self {
             Object::Integer(0) => false,
             Object::Integer(_) => true,
+            Object::Float(x) => *x != 0.0,
             Object::Bool(b) => *b,
             Object::Null => false,
             Object::Return(o) => o.is_truthy(),
@@ -30,51 +41,203 @@ impl Object {
         }
     }
 
+    /// Whether the object can be used as a hash key
+    pub fn is_hashable(&self) -> bool {
+        self.hash_key().is_some()
+    }
+
+    /// Key of the object in a hash, `None` if it cannot be used as one
+    pub fn hash_key(&self) -> Option<HashKey> {
+        match self {
+            Object::Integer(x) => Some(HashKey::Integer(*x)),
+            Object::String(s) => Some(HashKey::String(s.clone())),
+            Object::Bool(b) => Some(HashKey::Bool(*b)),
+            _ => None,
+        }
+    }
+
+    /// Numeric value of integers and floats, used when the two are mixed
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Object::Integer(x) => Some(*x as f64),
+            Object::Float(x) => Some(*x),
+            _ => None,
+        }
+    }
+
+    /// Whether the operands of a binary operator are numbers, at least one being a float
+    pub fn is_float_op(left: &Object, right: &Object) -> bool {
+        matches!(
+            (left, right),
+            (Object::Float(_), Object::Float(_) | Object::Integer(_))
+                | (Object::Integer(_), Object::Float(_))
+        )
+    }
+
+    /// Natural ordering of numbers and of strings
+    pub fn compare(&self, other: &Object) -> Result<Ordering, String> {
+        let ord = match (self, other) {
+            (Object::Integer(a), Object::Integer(b)) => Some(a.cmp(b)),
+            (Object::String(a), Object::String(b)) => Some(a.cmp(b)),
+            (a, b) if Object::is_float_op(a, b) => {
+                a.as_float().unwrap().partial_cmp(&b.as_float().unwrap())
+            }
+            _ => None,
+        };
+        ord.ok_or(format!(
+            "cannot compare {} and {}",
+            self.kind(),
+            other.kind()
+        ))
+    }
+
+    /// Source-like representation, with strings quoted and escaped
+    pub fn repr(&self) -> String {
+        match self {
+            Object::String(s) => format!("{:?}", s),
+            Object::Array(a) => {
+                let elements: Vec<_> = a.elements.iter().map(|e| e.repr()).collect();
+                format!("[{}]", elements.join(", "))
+            }
+            Object::Hash(h) => {
+                let pairs: Vec<_> = h
+                    .iter()
+                    .map(|(k, v)| format!("{}: {}", k.repr(), v.repr()))
+                    .collect();
+                format!("{{{}}}", pairs.join(", "))
+            }
+            o => o.to_string(),
+        }
+    }
+
     pub fn kind(&self) -> &'static str {
         match self {
             Object::Integer(_) => "INTEGER",
+            Object::Float(_) => "FLOAT",
             Object::Bool(_) => "BOOL",
             Object::String(_) => "STRING",
             Object::Null => "NULL",
             Object::Return(_) => "RETURN",
             Object::Func(_) => "FUNCTION",
             Object::CompiledFunc(_) => "COMPILED FUNCTION",
+            Object::Closure(_) => "CLOSURE",
             Object::Builtin(_) => "BUILTIN",
             Object::Array(_) => "ARRAY",
             Object::Hash(_) => "HASH",
+            Object::Range(_) => "RANGE",
+            Object::Quote(_) => "QUOTE",
+            Object::Macro(_) => "MACRO",
+        }
+    }
+
+    /// Array or range of the values `for-in` visits, which are the characters
+    /// of a string and the keys of a hash
+    pub fn iterable(&self) -> Result<Object, String> {
+        match self {
+            Object::Array(_) | Object::Range(_) => Ok(self.clone()),
+            Object::String(s) => Ok(Object::Array(ArrayObj {
+                elements: s
+                    .chars()
+                    .map(|c| Rc::new(Object::String(c.into())))
+                    .collect(),
+            })),
+            Object::Hash(h) => Ok(Object::Array(ArrayObj {
+                elements: h.iter().map(|(k, _)| k.clone()).collect(),
+            })),
+            _ => Err(format!("cannot iterate over {}", self.kind())),
+        }
+    }
+
+    /// Value at `index` of an array or range returned by `iterable`
+    pub fn iter_item(&self, index: usize) -> Option<Rc<Object>> {
+        match self {
+            Object::Array(a) => a.elements.get(index).cloned(),
+            Object::Range(r) => {
+                (index < r.len() as usize).then(|| Rc::new(Object::Integer(r.start + index as i64)))
+            }
+            _ => None,
         }
     }
-}
 
-impl Hash for Object {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+    /// `self[start:end]` on arrays and strings, where a `Null` bound is open
+    pub fn slice(&self, start: &Object, end: &Object) -> Result<Object, String> {
+        let bound = |o: &Object| match o {
+            Object::Integer(i) => Ok(Some(*i)),
+            Object::Null => Ok(None),
+            o => Err(format!("slice index must be INTEGER, got {}", o.kind())),
+        };
+        let (start, end) = (bound(start)?, bound(end)?);
+
         match self {
-            Object::Integer(v) => v.hash(state),
-            Object::String(v) => v.hash(state),
-            Object::Bool(v) => v.hash(state),
-            _ => panic!("Cannot hash object of type {}", self.kind()),
+            Object::Array(a) => {
+                let (start, end) = slice_bounds(start, end, a.elements.len());
+                Ok(Object::Array(ArrayObj {
+                    elements: a.elements[start..end].to_vec(),
+                }))
+            }
+            Object::String(s) => {
+                let (start, end) = slice_bounds(start, end, s.chars().count());
+                Ok(Object::String(
+                    s.chars().skip(start).take(end - start).collect(),
+                ))
+            }
+            Object::Range(r) => {
+                let (start, end) = slice_bounds(start, end, r.len() as usize);
+                Ok(Object::Range(RangeObj {
+                    start: r.start + start as i64,
+                    end: r.start + end as i64,
+                }))
+            }
+            _ => Err(format!("slice operator not supported: {}", self.kind())),
         }
     }
 }
 
+/// Resolves an index counted from the end when negative, `None` if out of bounds
+pub fn resolve_index(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { index + len as i64 } else { index };
+    (0..len as i64).contains(&index).then_some(index as usize)
+}
+
+/// Character of a string at a possibly negative index
+pub fn char_at(s: &str, index: i64) -> Option<String> {
+    resolve_index(index, s.chars().count()).and_then(|i| s.chars().nth(i).map(String::from))
+}
+
+/// Clamps the bounds of a slice to `0..=len`, with `start <= end`
+fn slice_bounds(start: Option<i64>, end: Option<i64>, len: usize) -> (usize, usize) {
+    let clamp = |i: i64| {
+        let i = if i < 0 { i + len as i64 } else { i };
+        i.clamp(0, len as i64) as usize
+    };
+    let start = start.map_or(0, clamp);
+    let end = end.map_or(len, clamp);
+    (start, end.max(start))
+}
+
 impl Display for Object {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Object::Integer(x) => write!(f, "{}", x),
+            Object::Float(x) => write!(f, "{:?}", x),
             Object::Bool(x) => write!(f, "{}", x),
             Object::String(s) => write!(f, "{}", s),
             Object::Null => write!(f, "null"),
             Object::Return(o) => write!(f, "{}", o),
             Object::Func(o) => write!(f, "{}", o),
             Object::CompiledFunc(o) => write!(f, "{}", o),
+            Object::Closure(o) => write!(f, "{}", o),
             Object::Builtin(_) => write!(f, "builtin"),
             Object::Array(a) => write!(f, "{}", a),
             Object::Hash(h) => write!(f, "{}", h),
+            Object::Range(r) => write!(f, "{}", r),
+            Object::Quote(e) => write!(f, "QUOTE({})", e),
+            Object::Macro(m) => write!(f, "{}", Expression::Macro(m.expr.clone())),
         }
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct FuncObj {
     pub expr: FuncExpr,
     pub env: Rc<RefCell<Environment>>,
@@ -86,34 +249,82 @@ impl Display for FuncObj {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, Clone)]
 pub struct CompiledFuncObj {
     pub instructions: Bytes,
+    /// Source line of the instructions that can fail, by position
+    pub lines: Vec<(usize, usize)>,
     pub locals: usize,
     pub params: usize,
+    /// Params without a default value
+    pub required: usize,
+    /// Whether extra arguments are collected into an array after `params`
+    pub rest: bool,
 }
 
 impl CompiledFuncObj {
     pub fn new(instructions: Bytes, locals: usize, params: usize) -> Self {
         Self {
             instructions,
+            lines: vec![],
             locals,
             params,
+            required: params,
+            rest: false,
         }
     }
+
+    /// Source line of the instruction at `ip`, if it can fail
+    pub fn line_at(&self, ip: usize) -> Option<usize> {
+        let idx = self.lines.binary_search_by_key(&ip, |(pos, _)| *pos).ok()?;
+        Some(self.lines[idx].1)
+    }
 }
 
+/// Lines are left out, they only locate errors
+impl PartialEq for CompiledFuncObj {
+    fn eq(&self, other: &Self) -> bool {
+        self.instructions == other.instructions
+            && self.locals == other.locals
+            && self.params == other.params
+            && self.required == other.required
+            && self.rest == other.rest
+    }
+}
+
+impl Eq for CompiledFuncObj {}
+
 impl Display for CompiledFuncObj {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, " {} {} locals", self.instructions, self.locals)
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq)]
+pub struct ClosureObj {
+    pub func: Rc<CompiledFuncObj>,
+    /// Captured values. Mutable so that function declarations in the same
+    /// block can capture each other
+    pub free: RefCell<Vec<Object>>,
+}
+
+impl Display for ClosureObj {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "closure{}", self.func)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub struct ArrayObj {
     pub elements: Vec<Rc<Object>>,
 }
 
+impl ArrayObj {
+    pub fn get(&self, index: i64) -> Option<&Rc<Object>> {
+        resolve_index(index, self.elements.len()).map(|i| &self.elements[i])
+    }
+}
+
 impl Display for ArrayObj {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "[")?;
@@ -128,16 +339,154 @@ impl Display for ArrayObj {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// Longest range `RangeObj::collect` turns into elements
+const MAX_COLLECTED: i64 = 1 << 24;
+
+/// Lazy range of integers, `end` is exclusive
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct RangeObj {
+    pub start: i64,
+    pub end: i64,
+}
+
+impl RangeObj {
+    /// Fails when the length of the range does not fit in an integer
+    pub fn new(start: &Object, end: &Object, inclusive: bool) -> Result<Self, String> {
+        match (start, end) {
+            (&Object::Integer(start), &Object::Integer(end)) => {
+                let op = if inclusive { "..=" } else { ".." };
+                match end.checked_add(inclusive as i64) {
+                    Some(end) if end.checked_sub(start).is_some() => Ok(Self { start, end }),
+                    _ => Err(format!("range too large: {}{}{}", start, op, end)),
+                }
+            }
+            _ => Err(format!(
+                "range bounds must be INTEGER, got {}..{}",
+                start.kind(),
+                end.kind()
+            )),
+        }
+    }
+
+    /// Cannot overflow, since `new` rejects ranges too large to measure
+    pub fn len(&self) -> i64 {
+        (self.end - self.start).max(0)
+    }
+
+    /// Integers of the range, failing instead of aborting when there are too many to hold
+    pub fn collect(&self) -> Result<Vec<Rc<Object>>, String> {
+        if self.len() > MAX_COLLECTED {
+            return Err(format!(
+                "range too large to collect: {}..{}",
+                self.start, self.end
+            ));
+        }
+        Ok((self.start..self.end)
+            .map(|i| Rc::new(Object::Integer(i)))
+            .collect())
+    }
+
+    pub fn get(&self, index: i64) -> Option<i64> {
+        resolve_index(index, self.len() as usize).map(|i| self.start + i as i64)
+    }
+
+    pub fn contains(&self, x: i64) -> bool {
+        (self.start..self.end).contains(&x)
+    }
+}
+
+impl Display for RangeObj {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+/// Hash map keeping its keys in the order they were first inserted
+#[derive(Debug, Clone, Default)]
 pub struct HashObj {
-    pub map: HashMap<Rc<Object>, Rc<Object>>,
+    pairs: Vec<(Rc<Object>, Rc<Object>)>,
+    /// Position of each key in `pairs`
+    index: HashMap<HashKey, usize>,
+}
+
+/// Value of an object usable as a hash key
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum HashKey {
+    Integer(i64),
+    String(String),
+    Bool(bool),
+}
+
+impl HashObj {
+    /// Value of a key, `None` for keys that cannot be hashed
+    pub fn get(&self, key: &Object) -> Option<&Rc<Object>> {
+        let i = self.index.get(&key.hash_key()?)?;
+        Some(&self.pairs[*i].1)
+    }
+
+    pub fn contains_key(&self, key: &Object) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Replaces the value of an existing key in place, or appends a new pair.
+    /// Keys that cannot be hashed are ignored, callers check them first
+    pub fn insert(&mut self, key: Rc<Object>, value: Rc<Object>) {
+        let Some(hash_key) = key.hash_key() else {
+            return;
+        };
+        match self.index.get(&hash_key) {
+            Some(&i) => self.pairs[i].1 = value,
+            None => {
+                self.index.insert(hash_key, self.pairs.len());
+                self.pairs.push((key, value));
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &Object) -> Option<Rc<Object>> {
+        let i = self.index.remove(&key.hash_key()?)?;
+        let (_, value) = self.pairs.remove(i);
+        for (k, _) in &self.pairs[i..] {
+            *self.index.get_mut(&k.hash_key().unwrap()).unwrap() -= 1;
+        }
+        Some(value)
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, (Rc<Object>, Rc<Object>)> {
+        self.pairs.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+}
+
+impl FromIterator<(Rc<Object>, Rc<Object>)> for HashObj {
+    fn from_iter<T: IntoIterator<Item = (Rc<Object>, Rc<Object>)>>(iter: T) -> Self {
+        let mut hash = Self::default();
+        for (k, v) in iter {
+            hash.insert(k, v);
+        }
+        hash
+    }
+}
+
+/// Hashes with the same pairs are equal whatever their order
+impl PartialEq for HashObj {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().all(|(k, v)| other.get(k) == Some(v))
+    }
 }
 
 impl Display for HashObj {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{{")?;
-        for (idx, (k, v)) in self.map.iter().enumerate() {
-            if idx != self.map.len() - 1 {
+        for (idx, (k, v)) in self.iter().enumerate() {
+            if idx != self.len() - 1 {
                 write!(f, "{}: {}, ", k, v)?;
             } else {
                 write!(f, "{}: {}", k, v)?;
@@ -1,7 +1,11 @@
-use std::collections::HashMap;
-
 use super::*;
-use crate::{ast::Parser, lexer::Lexer};
+use crate::{
+    ast::Parser,
+    lexer::Lexer,
+    module::{load_imports, test::write_files},
+    policy::{self, Policy},
+    script,
+};
 
 macro_rules! test {
     ($($case:expr),* $(,)?) => {
@@ -176,6 +180,14 @@ fn error_handling() {
         (
             r#"{"name": "Monkey"}[fn(x) { x }];"#,
             Err("unusable as hash key: FUNCTION".into()),
+        ),
+        (
+            r#"{float(1): 1}"#,
+            Err("unusable as hash key: FLOAT".into()),
+        ),
+        (
+            r#"{1: 1}[float(1)]"#,
+            Err("unusable as hash key: FLOAT".into()),
         )
     )
 }
@@ -193,6 +205,40 @@ fn eval_let() {
     )
 }
 
+#[test]
+fn eval_destructure() {
+    test!(
+        (
+            "let [a, b, ..rest] = [1, 2, 3, 4]; a + b + len(rest)",
+            Ok(Rc::new(Object::Integer(5)))
+        ),
+        (
+            "let [a, [b, _]] = [1, [2, 3]]; a + b",
+            Ok(Rc::new(Object::Integer(3)))
+        ),
+        (
+            r#"let {name, "age": years} = {"name": "Monkey", "age": 3}; name"#,
+            Ok(Rc::new(Object::String("Monkey".into())))
+        ),
+        (
+            r#"let f = fn(p) { let {x, y} = p; x * y }; f({"x": 2, "y": 5})"#,
+            Ok(Rc::new(Object::Integer(10)))
+        ),
+        (
+            "let [a, b] = [1];",
+            Err("cannot destructure ARRAY with pattern [a, b]".into())
+        ),
+        (
+            "let [a] = 1;",
+            Err("cannot destructure INTEGER with pattern [a]".into())
+        ),
+        (
+            r#"let {name} = {"age": 3};"#,
+            Err("cannot destructure HASH with pattern {name: name}".into())
+        ),
+    )
+}
+
 #[test]
 fn eval_func() {
     test!(
@@ -225,233 +271,1290 @@ fn eval_func() {
 }
 
 #[test]
-fn array_literal() {
-    test!((
-        "[1, 2 * 2, 3 + 3]",
-        Ok(Rc::new(Object::Array(ArrayObj {
-            elements: vec![
-                Rc::new(Object::Integer(1)),
-                Rc::new(Object::Integer(4)),
-                Rc::new(Object::Integer(6))
-            ]
-        })))
-    ))
-}
-
-#[test]
-fn index_arr() {
+fn eval_func_decl() {
     test!(
-        ("[1, 2, 3][0]", Ok(Rc::new(Object::Integer(1)))),
-        ("[1, 2, 3][1]", Ok(Rc::new(Object::Integer(2)))),
-        ("[1, 2, 3][2]", Ok(Rc::new(Object::Integer(3)))),
-        ("let i = 0; [1][i];", Ok(Rc::new(Object::Integer(1)))),
-        ("[1, 2, 3][1 + 1];", Ok(Rc::new(Object::Integer(3)))),
         (
-            "let myArray = [1, 2, 3]; myArray[2];",
+            "fn add(x, y) { x + y } add(1, 2)",
             Ok(Rc::new(Object::Integer(3)))
         ),
         (
-            "let myArray = [1, 2, 3]; myArray[0] + myArray[1] + myArray[2];",
-            Ok(Rc::new(Object::Integer(6)))
+            "double(4); fn double(x) { x * 2 }",
+            Ok(Rc::new(Object::Null))
         ),
         (
-            "let myArray = [1, 2, 3]; let i = myArray[0]; myArray[i]",
-            Ok(Rc::new(Object::Integer(2)))
+            "let r = double(4); fn double(x) { x * 2 } r",
+            Ok(Rc::new(Object::Integer(8)))
+        ),
+        (
+            r#"
+            fn is_even(n) { if (n == 0) { true } else { is_odd(n - 1) } }
+            fn is_odd(n) { if (n == 0) { false } else { is_even(n - 1) } }
+            [is_even(10), is_odd(7), is_even(3)]"#,
+            Ok(Rc::new(Object::Array(ArrayObj {
+                elements: vec![
+                    Rc::new(Object::Bool(true)),
+                    Rc::new(Object::Bool(true)),
+                    Rc::new(Object::Bool(false)),
+                ]
+            })))
+        ),
+        (
+            r#"
+            let parity = fn(n) {
+                let res = is_even(n);
+                fn is_odd(n) { if (n == 0) { false } else { is_even(n - 1) } }
+                fn is_even(n) { if (n == 0) { true } else { is_odd(n - 1) } }
+                res
+            };
+            parity(4)"#,
+            Ok(Rc::new(Object::Bool(true)))
         ),
-        ("[1, 2, 3][3]", Ok(Rc::new(Object::Null))),
-        ("[1, 2, 3][-1]", Ok(Rc::new(Object::Null))),
     )
 }
 
 #[test]
-fn hash_literal() {
-    test!((
-        r#"
-    let two = "two";
-    {
-        "one": 10 - 9,
-        two: 1 + 1,
-        "thr" + "ee": 6 / 2,
-        4: 4,
-        true: 5,
-        false: 6
-    }
-    "#,
-        Ok(Rc::new(Object::Hash(HashObj {
-            map: HashMap::from([
-                (
-                    Rc::new(Object::String("one".into())),
-                    Rc::new(Object::Integer(1))
-                ),
-                (
-                    Rc::new(Object::String("two".into())),
-                    Rc::new(Object::Integer(2))
-                ),
-                (
-                    Rc::new(Object::String("three".into())),
-                    Rc::new(Object::Integer(3))
-                ),
-                (Rc::new(Object::Integer(4)), Rc::new(Object::Integer(4))),
-                (Rc::new(Object::Bool(true)), Rc::new(Object::Integer(5))),
-                (Rc::new(Object::Bool(false)), Rc::new(Object::Integer(6))),
-            ])
-        })))
-    ))
-}
-
-#[test]
-fn index_hash() {
+fn eval_methods() {
     test!(
-        (r#"{"foo": 5}["foo"]"#, Ok(Rc::new(Object::Integer(5)))),
-        (r#"{"foo": 5}["bar"]"#, Ok(Rc::new(Object::Null))),
         (
-            r#"let key = "foo"; {"foo": 5}[key]"#,
-            Ok(Rc::new(Object::Integer(5)))
+            r#"let h = {"name": "monkey", "n": 2}; h.name"#,
+            Ok(Rc::new(Object::String("monkey".into())))
+        ),
+        (r#"{"a": 1}.b"#, Ok(Rc::new(Object::Null))),
+        ("[1, 2, 3].len()", Ok(Rc::new(Object::Integer(3)))),
+        (r#""abc".len()"#, Ok(Rc::new(Object::Integer(3)))),
+        (
+            r#"[1, "a"].join(",")"#,
+            Ok(Rc::new(Object::String("1,a".into())))
+        ),
+        (
+            "let a = [1]; a.push(2).rest()",
+            Ok(Rc::new(Object::Array(ArrayObj {
+                elements: vec![Rc::new(Object::Integer(2))]
+            })))
+        ),
+        (
+            "let p = {\"add\": fn(a, b) { a + b }}; p.add(...[1, 2])",
+            Ok(Rc::new(Object::Integer(3)))
+        ),
+        ("5.len()", Err("unknown method len for INTEGER".into())),
+        (
+            r#""abc".push(1)"#,
+            Err("unknown method push for STRING".into())
         ),
-        (r#"{}["foo"]"#, Ok(Rc::new(Object::Null))),
-        (r#"{5: 5}[5]"#, Ok(Rc::new(Object::Integer(5)))),
-        (r#"{true: 5}[true]"#, Ok(Rc::new(Object::Integer(5)))),
-        (r#"{false: 5}[false]"#, Ok(Rc::new(Object::Integer(5)))),
     )
 }
 
 #[test]
-fn builtin_len() {
+fn eval_templates() {
     test!(
-        (r#"len("")"#, Ok(Rc::new(Object::Integer(0)))),
-        (r#"len("four")"#, Ok(Rc::new(Object::Integer(4)))),
-        (r#"len("hello world")"#, Ok(Rc::new(Object::Integer(11)))),
         (
-            r#"len(1)"#,
-            Err("argument to `len` not supported, got INTEGER".into())
+            r#"let name = "monkey"; "Hello, ${name}!""#,
+            Ok(Rc::new(Object::String("Hello, monkey!".into())))
         ),
         (
-            r#"len("one", "two")"#,
-            Err("wrong number of arguments. expected 1, got 2".into())
+            r#"let items = [1, 2]; "${len(items)} items: ${items}""#,
+            Ok(Rc::new(Object::String("2 items: [1, 2]".into())))
         ),
-        (r#"len([1, 2, 3, 4])"#, Ok(Rc::new(Object::Integer(4)))),
+        (
+            r#""${true}${-1}${"nested ${1 + 1}"}""#,
+            Ok(Rc::new(Object::String("true-1nested 2".into())))
+        ),
+        (r#""${x}""#, Err("identifier not found: x".into())),
     )
 }
 
 #[test]
-fn builtin_first() {
-    test!(
+fn quote_unquote() {
+    let cases = [
+        ("quote(5)", "5"),
+        ("quote(foobar + barfoo)", "(foobar + barfoo)"),
+        ("quote(unquote(4 + 4))", "8"),
+        ("quote(8 + unquote(4 + 4))", "(8 + 8)"),
         (
-            r#"first(["a", "b"])"#,
-            Ok(Rc::new(Object::String("a".into())))
+            "let q = quote(4 + 4); quote(unquote(q) + 8)",
+            "((4 + 4) + 8)",
         ),
-        (r#"first([])"#, Ok(Rc::new(Object::Null))),
+        ("quote(unquote(true == false))", "false"),
+        (r#"quote(f(unquote([1, "a"])))"#, "f([1, a])"),
+    ];
+
+    for (inp, exp) in cases {
+        let lexer = Lexer::new(inp.to_string());
+        let mut parser = Parser::new(lexer);
+
+        let prog = parser.parse().expect("Skill issue");
+        let res = eval_program(prog, &Environment::new()).unwrap();
+        match &*res {
+            Object::Quote(e) => assert_eq!(e.to_string(), exp),
+            o => panic!("expected QUOTE, got {:?}", o),
+        }
+    }
+}
+
+#[test]
+fn macro_expansion() {
+    let cases = [
         (
-            r#"first(1)"#,
-            Err("argument to `first` not supported, got INTEGER".into())
+            "let infix = macro() { quote(1 + 2) }; infix()",
+            Ok("(1 + 2)\n".into()),
         ),
         (
-            r#"first("one", "two")"#,
-            Err("wrong number of arguments. expected 1, got 2".into())
+            "let reverse = macro(a, b) { quote(unquote(b) - unquote(a)) }; reverse(2 + 2, 10 - 5)",
+            Ok("((10 - 5) - (2 + 2))\n".into()),
         ),
-    )
+        (
+            "let m = macro() { 1 }; let x = 1; m()",
+            Err("macro m must return a QUOTE, got INTEGER".into()),
+        ),
+    ];
+
+    for (inp, exp) in cases {
+        let lexer = Lexer::new(inp.to_string());
+        let mut parser = Parser::new(lexer);
+
+        let mut prog = parser.parse().expect("Skill issue");
+        let env = Environment::new();
+        define_macros(&mut prog, &env);
+        let res =
+            expand_macros(prog, &env).map(|p| p.statements.last().unwrap().to_string() + "\n");
+        assert_eq!(res, exp);
+    }
 }
 
 #[test]
-fn builtin_last() {
-    test!(
+fn eval_macros() {
+    let input = r#"
+    let unless = macro(cond, cons, alt) {
+        quote(if (!(unquote(cond))) { unquote(cons) } else { unquote(alt) })
+    };
+    let log = [];
+    unless(10 > 5, push(log, "not greater"), push(log, "greater"))"#;
+
+    let lexer = Lexer::new(input.to_string());
+    let mut parser = Parser::new(lexer);
+
+    let mut prog = parser.parse().expect("Skill issue");
+    let env = Environment::new();
+    define_macros(&mut prog, &env);
+    let prog = expand_macros(prog, &env).unwrap();
+
+    let res = eval_program(prog, &Environment::new());
+    assert_eq!(
+        res,
+        Ok(Rc::new(Object::Array(ArrayObj {
+            elements: vec![Rc::new(Object::String("greater".into()))]
+        })))
+    );
+
+    test!((
+        "let m = macro() { 1 }; m",
+        Err("macros can only be defined with a top-level let".into())
+    ))
+}
+
+#[test]
+fn eval_imports() {
+    let dir = write_files(
+        "eval_imports",
+        &[
+            (
+                "math.monkey",
+                "let secret = 2; fn double(x) { x * secret } let inc = fn(x) { x + 1 }; export double, inc;",
+            ),
+            (
+                "lib/quad.monkey",
+                r#"import "../math.monkey" as m; let quad = fn(x) { m.double(m.double(x)) }; export quad;"#,
+            ),
+            (
+                "unless.monkey",
+                r#"let unless = macro(c, a, b) { quote(if (!(unquote(c))) { unquote(a) } else { unquote(b) }) }; let size = fn(x) { unless(x > 1, "small", "big") }; export size;"#,
+            ),
+            ("bad.monkey", "let a = 1; export a, nope;"),
+        ],
+    );
+    let cases = [
         (
-            r#"last(["a", "b"])"#,
-            Ok(Rc::new(Object::String("b".into())))
+            r#"import "lib/quad.monkey" as l; l.quad(3)"#,
+            Ok(Rc::new(Object::Integer(12))),
         ),
-        (r#"last([])"#, Ok(Rc::new(Object::Null))),
         (
-            r#"last(1)"#,
-            Err("argument to `last` not supported, got INTEGER".into())
+            r#"import "math.monkey" as m; [m.inc(1), m.secret]"#,
+            Ok(Rc::new(Object::Array(ArrayObj {
+                elements: vec![Rc::new(Object::Integer(2)), Rc::new(Object::Null)],
+            }))),
         ),
         (
-            r#"last("one", "two")"#,
-            Err("wrong number of arguments. expected 1, got 2".into())
+            r#"import "math.monkey" as m; secret"#,
+            Err("identifier not found: secret".into()),
         ),
-    )
+        (
+            r#"import "unless.monkey" as u; u.size(1) + u.size(2)"#,
+            Ok(Rc::new(Object::String("smallbig".into()))),
+        ),
+        (
+            r#"import "bad.monkey" as b;"#,
+            Err(format!(
+                "{}: exported name nope is not defined",
+                dir.join("bad.monkey").canonicalize().unwrap().display()
+            )),
+        ),
+    ];
+
+    for (inp, exp) in cases {
+        let lexer = Lexer::new(inp.to_string());
+        let mut parser = Parser::new(lexer);
+
+        let mut prog = parser.parse().expect("Skill issue");
+        let modules = load_imports(&mut prog, &dir).unwrap();
+        let env = Environment::new();
+
+        let res = eval_modules(modules, &env).and_then(|_| eval_program(prog, &env));
+        assert_eq!(res, exp);
+    }
 }
 
 #[test]
-fn builtin_rest() {
+fn eval_ranges() {
     test!(
         (
-            r#"rest(["a", "b", "c"])"#,
+            "1..4",
+            Ok(Rc::new(Object::Range(RangeObj { start: 1, end: 4 })))
+        ),
+        ("len(1..=10)", Ok(Rc::new(Object::Integer(10)))),
+        ("len(5..1)", Ok(Rc::new(Object::Integer(0)))),
+        ("let n = 3; (0..n * 2)[-1]", Ok(Rc::new(Object::Integer(5)))),
+        ("(0..3)[3]", Ok(Rc::new(Object::Null))),
+        (
+            "(0..1000000000).contains(999)",
+            Ok(Rc::new(Object::Bool(true)))
+        ),
+        ("contains(1..=3, 4)", Ok(Rc::new(Object::Bool(false)))),
+        ("contains([1, 2], 2)", Ok(Rc::new(Object::Bool(true)))),
+        (
+            "array(1..=3)",
             Ok(Rc::new(Object::Array(ArrayObj {
                 elements: vec![
-                    Rc::new(Object::String("b".into())),
-                    Rc::new(Object::String("c".into()))
+                    Rc::new(Object::Integer(1)),
+                    Rc::new(Object::Integer(2)),
+                    Rc::new(Object::Integer(3)),
                 ]
             })))
         ),
         (
-            r#"rest(["a"])"#,
-            Ok(Rc::new(Object::Array(ArrayObj { elements: vec![] })))
+            "(0..10)[2:-2]",
+            Ok(Rc::new(Object::Range(RangeObj { start: 2, end: 8 })))
         ),
         (
-            r#"rest([])"#,
-            Ok(Rc::new(Object::Array(ArrayObj { elements: vec![] })))
+            "1..true",
+            Err("range bounds must be INTEGER, got INTEGER..BOOL".into())
         ),
         (
-            r#"rest(1)"#,
-            Err("argument to `rest` not supported, got INTEGER".into())
+            "len(0..=9223372036854775807)",
+            Err("range too large: 0..=9223372036854775807".into())
         ),
         (
-            r#"rest("one", "two")"#,
-            Err("wrong number of arguments. expected 1, got 2".into())
+            "len(-9223372036854775807..9223372036854775807)",
+            Err("range too large: -9223372036854775807..9223372036854775807".into())
+        ),
+        (
+            "array(0..10000000000)",
+            Err("range too large to collect: 0..10000000000".into())
+        ),
+        (
+            "json_stringify(0..10000000000)",
+            Err("range too large to collect: 0..10000000000".into())
+        ),
+        (
+            "map(0..10000000000, fn(x) { if (x == 3) { 1 / 0 } })",
+            Err("division by zero".into())
+        ),
+        (
+            "try { reverse(0..10000000000) } catch (e) { 1 }",
+            Ok(Rc::new(Object::Integer(1)))
         ),
     )
 }
 
 #[test]
-fn builtin_push() {
+fn eval_pipe() {
     test!(
         (
-            r#"push(["a", "b"], "c")"#,
-            Ok(Rc::new(Object::Array(ArrayObj {
-                elements: vec![
-                    Rc::new(Object::String("a".into())),
-                    Rc::new(Object::String("b".into())),
-                    Rc::new(Object::String("c".into()))
-                ]
-            })))
+            "let add = fn(a, b) { a + b }; 1 |> add(2) |> add(3)",
+            Ok(Rc::new(Object::Integer(6)))
         ),
+        ("[1, 2] |> push(3) |> len", Ok(Rc::new(Object::Integer(3)))),
         (
-            r#"push(["a"], 1)"#,
-            Ok(Rc::new(Object::Array(ArrayObj {
-                elements: vec![
-                    Rc::new(Object::String("a".into())),
-                    Rc::new(Object::Integer(1))
-                ]
-            })))
+            "[1] |> fn(a) { a.first() }",
+            Ok(Rc::new(Object::Integer(1)))
         ),
+    )
+}
+
+#[test]
+fn eval_func_params() {
+    test!(
         (
-            r#"push(["a"], [1])"#,
-            Ok(Rc::new(Object::Array(ArrayObj {
-                elements: vec![
-                    Rc::new(Object::String("a".into())),
-                    Rc::new(Object::Array(ArrayObj {
-                        elements: vec![Rc::new(Object::Integer(1))]
-                    }))
-                ]
-            })))
+            "let f = fn(x, y = 10) { x + y }; f(1) + f(1, 2)",
+            Ok(Rc::new(Object::Integer(14)))
         ),
         (
-            r#"push([], "bar")"#,
+            "let f = fn(x, y = x * 2) { y }; f(3)",
+            Ok(Rc::new(Object::Integer(6)))
+        ),
+        (
+            "let f = fn(first, ...others) { [first, others] }; f(1, 2, 3)[1]",
             Ok(Rc::new(Object::Array(ArrayObj {
-                elements: vec![Rc::new(Object::String("bar".into()))]
+                elements: vec![Rc::new(Object::Integer(2)), Rc::new(Object::Integer(3))]
             })))
         ),
         (
-            r#"push(1, 2)"#,
-            Err("argument to `push` not supported, got INTEGER".into())
+            "let f = fn(...all) { len(all) }; f()",
+            Ok(Rc::new(Object::Integer(0)))
         ),
         (
-            r#"push([])"#,
+            "let f = fn(a, b, c) { a * b + c }; let args = [2, 3]; f(...args, 4)",
+            Ok(Rc::new(Object::Integer(10)))
+        ),
+        (
+            "let f = fn(a, ...rest) { len(rest) }; f(...[1, 2], 3, ...[4])",
+            Ok(Rc::new(Object::Integer(3)))
+        ),
+        ("len(...[[1, 2]])", Ok(Rc::new(Object::Integer(2)))),
+        (
+            "fn(x, y = 1) { x }()",
+            Err("function expects 1 to 2 arguments but 0 were given".into())
+        ),
+        (
+            "fn(x, ...y) { x }()",
+            Err("function expects at least 1 arguments but 0 were given".into())
+        ),
+        (
+            "fn(x) { x }(...1)",
+            Err("spread argument must be ARRAY, got INTEGER".into())
+        ),
+    )
+}
+
+#[test]
+fn array_literal() {
+    test!((
+        "[1, 2 * 2, 3 + 3]",
+        Ok(Rc::new(Object::Array(ArrayObj {
+            elements: vec![
+                Rc::new(Object::Integer(1)),
+                Rc::new(Object::Integer(4)),
+                Rc::new(Object::Integer(6))
+            ]
+        })))
+    ))
+}
+
+#[test]
+fn index_arr() {
+    test!(
+        ("[1, 2, 3][0]", Ok(Rc::new(Object::Integer(1)))),
+        ("[1, 2, 3][1]", Ok(Rc::new(Object::Integer(2)))),
+        ("[1, 2, 3][2]", Ok(Rc::new(Object::Integer(3)))),
+        ("let i = 0; [1][i];", Ok(Rc::new(Object::Integer(1)))),
+        ("[1, 2, 3][1 + 1];", Ok(Rc::new(Object::Integer(3)))),
+        (
+            "let myArray = [1, 2, 3]; myArray[2];",
+            Ok(Rc::new(Object::Integer(3)))
+        ),
+        (
+            "let myArray = [1, 2, 3]; myArray[0] + myArray[1] + myArray[2];",
+            Ok(Rc::new(Object::Integer(6)))
+        ),
+        (
+            "let myArray = [1, 2, 3]; let i = myArray[0]; myArray[i]",
+            Ok(Rc::new(Object::Integer(2)))
+        ),
+        ("[1, 2, 3][3]", Ok(Rc::new(Object::Null))),
+        ("[1, 2, 3][-1]", Ok(Rc::new(Object::Integer(3)))),
+        ("[1, 2, 3][-3]", Ok(Rc::new(Object::Integer(1)))),
+        ("[1, 2, 3][-4]", Ok(Rc::new(Object::Null))),
+    )
+}
+
+#[test]
+fn index_str() {
+    test!(
+        (r#""abc"[1]"#, Ok(Rc::new(Object::String("b".into())))),
+        (r#""abc"[-1]"#, Ok(Rc::new(Object::String("c".into())))),
+        (r#""abc"[3]"#, Ok(Rc::new(Object::Null))),
+    )
+}
+
+#[test]
+fn slices() {
+    test!(
+        (
+            "[1, 2, 3, 4][1:3]",
+            Ok(Rc::new(Object::Array(ArrayObj {
+                elements: vec![Rc::new(Object::Integer(2)), Rc::new(Object::Integer(3))]
+            })))
+        ),
+        (
+            "let n = 1; [1, 2, 3][:n]",
+            Ok(Rc::new(Object::Array(ArrayObj {
+                elements: vec![Rc::new(Object::Integer(1))]
+            })))
+        ),
+        (
+            "[1, 2, 3][-1:]",
+            Ok(Rc::new(Object::Array(ArrayObj {
+                elements: vec![Rc::new(Object::Integer(3))]
+            })))
+        ),
+        (
+            "[1, 2, 3][2:1]",
+            Ok(Rc::new(Object::Array(ArrayObj { elements: vec![] })))
+        ),
+        (
+            r#""monkey"[2:]"#,
+            Ok(Rc::new(Object::String("nkey".into())))
+        ),
+        (
+            r#""monkey"[:-3]"#,
+            Ok(Rc::new(Object::String("mon".into())))
+        ),
+        (
+            r#""monkey"[:]"#,
+            Ok(Rc::new(Object::String("monkey".into())))
+        ),
+        (
+            r#""abc"[-10:10]"#,
+            Ok(Rc::new(Object::String("abc".into())))
+        ),
+        ("5[1:]", Err("slice operator not supported: INTEGER".into())),
+        (
+            r#"[1][true:]"#,
+            Err("slice index must be INTEGER, got BOOL".into())
+        ),
+    )
+}
+
+#[test]
+fn hash_literal() {
+    test!((
+        r#"
+    let two = "two";
+    {
+        "one": 10 - 9,
+        two: 1 + 1,
+        "thr" + "ee": 6 / 2,
+        4: 4,
+        true: 5,
+        false: 6
+    }
+    "#,
+        Ok(Rc::new(Object::Hash(
+            [
+                (
+                    Rc::new(Object::String("one".into())),
+                    Rc::new(Object::Integer(1))
+                ),
+                (
+                    Rc::new(Object::String("two".into())),
+                    Rc::new(Object::Integer(2))
+                ),
+                (
+                    Rc::new(Object::String("three".into())),
+                    Rc::new(Object::Integer(3))
+                ),
+                (Rc::new(Object::Integer(4)), Rc::new(Object::Integer(4))),
+                (Rc::new(Object::Bool(true)), Rc::new(Object::Integer(5))),
+                (Rc::new(Object::Bool(false)), Rc::new(Object::Integer(6))),
+            ]
+            .into_iter()
+            .collect()
+        )))
+    ))
+}
+
+#[test]
+fn builtin_hashes() {
+    let string = |s: &str| Rc::new(Object::String(s.into()));
+    test!(
+        (
+            r#"let h = {"b": 1, "a": 2, "b": 3}; join([h, keys(h), values(h)], " ")"#,
+            Ok(string("{b: 3, a: 2} [b, a] [3, 2]"))
+        ),
+        (
+            r#"join(entries({"x": 1, 2: true}), ";")"#,
+            Ok(string("[x, 1];[2, true]"))
+        ),
+        (
+            r#"let h = {"a": 1}; [has(h, "a"), h.has("b"), has(delete(h, "a"), "a")]"#,
+            Ok(Rc::new(Object::Array(ArrayObj {
+                elements: vec![
+                    Rc::new(Object::Bool(true)),
+                    Rc::new(Object::Bool(false)),
+                    Rc::new(Object::Bool(false))
+                ]
+            })))
+        ),
+        (
+            r#"join([delete({1: 1, 2: 2, 3: 3}, 2), merge({1: 1, 2: 2}, {3: 3, 1: 4})], " ")"#,
+            Ok(string("{1: 1, 3: 3} {1: 4, 2: 2, 3: 3}"))
+        ),
+        (
+            r#"{1: 2, 3: 4} == {3: 4, 1: 2}"#,
+            Ok(Rc::new(Object::Bool(true)))
+        ),
+        (r#"has({}, [1])"#, Err("unusable as hash key: ARRAY".into())),
+        (
+            r#"merge({}, 1)"#,
+            Err("arguments to `merge` not supported, got HASH, INTEGER".into())
+        ),
+    )
+}
+
+#[test]
+fn index_hash() {
+    test!(
+        (r#"{"foo": 5}["foo"]"#, Ok(Rc::new(Object::Integer(5)))),
+        (r#"{"foo": 5}["bar"]"#, Ok(Rc::new(Object::Null))),
+        (
+            r#"let key = "foo"; {"foo": 5}[key]"#,
+            Ok(Rc::new(Object::Integer(5)))
+        ),
+        (r#"{}["foo"]"#, Ok(Rc::new(Object::Null))),
+        (r#"{5: 5}[5]"#, Ok(Rc::new(Object::Integer(5)))),
+        (r#"{true: 5}[true]"#, Ok(Rc::new(Object::Integer(5)))),
+        (r#"{false: 5}[false]"#, Ok(Rc::new(Object::Integer(5)))),
+    )
+}
+
+#[test]
+fn builtin_len() {
+    test!(
+        (r#"len("")"#, Ok(Rc::new(Object::Integer(0)))),
+        (r#"len("four")"#, Ok(Rc::new(Object::Integer(4)))),
+        (r#"len("hello world")"#, Ok(Rc::new(Object::Integer(11)))),
+        (
+            r#"len(1)"#,
+            Err("argument to `len` not supported, got INTEGER".into())
+        ),
+        (
+            r#"len("one", "two")"#,
+            Err("wrong number of arguments. expected 1, got 2".into())
+        ),
+        (r#"len([1, 2, 3, 4])"#, Ok(Rc::new(Object::Integer(4)))),
+    )
+}
+
+#[test]
+fn builtin_first() {
+    test!(
+        (
+            r#"first(["a", "b"])"#,
+            Ok(Rc::new(Object::String("a".into())))
+        ),
+        (r#"first([])"#, Ok(Rc::new(Object::Null))),
+        (
+            r#"first(1)"#,
+            Err("argument to `first` not supported, got INTEGER".into())
+        ),
+        (
+            r#"first("one", "two")"#,
+            Err("wrong number of arguments. expected 1, got 2".into())
+        ),
+    )
+}
+
+#[test]
+fn builtin_last() {
+    test!(
+        (
+            r#"last(["a", "b"])"#,
+            Ok(Rc::new(Object::String("b".into())))
+        ),
+        (r#"last([])"#, Ok(Rc::new(Object::Null))),
+        (
+            r#"last(1)"#,
+            Err("argument to `last` not supported, got INTEGER".into())
+        ),
+        (
+            r#"last("one", "two")"#,
+            Err("wrong number of arguments. expected 1, got 2".into())
+        ),
+    )
+}
+
+#[test]
+fn builtin_rest() {
+    test!(
+        (
+            r#"rest(["a", "b", "c"])"#,
+            Ok(Rc::new(Object::Array(ArrayObj {
+                elements: vec![
+                    Rc::new(Object::String("b".into())),
+                    Rc::new(Object::String("c".into()))
+                ]
+            })))
+        ),
+        (
+            r#"rest(["a"])"#,
+            Ok(Rc::new(Object::Array(ArrayObj { elements: vec![] })))
+        ),
+        (
+            r#"rest([])"#,
+            Ok(Rc::new(Object::Array(ArrayObj { elements: vec![] })))
+        ),
+        (
+            r#"rest(1)"#,
+            Err("argument to `rest` not supported, got INTEGER".into())
+        ),
+        (
+            r#"rest("one", "two")"#,
+            Err("wrong number of arguments. expected 1, got 2".into())
+        ),
+    )
+}
+
+#[test]
+fn builtin_strings() {
+    let string = |s: &str| Rc::new(Object::String(s.into()));
+    let strings = |s: &[&str]| {
+        Rc::new(Object::Array(ArrayObj {
+            elements: s.iter().map(|s| string(s)).collect(),
+        }))
+    };
+    test!(
+        (r#"len("héllo")"#, Ok(Rc::new(Object::Integer(5)))),
+        (r#"split("a,b,,c", ",")"#, Ok(strings(&["a", "b", "", "c"]))),
+        (r#"split("añb", "")"#, Ok(strings(&["a", "ñ", "b"]))),
+        (r#"join([1, "a", true], "-")"#, Ok(string("1-a-true"))),
+        (r#"trim("  a b  ")"#, Ok(string("a b"))),
+        (r#""Straße".upper()"#, Ok(string("STRASSE"))),
+        (r#"lower("ABC")"#, Ok(string("abc"))),
+        (r#"replace("a-b-c", "-", "+")"#, Ok(string("a+b+c"))),
+        (
+            r#"[contains("abc", "bc"), starts_with("abc", "ab"), ends_with("abc", "b")]"#,
+            Ok(Rc::new(Object::Array(ArrayObj {
+                elements: vec![
+                    Rc::new(Object::Bool(true)),
+                    Rc::new(Object::Bool(true)),
+                    Rc::new(Object::Bool(false))
+                ]
+            })))
+        ),
+        (r#"index_of("héllo", "l")"#, Ok(Rc::new(Object::Integer(2)))),
+        (r#"index_of("abc", "x")"#, Ok(Rc::new(Object::Integer(-1)))),
+        (r#"chars("hé")"#, Ok(strings(&["h", "é"]))),
+        (r#""ab".repeat(3)"#, Ok(string("ababab"))),
+        (r#"pad_left("7", 3, "0")"#, Ok(string("007"))),
+        (r#"pad_right("é", 3)"#, Ok(string("é  "))),
+        (r#"pad_left("long", 2)"#, Ok(string("long"))),
+        (
+            r#"split("a", 1)"#,
+            Err("arguments to `split` not supported, got STRING, INTEGER".into())
+        ),
+        (
+            r#"repeat("a", -1)"#,
+            Err("repeat count must not be negative, got -1".into())
+        ),
+        (
+            r#"repeat("ab", 9223372036854775807)"#,
+            Err("result of `repeat` is too large".into())
+        ),
+        (
+            r#"pad_left("a", 9223372036854775807)"#,
+            Err("result of `pad_left` is too large".into())
+        ),
+        (
+            r#"pad_left("a", 3, "ab")"#,
+            Err("padding must be a single character, got \"ab\"".into())
+        ),
+        (
+            r#"upper(1)"#,
+            Err("argument to `upper` not supported, got INTEGER".into())
+        ),
+        (
+            r#"pad_right("a")"#,
+            Err("wrong number of arguments. expected 2 or 3, got 1".into())
+        ),
+    )
+}
+
+#[test]
+fn builtin_conversions() {
+    let string = |s: &str| Ok(Rc::new(Object::String(s.into())));
+    test!(
+        ("type(1)", string("INTEGER")),
+        (r#"[1, "a"].type()"#, string("ARRAY")),
+        ("type(float(1))", string("FLOAT")),
+        (r#"str([1, "a"]) + str(true)"#, string("[1, a]true")),
+        (r#"int(" -42 ")"#, Ok(Rc::new(Object::Integer(-42)))),
+        (
+            r#"int(float("2.9")) + int(true)"#,
+            Ok(Rc::new(Object::Integer(3)))
+        ),
+        (r#"float("1.5") * 2"#, Ok(Rc::new(Object::Float(3.0)))),
+        ("str(float(1) / 4)", string("0.25")),
+        ("float(3) == 3", Ok(Rc::new(Object::Bool(true)))),
+        ("-float(1) < 0", Ok(Rc::new(Object::Bool(true)))),
+        ("[bool(0), bool(2)].str()", string("[false, true]")),
+        (
+            "repr([\"a\nb\", 1, {\"k\": \"v\"}])",
+            string(r#"["a\nb", 1, {"k": "v"}]"#)
+        ),
+        (
+            r#"int("12a")"#,
+            Err(r#"could not parse "12a" as INTEGER"#.into())
+        ),
+        (
+            r#"float("x")"#,
+            Err(r#"could not parse "x" as FLOAT"#.into())
+        ),
+        (
+            "float([])",
+            Err("argument to `float` not supported, got ARRAY".into())
+        ),
+    )
+}
+
+#[test]
+fn builtin_higher_order() {
+    let string = |s: &str| Ok(Rc::new(Object::String(s.into())));
+    test!(
+        ("str(map([1, 2, 3], fn(x) { x * 2 }))", string("[2, 4, 6]")),
+        ("str((1..6).filter(fn(x) { x > 3 }))", string("[4, 5]")),
+        (
+            "reduce([1, 2, 3], 10, fn(acc, x) { acc + x })",
+            Ok(Rc::new(Object::Integer(16)))
+        ),
+        (
+            "let k = 3; str(sort([1, 5, 3], fn(a, b) { b - a }).map(fn(x) { x * k }))",
+            string("[15, 9, 3]")
+        ),
+        (
+            r#"str(sort([[2, "a"], [1, "b"], [2, "c"]], fn(a, b) { a[0] - b[0] }))"#,
+            string("[[1, b], [2, a], [2, c]]")
+        ),
+        (
+            "str(map([[1, 2], [3]], fn(xs) { reduce(xs, 0, fn(a, b) { a + b }) }))",
+            string("[3, 3]")
+        ),
+        (r#"str(map(["a", "bc"], len))"#, string("[1, 2]")),
+        ("each([1, 2], fn(x) { x })", Ok(Rc::new(Object::Null))),
+        (
+            "map([1], fn(x) { x + true })",
+            Err("type mismatch: INTEGER + BOOL".into())
+        ),
+        (
+            "sort([1, 2], fn(a, b) { true })",
+            Err("sort comparator must return INTEGER, got BOOL".into())
+        ),
+        (
+            "filter(1, fn(x) { x })",
+            Err("argument to `filter` not supported, got INTEGER".into())
+        ),
+    )
+}
+
+#[test]
+fn builtin_arrays() {
+    let string = |s: &str| Ok(Rc::new(Object::String(s.into())));
+    let int = |i| Ok(Rc::new(Object::Integer(i)));
+    test!(
+        (r#"str(sort([3, 1, 2]))"#, string("[1, 2, 3]")),
+        (r#"str(sort(["b", "a", "c"]))"#, string("[a, b, c]")),
+        ("str(sort([2, float(1) / 2, 1]))", string("[0.5, 1, 2]")),
+        ("str(reverse(1..4))", string("[3, 2, 1]")),
+        (r#"[1, "a", [2]].index_of([2])"#, int(2)),
+        ("index_of([1], 5)", int(-1)),
+        ("str(concat([1], [], [2, 3]))", string("[1, 2, 3]")),
+        ("str(flatten([1, [2, [3]], []]))", string("[1, 2, [3]]")),
+        (
+            r#"str(unique([1, "1", 1, [1], [1], 2]))"#,
+            string("[1, 1, [1], 2]")
+        ),
+        (
+            "str(unique([float(1), 1, float(1), float(2)]))",
+            string("[1.0, 1, 2.0]")
+        ),
+        ("[min([3, 1, 2]), max(3, 5, 4)].str()", string("[1, 5]")),
+        (r#"max(["b", "c", "a"])"#, string("c")),
+        ("min([])", Ok(Rc::new(Object::Null))),
+        ("sum(1..=4)", int(10)),
+        ("sum([1, float(1) / 2])", Ok(Rc::new(Object::Float(1.5)))),
+        (
+            "str([[1, 2, 3, 4].slice(1), slice([1, 2, 3], 0, -1)])",
+            string("[[2, 3, 4], [1, 2]]")
+        ),
+        (
+            "str([insert([1, 3], 1, 2), insert([1], 1, 2), remove_at([1, 2, 3], -1)])",
+            string("[[1, 2, 3], [1, 2], [1, 2]]")
+        ),
+        (
+            r#"sort([1, "a"])"#,
+            Err("cannot compare STRING and INTEGER".into())
+        ),
+        (
+            "insert([1], 3, 0)",
+            Err("index 3 out of range for length 1".into())
+        ),
+        (
+            "remove_at([], 0)",
+            Err("index 0 out of range for length 0".into())
+        ),
+        (r#"sum(["a"])"#, Err("cannot sum STRING".into())),
+        (
+            "sum([9223372036854775807, 1])",
+            Err("integer overflow".into())
+        ),
+        (
+            "concat([1], 2)",
+            Err("arguments to `concat` not supported, got ARRAY, INTEGER".into())
+        ),
+    )
+}
+
+#[test]
+fn builtin_math() {
+    let int = |i| Ok(Rc::new(Object::Integer(i)));
+    let float = |x| Ok(Rc::new(Object::Float(x)));
+    test!(
+        ("abs(-3)", int(3)),
+        ("abs(-float(2))", float(2.0)),
+        ("pow(2, 10)", int(1024)),
+        ("pow(4, float(1) / 2)", float(2.0)),
+        ("pow(2, -1)", float(0.5)),
+        ("sqrt(16)", float(4.0)),
+        (
+            r#"[floor(float("2.7")), ceil(float("2.1")), round(float("-2.5")), round(3)].str()"#,
+            Ok(Rc::new(Object::String("[2, 3, -3, 3]".into())))
+        ),
+        ("clamp(5, 0, 3) + clamp(-1, 0, 3) + clamp(2, 0, 3)", int(5)),
+        ("gcd(12, -18)", int(6)),
+        ("round(sin(PI / 2) + cos(0) + tan(0))", int(2)),
+        ("round(atan2(1, 1) * 4 * 1000)", int(3142)),
+        ("round(log(exp(2)))", int(2)),
+        ("log(8, 2)", float(3.0)),
+        ("round(E * 1000)", int(2718)),
+        ("let PI = 3; PI", int(3)),
+        ("pow(10, 20)", Err("integer overflow".into())),
+        (
+            r#"sqrt("4")"#,
+            Err("argument to `sqrt` not supported, got STRING".into())
+        ),
+        (
+            "clamp(1, 3, 0)",
+            Err("clamp bounds are reversed: 3 > 0".into())
+        ),
+        (
+            "floor(sqrt(-1))",
+            Err("could not convert NaN to INTEGER".into())
+        ),
+        (
+            r#"round(float("1e300"))"#,
+            Err("could not convert 1e300 to INTEGER".into())
+        ),
+        (
+            r#"int(float("inf"))"#,
+            Err("could not convert inf to INTEGER".into())
+        ),
+        (
+            r#"int(float("-9223372036854775808"))"#,
+            int(-9223372036854775808)
+        ),
+    )
+}
+
+#[test]
+fn builtin_random() {
+    crate::random::seed(1);
+    test!(
+        (
+            "let xs = map(1..100, fn(_) { random_int(-2, 2) }); [min(xs), max(xs)].str()",
+            Ok(Rc::new(Object::String("[-2, 2]".into())))
+        ),
+        (
+            "let x = random(); x > -1 == x < 1",
+            Ok(Rc::new(Object::Bool(true)))
+        ),
+        (
+            "sort(shuffle(1..5)).str()",
+            Ok(Rc::new(Object::String("[1, 2, 3, 4]".into())))
+        ),
+        (
+            "contains(4..7, choice(4..7))",
+            Ok(Rc::new(Object::Bool(true)))
+        ),
+        ("choice([])", Ok(Rc::new(Object::Null))),
+        (
+            "random_int(3, 1)",
+            Err("random_int bounds are reversed: 3 > 1".into())
+        ),
+        (
+            "random(1)",
+            Err("wrong number of arguments. expected 0, got 1".into())
+        ),
+    )
+}
+
+#[test]
+fn builtin_json() {
+    let string = |s: &str| Ok(Rc::new(Object::String(s.into())));
+    test!(
+        (
+            r#"json_stringify({"a": [1, float(1) / 2, true, first([])], 2: "x"})"#,
+            string(r#"{"a":[1,0.5,true,null],"2":"x"}"#)
+        ),
+        (
+            "json_stringify([[], {}, 1..3], 1)",
+            string("[\n [],\n {},\n [\n  1,\n  2\n ]\n]")
+        ),
+        (
+            r#"let v = json_parse(json_stringify({"k": [1, "two"]})); v.k[1] + str(v.k[0])"#,
+            string("two1")
+        ),
+        (
+            "json_stringify(fn(x) { x })",
+            Err("cannot convert FUNCTION to JSON".into())
+        ),
+        (
+            "json_stringify(float(1) / 0)",
+            Err("cannot convert inf to JSON".into())
+        ),
+        (
+            r#"json_parse("[1")"#,
+            Err("invalid JSON: expected ',' at line 1, column 3".into())
+        ),
+        (
+            "json_parse(1)",
+            Err("argument to `json_parse` not supported, got INTEGER".into())
+        ),
+        (
+            "json_stringify([], 11)",
+            Err("indent must be between 0 and 10, got 11".into())
+        ),
+    )
+}
+
+#[test]
+fn builtin_files() {
+    let dir = write_files("builtin_files", &[("data/a.txt", "hello")]);
+    let root = dir.display();
+    let string = |s: &str| Ok(Rc::new(Object::String(s.into())));
+    let cases = [
+        (
+            format!(r#"read_file("{}/data/a.txt")"#, root),
+            string("hello"),
+        ),
+        (
+            format!(
+                r#"let p = "{}/data/b.txt"; write_file(p, "x"); append_file(p, "y"); read_file(p)"#,
+                root
+            ),
+            string("xy"),
+        ),
+        (
+            format!(r#"list_dir("{}/data").str()"#, root),
+            string("[a.txt, b.txt]"),
+        ),
+        (
+            format!(
+                r#"let p = "{}/data/b.txt"; remove_file(p); exists(p)"#,
+                root
+            ),
+            Ok(Rc::new(Object::Bool(false))),
+        ),
+        (
+            format!(r#"read_file("{}/data/../../x")"#, root),
+            Err(format!(
+                "access denied: {}/data/../../x is outside the allowed roots",
+                root
+            )),
+        ),
+        (
+            format!(r#"read_file("{}/data/none")"#, root),
+            Err(format!(
+                "cannot read {}/data/none: No such file or directory (os error 2)",
+                root
+            )),
+        ),
+        (
+            format!(r#"exists("{}/missing/x.txt")"#, root),
+            Ok(Rc::new(Object::Bool(false))),
+        ),
+        (
+            format!(r#"exists("{}/./missing/../data/a.txt")"#, root),
+            Ok(Rc::new(Object::Bool(true))),
+        ),
+        (
+            format!(r#"exists("{}/../missing/x.txt")"#, root),
+            Err(format!(
+                "access denied: {}/../missing/x.txt is outside the allowed roots",
+                root
+            )),
+        ),
+        (
+            "write_file(1, 2)".into(),
+            Err("arguments to `write_file` not supported, got INTEGER, INTEGER".into()),
+        ),
+    ];
+
+    policy::set(Policy {
+        roots: vec![dir.clone()],
+        ..Policy::default()
+    });
+    for (inp, exp) in &cases {
+        test(&[(inp, exp.clone())]);
+    }
+
+    let outside = std::env::temp_dir().join(format!("monkey-{}-escaped", std::process::id()));
+    let _ = std::fs::remove_file(dir.join("link"));
+    std::os::unix::fs::symlink(&outside, dir.join("link")).unwrap();
+    test!((
+        &format!(r#"write_file("{}/link", "x")"#, root),
+        Err(format!(
+            "cannot resolve {}/link: dangling symbolic link",
+            root
+        ))
+    ));
+    assert!(!outside.exists());
+
+    policy::set(Policy {
+        roots: vec![dir.clone()],
+        read_only: true,
+        ..Policy::default()
+    });
+    test!((
+        &format!(r#"remove_file("{}/data/a.txt")"#, root),
+        Err(format!(
+            "access denied: {}/data/a.txt (read-only mode)",
+            root
+        ))
+    ));
+
+    policy::set(Policy::default());
+    test!((
+        &format!(r#"exists("{}/data/a.txt")"#, root),
+        Err(format!(
+            "access denied: {}/data/a.txt is outside the allowed roots",
+            root
+        ))
+    ));
+}
+
+#[test]
+fn builtin_script_io() {
+    script::set_args(vec!["a".into(), "b c".into()]);
+    test!(
+        (
+            "args().str()",
+            Ok(Rc::new(Object::String("[a, b c]".into())))
+        ),
+        (r#"print("")"#, Ok(Rc::new(Object::Null))),
+        (
+            "read_line(1)",
+            Err("wrong number of arguments. expected 0, got 1".into())
+        ),
+        (
+            r#"exit("1")"#,
+            Err("arguments to `exit` not supported, got STRING".into())
+        ),
+        (
+            "exit(256)",
+            Err("exit code must be between 0 and 255, got 256".into())
+        ),
+        (
+            "exit(-1)",
+            Err("exit code must be between 0 and 255, got -1".into())
+        ),
+        (r#"eprint("")"#, Ok(Rc::new(Object::Null))),
+    )
+}
+
+#[test]
+fn builtin_system() {
+    let string = |s: &str| Ok(Rc::new(Object::String(s.into())));
+    policy::set(Policy::default());
+    test!(
+        (
+            r#"env_get("HOME")"#,
+            Err("access denied: env capability is disabled".into())
+        ),
+        (
+            "now()",
+            Err("access denied: time capability is disabled".into())
+        ),
+        (
+            r#"run("true")"#,
+            Err("access denied: run capability is disabled".into())
+        ),
+        ("format_time(0)", string("1970-01-01T00:00:00Z")),
+    );
+
+    policy::set(Policy {
+        env: true,
+        time: true,
+        run: true,
+        ..Policy::default()
+    });
+    test!(
+        (
+            r#"env_set("MONKEY_TEST_VAR", "x"); [env_get("MONKEY_TEST_VAR"), env_get("MONKEY_TEST_UNSET")].str()"#,
+            string("[x, null]")
+        ),
+        (
+            "let t = monotonic(); sleep(5); monotonic() - t > 4",
+            Ok(Rc::new(Object::Bool(true)))
+        ),
+        ("now() > 1700000000000", Ok(Rc::new(Object::Bool(true)))),
+        ("sleep(-1)", Err("cannot sleep for -1 ms".into())),
+        (
+            r#"run("sh", ["-c", "echo hi; exit 3"]).str()"#,
+            string("{status: 3, stdout: hi\n, stderr: }")
+        ),
+        (
+            r#"run("sh", [1])"#,
+            Err("command arguments must be STRING, got INTEGER".into())
+        ),
+    );
+}
+
+#[test]
+fn builtin_assert() {
+    test!(
+        ("assert(1 == 1)", Ok(Rc::new(Object::Null))),
+        ("assert(1 == 2)", Err("assertion failed".into())),
+        (
+            r#"assert(false, "broken")"#,
+            Err("assertion failed: broken".into())
+        ),
+        (
+            r#"assert_eq({"a": [1, 2]}, {"a": [1, 2]})"#,
+            Ok(Rc::new(Object::Null))
+        ),
+        (
+            "assert_eq(1 + 1, 3)",
+            Err("assertion failed\n- 2\n+ 3".into())
+        ),
+        (
+            r#"assert_eq({"a": [1, 2]}, {"a": [1, 3]}, "hash")"#,
+            Err(
+                "assertion failed: hash\n  {\n    \"a\": [\n      1,\n-     2\n+     3\n    ]\n  }"
+                    .into()
+            )
+        ),
+        (
+            "assert(true, 1)",
+            Err("arguments to `assert` not supported, got BOOL, INTEGER".into())
+        ),
+        (
+            "assert_eq(1)",
+            Err("wrong number of arguments. expected 2 or 3, got 1".into())
+        ),
+    );
+}
+
+#[test]
+fn builtin_push() {
+    test!(
+        (
+            r#"push(["a", "b"], "c")"#,
+            Ok(Rc::new(Object::Array(ArrayObj {
+                elements: vec![
+                    Rc::new(Object::String("a".into())),
+                    Rc::new(Object::String("b".into())),
+                    Rc::new(Object::String("c".into()))
+                ]
+            })))
+        ),
+        (
+            r#"push(["a"], 1)"#,
+            Ok(Rc::new(Object::Array(ArrayObj {
+                elements: vec![
+                    Rc::new(Object::String("a".into())),
+                    Rc::new(Object::Integer(1))
+                ]
+            })))
+        ),
+        (
+            r#"push(["a"], [1])"#,
+            Ok(Rc::new(Object::Array(ArrayObj {
+                elements: vec![
+                    Rc::new(Object::String("a".into())),
+                    Rc::new(Object::Array(ArrayObj {
+                        elements: vec![Rc::new(Object::Integer(1))]
+                    }))
+                ]
+            })))
+        ),
+        (
+            r#"push([], "bar")"#,
+            Ok(Rc::new(Object::Array(ArrayObj {
+                elements: vec![Rc::new(Object::String("bar".into()))]
+            })))
+        ),
+        (
+            r#"push(1, 2)"#,
+            Err("argument to `push` not supported, got INTEGER".into())
+        ),
+        (
+            r#"push([])"#,
             Err("wrong number of arguments. expected 2, got 1".into())
         ),
     )
 }
 
+#[test]
+fn eval_match() {
+    test!(
+        (
+            r#"match (2) { 1 => "one", 2 => "two", _ => "many" }"#,
+            Ok(Rc::new(Object::String("two".into())))
+        ),
+        (
+            r#"match (5) { 1 => "one", _ => "many" }"#,
+            Ok(Rc::new(Object::String("many".into())))
+        ),
+        ("match (5) { 1 => 1 }", Ok(Rc::new(Object::Null))),
+        (
+            r#"match ("a") { 1 => 1, "a" => 2 }"#,
+            Ok(Rc::new(Object::Integer(2)))
+        ),
+        ("match (-3) { -3 => true }", Ok(Rc::new(Object::Bool(true)))),
+        ("match (4) { x => x * 2 }", Ok(Rc::new(Object::Integer(8)))),
+        (
+            "match (4) { x if x > 5 => 1, x if x > 3 => 2, _ => 3 }",
+            Ok(Rc::new(Object::Integer(2)))
+        ),
+        (
+            "match ([1, 2, 3]) { [] => 0, [a] => a, [a, b] => a + b, [a, ..rest] => len(rest) }",
+            Ok(Rc::new(Object::Integer(2)))
+        ),
+        (
+            "match ([1, [2, 3]]) { [a, [b, c]] => a + b + c }",
+            Ok(Rc::new(Object::Integer(6)))
+        ),
+        (
+            "match ([]) { [_, ..] => 1, [..] => 2 }",
+            Ok(Rc::new(Object::Integer(2)))
+        ),
+        (
+            "match (1) { [..] => 1, _ => 2 }",
+            Ok(Rc::new(Object::Integer(2)))
+        ),
+        (
+            r#"match ({"type": "circle", "r": 2}) { {"type": "square", "a": a} => a * a, {"type": "circle", "r": r} => 3 * r * r }"#,
+            Ok(Rc::new(Object::Integer(12)))
+        ),
+        (
+            r#"match ({"a": 1}) { {"b": b} => b, _ => 0 }"#,
+            Ok(Rc::new(Object::Integer(0)))
+        ),
+        (
+            "let x = 1; match (2) { x => x }; x",
+            Ok(Rc::new(Object::Integer(1)))
+        ),
+    )
+}
+
 fn test(cases: &[(&str, EvalResult)]) {
     for (inp, exp) in cases {
         let lexer = Lexer::new(inp.to_string());
@@ -464,3 +1567,175 @@ fn test(cases: &[(&str, EvalResult)]) {
         assert_eq!(&res, exp);
     }
 }
+
+#[test]
+fn eval_for() {
+    let string = |s: &str| Ok(Rc::new(Object::String(s.into())));
+    test!(
+        (
+            "let sum = 0; for (x in 1..=4) { let sum = sum + x; } sum",
+            Ok(Rc::new(Object::Integer(10)))
+        ),
+        (
+            r#"let s = ""; for (c in "héy") { let s = c + s; } s"#,
+            string("yéh")
+        ),
+        (
+            r#"let s = ""; for (k in {"a": 1, "b": 2}) { let s = s + k; } s"#,
+            string("ab")
+        ),
+        (
+            "let x = 1; for (x in [5]) { x }; x",
+            Ok(Rc::new(Object::Integer(1)))
+        ),
+        ("for (x in []) { x }", Ok(Rc::new(Object::Null))),
+        (
+            "let f = fn() { for (x in 0..1000000) { if (x > 2) { return x; } } }; f()",
+            Ok(Rc::new(Object::Integer(3)))
+        ),
+        (
+            "let fs = []; for (i in 0..2) { let fs = push(fs, fn() { i }); } fs[0]() + fs[1]()",
+            Ok(Rc::new(Object::Integer(1)))
+        ),
+        (
+            "for (x in 5) { x }",
+            Err("cannot iterate over INTEGER".into())
+        ),
+    )
+}
+
+#[test]
+fn eval_try_catch() {
+    let string = |s: &str| Ok(Rc::new(Object::String(s.into())));
+    let f =
+        r#"let f = fn(x) { if (x > 2) { throw {"kind": "ValueError", "message": "big"} }; x };"#;
+    test!(
+        (
+            "try { 1 + true } catch (e) { e.str() }",
+            string("{kind: runtime, message: type mismatch: INTEGER + BOOL, location: line 1}")
+        ),
+        (
+            &format!(
+                "{} try {{ f(1) + f(5) }} catch (e) {{ e.kind + e.message }}",
+                f
+            ),
+            string("ValueErrorbig")
+        ),
+        (
+            "try { throw 42 } catch (e) { e.str() }",
+            string("{kind: error, message: 42, location: line 1, value: 42}")
+        ),
+        (
+            &format!("{} try {{ map([1, 5], f) }} catch (e) {{ e.message }}", f),
+            string("big")
+        ),
+        (
+            r#"try { try { throw "a" } catch (e) { throw e } } catch (e) { e.message }"#,
+            string("a")
+        ),
+        ("try { 5 } finally { 6 }", Ok(Rc::new(Object::Integer(5)))),
+        (
+            "let g = fn() { try { return 1 } finally { return 7 } }; g()",
+            Ok(Rc::new(Object::Integer(7)))
+        ),
+        (
+            "let a = 1;\ntry {\n  throw a\n} catch (e) { e.location }",
+            string("line 3")
+        ),
+        (
+            "let f = fn(x) {\n  x / 0\n};\ntry { f(1) } catch (e) { e.location }",
+            string("line 2")
+        ),
+        (
+            "try {\n  [1]\n    .repeat(2)\n} catch (e) { e.location }",
+            string("line 3")
+        ),
+        (
+            r#"let e = 1; let r = try { throw "x" } catch (e) { let b = 2; e.message }; [e, b, r].str()"#,
+            string("[1, 2, x]")
+        ),
+        (
+            "try { 1 / 0 } catch (e) { e.message }",
+            string("division by zero")
+        ),
+        ("9223372036854775807 + 1", Err("integer overflow".into())),
+        (
+            "-(-9223372036854775807 - 1)",
+            Err("integer overflow".into())
+        ),
+        (
+            "(-9223372036854775807 - 1) / -1",
+            Err("integer overflow".into())
+        ),
+        ("5 / 0", Err("division by zero".into())),
+        (r#"throw "boom""#, Err("error: boom at line 1".into())),
+        (
+            r#"try { throw "a" } finally { 1 }"#,
+            Err("error: a at line 1".into())
+        ),
+        (
+            r#"try { 1 } finally { throw "b" }"#,
+            Err("error: b at line 1".into())
+        ),
+        (
+            "try { 1 / 0 } finally { 0 }",
+            Err("division by zero".into())
+        ),
+        (
+            r#"try { 1 / 0 } finally { try { throw "b" } catch (e) { 0 } }"#,
+            Err("division by zero".into())
+        ),
+    )
+}
+
+#[test]
+fn eval_prelude() {
+    let int = |i| Rc::new(Object::Integer(i));
+    let array = |elements| Rc::new(Object::Array(ArrayObj { elements }));
+    let cases = [
+        (
+            "map([1, 2, 3], fn(x) { x * 2 })",
+            array(vec![int(2), int(4), int(6)]),
+        ),
+        (
+            "filter(range(6), fn(x) { x > 3 })",
+            array(vec![int(4), int(5)]),
+        ),
+        ("reduce(1..=4, 0, fn(acc, x) { acc + x })", int(10)),
+        (
+            "zip([1, 2, 3], [4, 5])",
+            array(vec![
+                array(vec![int(1), int(4)]),
+                array(vec![int(2), int(5)]),
+            ]),
+        ),
+        (
+            "[any([1, 2], fn(x) { x > 1 }), all([1, 2], fn(x) { x > 1 })]",
+            array(vec![
+                Rc::new(Object::Bool(true)),
+                Rc::new(Object::Bool(false)),
+            ]),
+        ),
+        (
+            "[any(1..20000, fn(x) { false }), all(1..20000, fn(x) { true })]",
+            array(vec![
+                Rc::new(Object::Bool(false)),
+                Rc::new(Object::Bool(true)),
+            ]),
+        ),
+        (
+            "sort_by([3, 1, 2], fn(x) { -x })",
+            array(vec![int(3), int(2), int(1)]),
+        ),
+        (
+            "fn map(xs, f) { 0 } [map([1], len), len(zip([1], [2]))]",
+            array(vec![int(0), int(1)]),
+        ),
+    ];
+
+    for (inp, exp) in cases {
+        let prog = Parser::new(Lexer::new(inp.to_string())).parse().unwrap();
+        let res = eval_program(prog, &prelude_env());
+        assert_eq!(res, Ok(exp), "{}", inp);
+    }
+}
@@ -0,0 +1,152 @@
+use crate::eval::{ArrayObj, HashObj, Object};
+use std::{cell::RefCell, rc::Rc};
+
+/// What is known about the error being propagated, whose message identifies it
+struct Pending {
+    msg: String,
+    /// Error value of a `throw`
+    value: Option<Rc<Object>>,
+    /// Line the error happened at
+    line: usize,
+}
+
+thread_local! {
+    static PENDING: RefCell<Option<Pending>> = RefCell::default();
+}
+
+/// Forgets the error of a previous run, called before running a program
+pub fn reset() {
+    PENDING.with(|p| *p.borrow_mut() = None);
+}
+
+/// Records the line of an error, unless it was already located deeper down
+pub fn locate(err: String, line: usize) -> String {
+    PENDING.with(|p| {
+        let mut pending = p.borrow_mut();
+        if !pending.as_ref().is_some_and(|p| p.msg == err) {
+            *pending = Some(Pending {
+                msg: err.clone(),
+                value: None,
+                line,
+            });
+        }
+    });
+    err
+}
+
+/// Line the pending error `err` happened at, if it was located
+pub fn line(err: &str) -> Option<usize> {
+    PENDING.with(|p| match &*p.borrow() {
+        Some(p) if p.msg == err => Some(p.line),
+        _ => None,
+    })
+}
+
+/// Raises `value` as an error, returning the message propagated to the nearest `try`
+///
+/// Hashes are thrown as they are, with a missing `kind`, `message` or
+/// `location` filled in, so caught errors can be thrown again unchanged.
+/// Other values are kept under `value`.
+pub fn throw(value: Rc<Object>, line: usize) -> String {
+    let mut error = match &*value {
+        Object::Hash(h) => h.clone(),
+        _ => HashObj::default(),
+    };
+    for (name, default) in [
+        ("kind", Object::String("error".into())),
+        ("message", Object::String(value.to_string())),
+        ("location", location(line)),
+    ] {
+        if !error.contains_key(&key(name)) {
+            error.insert(key(name), Rc::new(default));
+        }
+    }
+    if !matches!(*value, Object::Hash(_)) {
+        error.insert(key("value"), value);
+    }
+
+    let field = |name| error.get(&key(name)).unwrap().to_string();
+    let msg = format!(
+        "{}: {} at {}",
+        field("kind"),
+        field("message"),
+        field("location")
+    );
+    PENDING.with(|p| {
+        *p.borrow_mut() = Some(Pending {
+            msg: msg.clone(),
+            value: Some(Rc::new(Object::Hash(error))),
+            line,
+        })
+    });
+    msg
+}
+
+/// Error value bound by `catch`, which is the thrown value when `err` came from `throw`.
+/// Runtime errors that were not located are reported at `line`
+pub fn catch(err: String, line: usize) -> Rc<Object> {
+    let pending = PENDING
+        .with(|p| p.borrow_mut().take())
+        .filter(|p| p.msg == err);
+    if let Some(Pending {
+        value: Some(value), ..
+    }) = pending
+    {
+        return value;
+    }
+
+    let error = [
+        ("kind", Object::String("runtime".into())),
+        ("message", Object::String(err)),
+        ("location", location(pending.map_or(line, |p| p.line))),
+    ];
+    let error = error.into_iter().map(|(k, v)| (key(k), Rc::new(v)));
+    Rc::new(Object::Hash(error.collect()))
+}
+
+/// Sets aside an error while a `finally` block runs, so that errors caught in
+/// the block do not lose it. The result is only meant for `resume`
+pub fn suspend(err: String) -> Object {
+    let pending = PENDING
+        .with(|p| p.borrow_mut().take())
+        .filter(|p| p.msg == err);
+    let (value, line) = match pending {
+        Some(p) => (
+            p.value.unwrap_or(Rc::new(Object::Null)),
+            Object::Integer(p.line as i64),
+        ),
+        None => (Rc::new(Object::Null), Object::Null),
+    };
+    let elements = vec![Rc::new(Object::String(err)), value, Rc::new(line)];
+    Object::Array(ArrayObj { elements })
+}
+
+/// Raises again an error set aside by `suspend`, returning its unchanged message
+pub fn resume(suspended: &Object) -> String {
+    let Object::Array(a) = suspended else {
+        return format!("cannot resume {}", suspended.kind());
+    };
+    let msg = a.elements[0].to_string();
+    if let Object::Integer(line) = *a.elements[2] {
+        let value = match &*a.elements[1] {
+            Object::Null => None,
+            _ => Some(a.elements[1].clone()),
+        };
+        PENDING.with(|p| {
+            *p.borrow_mut() = Some(Pending {
+                msg: msg.clone(),
+                value,
+                line: line as usize,
+            })
+        });
+    }
+    msg
+}
+
+fn key(name: &str) -> Rc<Object> {
+    Rc::new(Object::String(name.into()))
+}
+
+fn location(line: usize) -> Object {
+    Object::String(format!("line {}", line))
+}
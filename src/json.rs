@@ -0,0 +1,390 @@
+use crate::eval::{ArrayObj, HashObj, Object};
+use std::rc::Rc;
+
+/// Parses a JSON document, with integral numbers becoming integers
+pub fn parse(input: &str) -> Result<Object, String> {
+    let mut parser = Parser {
+        chars: input.chars().collect(),
+        pos: 0,
+        depth: 0,
+    };
+    parser.skip_whitespace();
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.pos < parser.chars.len() {
+        return Err(parser.error("unexpected trailing characters"));
+    }
+    Ok(value)
+}
+
+/// Encodes a value as compact JSON, or indented by `indent` spaces per level
+pub fn stringify(value: &Object, indent: Option<usize>) -> Result<String, String> {
+    let mut out = String::new();
+    write_value(&mut out, value, indent, 0)?;
+    Ok(out)
+}
+
+/// Deepest nesting of arrays and objects accepted by `parse`
+const MAX_DEPTH: usize = 128;
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    /// Arrays and objects being parsed around the current position
+    depth: usize,
+}
+
+impl Parser {
+    fn value(&mut self) -> Result<Object, String> {
+        match self.peek() {
+            Some('{') => self.nested(Self::object),
+            Some('[') => self.nested(Self::array),
+            Some('"') => self.string().map(Object::String),
+            Some('-' | '0'..='9') => self.number(),
+            Some('t') => self.literal("true", Object::Bool(true)),
+            Some('f') => self.literal("false", Object::Bool(false)),
+            Some('n') => self.literal("null", Object::Null),
+            Some(c) => Err(self.error(&format!("unexpected character {:?}", c))),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    /// Parses an array or object one level deeper, failing past `MAX_DEPTH`
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Object, String>) -> Result<Object, String> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("nesting too deep"));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn object(&mut self) -> Result<Object, String> {
+        self.pos += 1;
+        let mut hash = HashObj::default();
+        self.skip_whitespace();
+        if self.eat('}') {
+            return Ok(Object::Hash(hash));
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return Err(self.error("expected string key"));
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            self.skip_whitespace();
+            let value = self.value()?;
+            hash.insert(Rc::new(Object::String(key)), Rc::new(value));
+
+            self.skip_whitespace();
+            if self.eat('}') {
+                return Ok(Object::Hash(hash));
+            }
+            self.expect(',')?;
+        }
+    }
+
+    fn array(&mut self) -> Result<Object, String> {
+        self.pos += 1;
+        let mut elements = vec![];
+        self.skip_whitespace();
+        if self.eat(']') {
+            return Ok(Object::Array(ArrayObj { elements }));
+        }
+
+        loop {
+            self.skip_whitespace();
+            elements.push(Rc::new(self.value()?));
+            self.skip_whitespace();
+            if self.eat(']') {
+                return Ok(Object::Array(ArrayObj { elements }));
+            }
+            self.expect(',')?;
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.pos += 1;
+        let mut s = String::new();
+        loop {
+            let c = self
+                .next()
+                .ok_or_else(|| self.error("unterminated string"))?;
+            match c {
+                '"' => return Ok(s),
+                '\\' => s.push(self.escape()?),
+                c if (c as u32) < 0x20 => {
+                    return Err(self.error("control character in string"));
+                }
+                c => s.push(c),
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<char, String> {
+        match self.next() {
+            Some('"') => Ok('"'),
+            Some('\\') => Ok('\\'),
+            Some('/') => Ok('/'),
+            Some('b') => Ok('\u{8}'),
+            Some('f') => Ok('\u{c}'),
+            Some('n') => Ok('\n'),
+            Some('r') => Ok('\r'),
+            Some('t') => Ok('\t'),
+            Some('u') => {
+                let high = self.hex4()?;
+                let code = if (0xD800..0xDC00).contains(&high) {
+                    if !(self.eat('\\') && self.eat('u')) {
+                        return Err(self.error("unpaired surrogate"));
+                    }
+                    let low = self.hex4()?;
+                    if !(0xDC00..0xE000).contains(&low) {
+                        return Err(self.error("unpaired surrogate"));
+                    }
+                    0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+                } else {
+                    high
+                };
+                char::from_u32(code).ok_or_else(|| self.error("invalid unicode escape"))
+            }
+            _ => Err(self.error("invalid escape")),
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self
+                .next()
+                .and_then(|c| c.to_digit(16))
+                .ok_or_else(|| self.error("invalid unicode escape"))?;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    fn number(&mut self) -> Result<Object, String> {
+        let start = self.pos;
+        self.eat('-');
+        if !self.eat('0') && !self.digits() {
+            return Err(self.error("expected digit"));
+        }
+        let mut integral = true;
+        if self.eat('.') {
+            integral = false;
+            if !self.digits() {
+                return Err(self.error("expected digit"));
+            }
+        }
+        if self.eat('e') || self.eat('E') {
+            integral = false;
+            let _ = self.eat('+') || self.eat('-');
+            if !self.digits() {
+                return Err(self.error("expected digit"));
+            }
+        }
+
+        let text: String = self.chars[start..self.pos].iter().collect();
+        match text.parse() {
+            Ok(i) if integral => Ok(Object::Integer(i)),
+            _ => Ok(Object::Float(text.parse().unwrap())),
+        }
+    }
+
+    /// Consumes digits, returning whether there was any
+    fn digits(&mut self) -> bool {
+        let start = self.pos;
+        while matches!(self.peek(), Some('0'..='9')) {
+            self.pos += 1;
+        }
+        self.pos > start
+    }
+
+    fn literal(&mut self, word: &str, value: Object) -> Result<Object, String> {
+        for c in word.chars() {
+            if !self.eat(c) {
+                return Err(self.error(&format!("expected {}", word)));
+            }
+        }
+        Ok(value)
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected {:?}", c)))
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(c);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.pos += 1;
+        c
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn error(&self, msg: &str) -> String {
+        let before = &self.chars[..self.pos.min(self.chars.len())];
+        let line = before.iter().filter(|c| **c == '\n').count() + 1;
+        let column = before.iter().rev().take_while(|c| **c != '\n').count() + 1;
+        format!("invalid JSON: {} at line {}, column {}", msg, line, column)
+    }
+}
+
+fn write_value(
+    out: &mut String,
+    value: &Object,
+    indent: Option<usize>,
+    depth: usize,
+) -> Result<(), String> {
+    match value {
+        Object::Null => out.push_str("null"),
+        Object::Bool(b) => out.push_str(&b.to_string()),
+        Object::Integer(i) => out.push_str(&i.to_string()),
+        Object::Float(x) if x.is_finite() => out.push_str(&value.to_string()),
+        Object::Float(x) => return Err(format!("cannot convert {} to JSON", x)),
+        Object::String(s) => write_string(out, s),
+        Object::Array(a) => {
+            let items = a.elements.iter().map(|e| (None, &**e));
+            write_items(out, ('[', ']'), items, indent, depth)?;
+        }
+        Object::Range(r) => {
+            let items = r.collect()?;
+            let items = items.iter().map(|i| (None, &**i));
+            write_items(out, ('[', ']'), items, indent, depth)?;
+        }
+        Object::Hash(h) => {
+            let items = h.iter().map(|(k, v)| (Some(&**k), &**v));
+            write_items(out, ('{', '}'), items, indent, depth)?;
+        }
+        o => return Err(format!("cannot convert {} to JSON", o.kind())),
+    }
+    Ok(())
+}
+
+/// Writes array elements, or hash pairs when they have a key
+fn write_items<'a>(
+    out: &mut String,
+    (open, close): (char, char),
+    items: impl ExactSizeIterator<Item = (Option<&'a Object>, &'a Object)>,
+    indent: Option<usize>,
+    depth: usize,
+) -> Result<(), String> {
+    out.push(open);
+    let empty = items.len() == 0;
+    for (idx, (key, value)) in items.enumerate() {
+        if idx > 0 {
+            out.push(',');
+        }
+        newline(out, indent, depth + 1);
+        if let Some(key) = key {
+            match key {
+                Object::String(s) => write_string(out, s),
+                k => write_string(out, &k.to_string()),
+            }
+            out.push(':');
+            if indent.is_some() {
+                out.push(' ');
+            }
+        }
+        write_value(out, value, indent, depth + 1)?;
+    }
+    if !empty {
+        newline(out, indent, depth);
+    }
+    out.push(close);
+    Ok(())
+}
+
+fn newline(out: &mut String, indent: Option<usize>, depth: usize) {
+    if let Some(indent) = indent {
+        out.push('\n');
+        out.push_str(&" ".repeat(indent * depth));
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let input = r#"{"b": [1, -2.5, 1e2, true, null], "a": {"s": "x\"\u00e9\ud83d\ude00\n"}}"#;
+        let value = parse(input).unwrap();
+        assert_eq!(
+            stringify(&value, None).unwrap(),
+            r#"{"b":[1,-2.5,100.0,true,null],"a":{"s":"x\"é😀\n"}}"#
+        );
+        assert_eq!(
+            stringify(&value, Some(2)).unwrap(),
+            "{\n  \"b\": [\n    1,\n    -2.5,\n    100.0,\n    true,\n    null\n  ],\n  \"a\": {\n    \"s\": \"x\\\"é😀\\n\"\n  }\n}"
+        );
+        assert_eq!(stringify(&parse(" [ ] ").unwrap(), Some(2)).unwrap(), "[]");
+        let deepest = "[".repeat(MAX_DEPTH) + &"]".repeat(MAX_DEPTH);
+        assert_eq!(stringify(&parse(&deepest).unwrap(), None).unwrap(), deepest);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let cases = [
+            ("", "unexpected end of input at line 1, column 1"),
+            ("[1,]", "unexpected character ']' at line 1, column 4"),
+            ("{\n  \"a\" 1}", "expected ':' at line 2, column 7"),
+            ("{1: 2}", "expected string key at line 1, column 2"),
+            ("\"abc", "unterminated string at line 1, column 5"),
+            ("01", "unexpected trailing characters at line 1, column 2"),
+            ("tru", "expected true at line 1, column 4"),
+            ("\"\\x\"", "invalid escape at line 1, column 4"),
+        ];
+        for (input, msg) in cases {
+            assert_eq!(
+                parse(input),
+                Err(format!("invalid JSON: {}", msg)),
+                "{}",
+                input
+            );
+        }
+        assert_eq!(
+            parse(&"[".repeat(200)),
+            Err("invalid JSON: nesting too deep at line 1, column 129".into())
+        );
+    }
+}
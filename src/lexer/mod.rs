@@ -7,6 +7,7 @@ pub struct Lexer {
     pos: usize,
     read_pos: usize,
     ch: char,
+    line: usize,
 }
 
 impl Lexer {
@@ -16,6 +17,7 @@ impl Lexer {
             pos: 0,
             read_pos: 0,
             ch: '\0',
+            line: 1,
         };
         s.read();
         s
@@ -24,11 +26,23 @@ impl Lexer {
     pub fn next(&mut self) -> Token {
         self.skip_whitespace();
 
+        let line = self.line;
+        let mut token = self.read_token();
+        token.line = line;
+        token
+    }
+}
+
+impl Lexer {
+    fn read_token(&mut self) -> Token {
         let token = match self.ch {
             '=' => {
                 if self.peek() == '=' {
                     self.read();
                     Token::new(TokenType::Eq, None)
+                } else if self.peek() == '>' {
+                    self.read();
+                    Token::new(TokenType::FatArrow, None)
                 } else {
                     Token::new(TokenType::Assign, None)
                 }
@@ -41,6 +55,30 @@ impl Lexer {
                     Token::new(TokenType::Bang, None)
                 }
             }
+            '.' => {
+                if self.peek() == '.' {
+                    self.read();
+                    if self.peek() == '.' {
+                        self.read();
+                        Token::new(TokenType::Ellipsis, None)
+                    } else if self.peek() == '=' {
+                        self.read();
+                        Token::new(TokenType::DotDotEq, None)
+                    } else {
+                        Token::new(TokenType::DotDot, None)
+                    }
+                } else {
+                    Token::new(TokenType::Dot, None)
+                }
+            }
+            '|' => {
+                if self.peek() == '>' {
+                    self.read();
+                    Token::new(TokenType::Pipe, None)
+                } else {
+                    Token::new(TokenType::Illegal, None)
+                }
+            }
             '+' => Token::new(TokenType::Plus, None),
             '-' => Token::new(TokenType::Minus, None),
             '/' => Token::new(TokenType::Slash, None),
@@ -92,20 +130,67 @@ impl Lexer {
     }
 
     fn read_string(&mut self) -> Token {
+        let mut parts = vec![];
+        let mut str = String::new();
+
+        loop {
+            self.read();
+            match self.ch {
+                '"' | '\0' => break,
+                '$' if self.peek() == '{' => {
+                    self.read();
+                    if !str.is_empty() {
+                        parts.push(TemplatePart::Str(std::mem::take(&mut str)));
+                    }
+                    parts.push(TemplatePart::Expr(self.read_interpolation()));
+                }
+                c => str.push(c),
+            }
+        }
+
+        if parts.is_empty() {
+            return Token::new(TokenType::String, Some(str));
+        }
+        if !str.is_empty() {
+            parts.push(TemplatePart::Str(str));
+        }
+        Token::template(parts)
+    }
+
+    /// Source of a `${...}` part, with the current char being `{`
+    fn read_interpolation(&mut self) -> String {
         let start = self.pos + 1;
+        let mut depth = 1;
 
         loop {
             self.read();
-            if self.ch == '"' || self.ch == '\0' {
-                break;
+            match self.ch {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                '"' => {
+                    // Nested string literal, which may contain braces
+                    self.read();
+                    while self.ch != '"' && self.ch != '\0' {
+                        self.read();
+                    }
+                }
+                '\0' => break,
+                _ => {}
             }
         }
 
-        let str: String = self.input[start..self.pos].iter().collect();
-        Token::new(TokenType::String, Some(str))
+        self.input[start..self.pos].iter().collect()
     }
 
     fn read(&mut self) {
+        if self.ch == '\n' {
+            self.line += 1;
+        }
         self.ch = if self.read_pos >= self.input.len() {
             '\0'
         } else {
@@ -148,6 +233,17 @@ fn keyword_or_ident(s: String) -> Token {
         "return" => Token::new(TokenType::Return, None),
         "true" => Token::new(TokenType::True, None),
         "false" => Token::new(TokenType::False, None),
+        "match" => Token::new(TokenType::Match, None),
+        "macro" => Token::new(TokenType::Macro, None),
+        "import" => Token::new(TokenType::Import, None),
+        "as" => Token::new(TokenType::As, None),
+        "export" => Token::new(TokenType::Export, None),
+        "throw" => Token::new(TokenType::Throw, None),
+        "try" => Token::new(TokenType::Try, None),
+        "catch" => Token::new(TokenType::Catch, None),
+        "finally" => Token::new(TokenType::Finally, None),
+        "for" => Token::new(TokenType::For, None),
+        "in" => Token::new(TokenType::In, None),
         _ => Token::new(TokenType::Ident, Some(s)),
     }
 }
@@ -162,6 +258,7 @@ mod test {
         Number(i64),
         Ident(String),
         String(String),
+        Template(Vec<TemplatePart>),
     }
 
     impl PartialEq<Token> for TestToken {
@@ -176,6 +273,10 @@ mod test {
                 TestToken::String(s) => {
                     other.ty == TokenType::String && other.literal == TokenLiteral::String(s.into())
                 }
+                TestToken::Template(p) => {
+                    other.ty == TokenType::Template
+                        && other.literal == TokenLiteral::Template(p.clone())
+                }
                 TestToken::Token(t) => other.ty == *t,
             }
         }
@@ -206,6 +307,13 @@ if (5 < 10) {
 "bar baz"
 [1, 2];
 {"foo": "bar"}
+match (x) { [a, ..b] => a }
+f(...args)
+a.b
+x |> f
+1..=n
+import "lib.monkey" as lib; export a;
+"a ${h["}"] + 1}b${x}"
         "#;
 
         let expected = vec![
@@ -303,6 +411,55 @@ if (5 < 10) {
             TestToken::Token(TokenType::Colon),
             TestToken::String("bar".into()),
             TestToken::Token(TokenType::RBrace),
+            //
+            TestToken::Token(TokenType::Match),
+            TestToken::Token(TokenType::LParen),
+            TestToken::Ident("x".into()),
+            TestToken::Token(TokenType::RParen),
+            TestToken::Token(TokenType::LBrace),
+            TestToken::Token(TokenType::LBracket),
+            TestToken::Ident("a".into()),
+            TestToken::Token(TokenType::Comma),
+            TestToken::Token(TokenType::DotDot),
+            TestToken::Ident("b".into()),
+            TestToken::Token(TokenType::RBracket),
+            TestToken::Token(TokenType::FatArrow),
+            TestToken::Ident("a".into()),
+            TestToken::Token(TokenType::RBrace),
+            //
+            TestToken::Ident("f".into()),
+            TestToken::Token(TokenType::LParen),
+            TestToken::Token(TokenType::Ellipsis),
+            TestToken::Ident("args".into()),
+            TestToken::Token(TokenType::RParen),
+            //
+            TestToken::Ident("a".into()),
+            TestToken::Token(TokenType::Dot),
+            TestToken::Ident("b".into()),
+            //
+            TestToken::Ident("x".into()),
+            TestToken::Token(TokenType::Pipe),
+            TestToken::Ident("f".into()),
+            //
+            TestToken::Number(1),
+            TestToken::Token(TokenType::DotDotEq),
+            TestToken::Ident("n".into()),
+            //
+            TestToken::Token(TokenType::Import),
+            TestToken::String("lib.monkey".into()),
+            TestToken::Token(TokenType::As),
+            TestToken::Ident("lib".into()),
+            TestToken::Token(TokenType::Semicolon),
+            TestToken::Token(TokenType::Export),
+            TestToken::Ident("a".into()),
+            TestToken::Token(TokenType::Semicolon),
+            //
+            TestToken::Template(vec![
+                TemplatePart::Str("a ".into()),
+                TemplatePart::Expr(r#"h["}"] + 1"#.into()),
+                TemplatePart::Str("b".into()),
+                TemplatePart::Expr("x".into()),
+            ]),
             TestToken::Token(TokenType::Eof),
         ];
 
@@ -4,6 +4,8 @@ use std::fmt::Display;
 pub struct Token {
     pub ty: TokenType,
     pub literal: TokenLiteral,
+    /// Line the token starts on, counted from 1 once lexed
+    pub line: usize,
 }
 
 impl Token {
@@ -14,6 +16,7 @@ impl Token {
                 Self {
                     ty,
                     literal: TokenLiteral::Ident(lit),
+                    line: 0,
                 }
             }
             TokenType::Number => {
@@ -24,6 +27,7 @@ impl Token {
                 Self {
                     ty,
                     literal: TokenLiteral::Num(lit),
+                    line: 0,
                 }
             }
             TokenType::String => {
@@ -31,11 +35,14 @@ impl Token {
                 Self {
                     ty,
                     literal: TokenLiteral::String(lit),
+                    line: 0,
                 }
             }
+            TokenType::Template => panic!("Template tokens are built with Token::template"),
             _ if literal.is_none() => Self {
                 literal: TokenLiteral::String(ty.to_string()),
                 ty,
+                line: 0,
             },
             _ => {
                 panic!("Token type: {:?} doesn't require any literal", ty)
@@ -44,6 +51,16 @@ impl Token {
     }
 }
 
+impl Token {
+    pub fn template(parts: Vec<TemplatePart>) -> Self {
+        Self {
+            ty: TokenType::Template,
+            literal: TokenLiteral::Template(parts),
+            line: 0,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum TokenType {
     Let,
@@ -53,10 +70,23 @@ pub enum TokenType {
     Return,
     True,
     False,
+    Match,
+    Macro,
+    Import,
+    As,
+    Export,
+    Throw,
+    Try,
+    Catch,
+    Finally,
+    For,
+    In,
 
     Ident,
     Number,
     String,
+    /// String containing `${expr}` parts
+    Template,
 
     Assign,
     Bang,
@@ -73,6 +103,12 @@ pub enum TokenType {
     RBrace,
     LBracket,
     RBracket,
+    FatArrow,
+    Dot,
+    DotDot,
+    DotDotEq,
+    Ellipsis,
+    Pipe,
 
     Lt,
     Gt,
@@ -96,9 +132,21 @@ impl Display for TokenType {
                 TokenType::Return => "return",
                 TokenType::True => "true",
                 TokenType::False => "false",
+                TokenType::Match => "match",
+                TokenType::Macro => "macro",
+                TokenType::Import => "import",
+                TokenType::As => "as",
+                TokenType::Export => "export",
+                TokenType::Throw => "throw",
+                TokenType::Try => "try",
+                TokenType::Catch => "catch",
+                TokenType::Finally => "finally",
+                TokenType::For => "for",
+                TokenType::In => "in",
                 TokenType::Ident => "ident",
                 TokenType::Number => "number",
                 TokenType::String => "string",
+                TokenType::Template => "template",
                 TokenType::Assign => "=",
                 TokenType::Bang => "!",
                 TokenType::Plus => "+",
@@ -114,6 +162,12 @@ impl Display for TokenType {
                 TokenType::RBrace => "}",
                 TokenType::LBracket => "[",
                 TokenType::RBracket => "]",
+                TokenType::FatArrow => "=>",
+                TokenType::Dot => ".",
+                TokenType::DotDot => "..",
+                TokenType::DotDotEq => "..=",
+                TokenType::Ellipsis => "...",
+                TokenType::Pipe => "|>",
                 TokenType::Lt => "<",
                 TokenType::Gt => ">",
                 TokenType::Eq => "==",
@@ -130,6 +184,14 @@ pub enum TokenLiteral {
     Ident(String),
     Num(i64),
     String(String),
+    Template(Vec<TemplatePart>),
+}
+
+/// Part of a template string, expressions are kept as source
+#[derive(Debug, Clone, PartialEq)]
+pub enum TemplatePart {
+    Str(String),
+    Expr(String),
 }
 
 impl TokenLiteral {
@@ -153,4 +215,11 @@ impl TokenLiteral {
             _ => None,
         }
     }
+
+    pub fn template(&self) -> Option<&[TemplatePart]> {
+        match self {
+            TokenLiteral::Template(parts) => Some(parts),
+            _ => None,
+        }
+    }
 }
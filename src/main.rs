@@ -1,40 +1,114 @@
 #![feature(variant_count)]
 
 use ast::Parser;
-use eval::{eval_program, Environment};
+use eval::{define_macros, eval_modules, eval_program, expand_macros, prelude_env, Environment};
 use lexer::Lexer;
+use policy::Policy;
+use std::{io::Write, path::Path};
 
 mod ast;
 mod builtin;
 mod compiler;
+mod diff;
 mod eval;
+mod exception;
+mod json;
 mod lexer;
+mod module;
+mod policy;
+mod prelude;
+mod random;
 mod repl;
+mod script;
+mod test_runner;
+mod time;
 mod vm;
 
+const USAGE: &str = "Usage: monkey [options] [file [args...]]
+       monkey [options] test [paths...]
+
+Options:
+  --seed N          seed the random builtins
+  --allow-dir DIR   allow file access under DIR, repeatable
+  --read-only       forbid writing and removing files
+  --allow-env       allow reading and setting environment variables
+  --allow-time      allow reading the clock and sleeping
+  --allow-run       allow running commands";
+
 fn main() {
-    let mut args = std::env::args().skip(1);
+    let mut args = std::env::args().skip(1).peekable();
+    let mut policy = Policy::default();
+
+    while let Some(opt) = args.next_if(|a| a.starts_with("--")) {
+        match opt.as_str() {
+            "--seed" => match args.next().and_then(|s| s.parse().ok()) {
+                Some(seed) => random::seed(seed),
+                None => usage(),
+            },
+            "--allow-dir" => match args.next() {
+                Some(dir) => policy.roots.push(dir.into()),
+                None => usage(),
+            },
+            "--read-only" => policy.read_only = true,
+            "--allow-env" => policy.env = true,
+            "--allow-time" => policy.time = true,
+            "--allow-run" => policy.run = true,
+            _ => usage(),
+        }
+    }
+    policy::set(policy);
 
-    match args.len() {
-        0 => repl::start(),
-        1 => {
-            let file = args.next().unwrap();
-            run(&file)
+    let Some(file) = args.next() else {
+        return repl::start();
+    };
+    if file == "test" {
+        let mut paths: Vec<String> = args.collect();
+        if paths.is_empty() {
+            paths.push(".".into());
+        }
+        match test_runner::run(&paths) {
+            Ok(true) => return,
+            Ok(false) => std::process::exit(1),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
         }
-        _ => println!("Usage: monkey [file]"),
     }
+    script::set_args(args.collect());
+    if let Err(e) = run(&file) {
+        let _ = std::io::stdout().flush();
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(2)
 }
 
-fn run(file: &str) {
-    let contents = std::fs::read_to_string(file).expect("Failed to open file");
+fn run(file: &str) -> Result<(), String> {
+    let contents =
+        std::fs::read_to_string(file).map_err(|e| format!("cannot open {}: {}", file, e))?;
 
     let lexer = Lexer::new(contents);
     let mut parser = Parser::new(lexer);
 
-    let env = Environment::new();
-    let program = parser.parse().unwrap();
+    let env = prelude_env();
+    let mut program = parser
+        .parse()
+        .map_err(|e| format!("Parse error: {:?}", e))?;
 
-    if let Err(e) = eval_program(program, &env) {
-        println!("Evaluation error: {}", e)
-    }
+    let macro_env = Environment::new();
+    define_macros(&mut program, &macro_env);
+    let mut program =
+        expand_macros(program, &macro_env).map_err(|e| format!("Macro expansion error: {}", e))?;
+
+    let dir = Path::new(file).parent().unwrap_or(Path::new("."));
+    let modules =
+        module::load_imports(&mut program, dir).map_err(|e| format!("Import error: {}", e))?;
+    eval_modules(modules, &env).map_err(|e| format!("Evaluation error: {}", e))?;
+    eval_program(program, &env).map_err(|e| format!("Evaluation error: {}", e))?;
+    Ok(())
 }
@@ -0,0 +1,181 @@
+use crate::{
+    ast::{Ident, Parser, Program, Statement},
+    eval::{define_macros, expand_macros, Environment},
+    lexer::Lexer,
+};
+use std::path::{Path, PathBuf};
+
+/// A file imported by the program, with its imports already resolved
+pub struct Module {
+    /// Canonical path, which is also the path of the imports referring to it
+    pub path: String,
+    pub program: Program,
+    pub exports: Vec<Ident>,
+}
+
+impl Module {
+    /// Name under which the exports of the module at `path` are bound
+    pub fn binding(path: &str) -> Ident {
+        format!("@{}", path)
+    }
+}
+
+/// Loads every module imported by `program`, directly or not, ordered so
+/// that each module comes after the ones it imports.
+/// Import paths are resolved relative to `dir`, then rewritten to canonical ones
+pub fn load_imports(program: &mut Program, dir: &Path) -> Result<Vec<Module>, String> {
+    let mut loader = Loader::default();
+    loader.resolve(program, dir)?;
+    Ok(loader.modules)
+}
+
+#[derive(Default)]
+struct Loader {
+    modules: Vec<Module>,
+    /// Files being loaded, used to detect cycles
+    loading: Vec<PathBuf>,
+}
+
+impl Loader {
+    fn resolve(&mut self, program: &mut Program, dir: &Path) -> Result<(), String> {
+        for stmt in &mut program.statements {
+            let Statement::Import(import) = stmt else {
+                continue;
+            };
+
+            let path = dir
+                .join(&import.path)
+                .canonicalize()
+                .map_err(|e| format!("cannot import {}: {}", import.path, e))?;
+            self.load(&path)?;
+            import.path = path.display().to_string();
+        }
+        Ok(())
+    }
+
+    fn load(&mut self, path: &Path) -> Result<(), String> {
+        let name = path.display().to_string();
+        if self.modules.iter().any(|m| m.path == name) {
+            return Ok(());
+        }
+        if let Some(start) = self.loading.iter().position(|p| p == path) {
+            let cycle: Vec<_> = self.loading[start..]
+                .iter()
+                .chain([&path.to_path_buf()])
+                .map(|p| p.display().to_string())
+                .collect();
+            return Err(format!("cyclic import: {}", cycle.join(" -> ")));
+        }
+
+        let contents =
+            std::fs::read_to_string(path).map_err(|e| format!("cannot import {}: {}", name, e))?;
+        let mut program = Parser::new(Lexer::new(contents))
+            .parse()
+            .map_err(|e| format!("cannot parse {}: {:?}", name, e))?;
+        // Macros are expanded within the file defining them, they are not exported
+        let macro_env = Environment::new();
+        define_macros(&mut program, &macro_env);
+        let mut program = expand_macros(program, &macro_env)
+            .map_err(|e| format!("cannot expand macros in {}: {}", name, e))?;
+
+        self.loading.push(path.to_path_buf());
+        self.resolve(&mut program, path.parent().unwrap_or(Path::new(".")))?;
+        self.loading.pop();
+
+        let exports = program
+            .statements
+            .iter()
+            .filter_map(|s| match s {
+                Statement::Export(e) => Some(e.names.clone()),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        self.modules.push(Module {
+            path: name,
+            program,
+            exports,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    /// Writes the files into a fresh directory named after the test
+    pub fn write_files(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("monkey-{}-{}", std::process::id(), test));
+        for (name, contents) in files {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    fn load(dir: &Path, input: &str) -> Result<(Program, Vec<Module>), String> {
+        let mut program = Parser::new(Lexer::new(input.into())).parse().unwrap();
+        let modules = load_imports(&mut program, dir)?;
+        Ok((program, modules))
+    }
+
+    #[test]
+    fn load_order() {
+        let dir = write_files(
+            "load_order",
+            &[
+                (
+                    "a.monkey",
+                    r#"import "lib/b.monkey" as b; import "c.monkey" as c;"#,
+                ),
+                ("lib/b.monkey", r#"import "../c.monkey" as c; export x, y;"#),
+                ("c.monkey", "export z;"),
+            ],
+        );
+
+        let (program, modules) =
+            load(&dir, r#"import "a.monkey" as a; import "c.monkey" as c;"#).unwrap();
+        let names: Vec<_> = modules
+            .iter()
+            .map(|m| Path::new(&m.path).file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(names, ["c.monkey", "b.monkey", "a.monkey"]);
+        assert_eq!(modules[1].exports, ["x", "y"]);
+
+        let c = dir.join("c.monkey").canonicalize().unwrap();
+        assert_eq!(
+            program.statements[1].to_string(),
+            format!("import \"{}\" as c;", c.display())
+        );
+    }
+
+    #[test]
+    fn load_errors() {
+        let dir = write_files(
+            "load_errors",
+            &[
+                ("a.monkey", r#"import "b.monkey" as b;"#),
+                ("b.monkey", r#"import "a.monkey" as a;"#),
+            ],
+        );
+        let path = |name: &str| dir.join(name).canonicalize().unwrap().display().to_string();
+
+        let err = load(&dir, r#"import "a.monkey" as a;"#).err().unwrap();
+        assert_eq!(
+            err,
+            format!(
+                "cyclic import: {} -> {} -> {}",
+                path("a.monkey"),
+                path("b.monkey"),
+                path("a.monkey")
+            )
+        );
+
+        let err = load(&dir, r#"import "missing.monkey" as m;"#)
+            .err()
+            .unwrap();
+        assert!(err.starts_with("cannot import missing.monkey"), "{}", err);
+    }
+}
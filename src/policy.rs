@@ -0,0 +1,116 @@
+use std::{
+    cell::RefCell,
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+/// What scripts may access outside the interpreter, denying everything by default
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    /// Directories whose files can be accessed
+    pub roots: Vec<PathBuf>,
+    /// Forbids writing and removing files, even under the roots
+    pub read_only: bool,
+    /// Allows reading and setting environment variables
+    pub env: bool,
+    /// Allows reading the clock and sleeping
+    pub time: bool,
+    /// Allows spawning subprocesses
+    pub run: bool,
+}
+
+/// Capability which is either allowed or not, regardless of its arguments
+#[derive(Debug, Clone, Copy)]
+pub enum Capability {
+    Env,
+    Time,
+    Run,
+}
+
+thread_local! {
+    static POLICY: RefCell<Policy> = RefCell::default();
+}
+
+/// Replaces the policy used by the following builtin calls
+pub fn set(policy: Policy) {
+    POLICY.with(|p| *p.borrow_mut() = policy);
+}
+
+/// Fails when the policy does not grant the capability
+pub fn check(cap: Capability) -> Result<(), String> {
+    POLICY.with(|p| {
+        let policy = p.borrow();
+        let (allowed, name) = match cap {
+            Capability::Env => (policy.env, "env"),
+            Capability::Time => (policy.time, "time"),
+            Capability::Run => (policy.run, "run"),
+        };
+        if allowed {
+            Ok(())
+        } else {
+            Err(format!("access denied: {} capability is disabled", name))
+        }
+    })
+}
+
+/// Resolves a path given to a file builtin, failing when the policy does not allow it
+pub fn check_path(path: &str, write: bool) -> Result<PathBuf, String> {
+    POLICY.with(|p| {
+        let policy = p.borrow();
+        if write && policy.read_only {
+            return Err(format!("access denied: {} (read-only mode)", path));
+        }
+
+        let resolved =
+            resolve(Path::new(path)).map_err(|e| format!("cannot resolve {}: {}", path, e))?;
+        let allowed = policy
+            .roots
+            .iter()
+            .filter_map(|root| root.canonicalize().ok())
+            .any(|root| resolved.starts_with(root));
+        if allowed {
+            Ok(resolved)
+        } else {
+            Err(format!(
+                "access denied: {} is outside the allowed roots",
+                path
+            ))
+        }
+    })
+}
+
+/// Canonical path, whose trailing components may not exist yet. `.` and `..`
+/// are resolved lexically, then links in the existing part are followed
+fn resolve(path: &Path) -> io::Result<PathBuf> {
+    let mut normal = std::env::current_dir()?;
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normal.pop();
+            }
+            c => normal.push(c),
+        }
+    }
+
+    let mut missing = vec![];
+    let mut existing = normal.as_path();
+    while existing.symlink_metadata().is_err() {
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                missing.push(name);
+                existing = parent;
+            }
+            _ => return Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+    match existing.canonicalize() {
+        Ok(mut resolved) => {
+            resolved.extend(missing.iter().rev());
+            Ok(resolved)
+        }
+        // Writing through a dangling link would create its target, wherever it is
+        Err(_) if existing.is_symlink() => Err(io::Error::other("dangling symbolic link")),
+        Err(e) => Err(e),
+    }
+}
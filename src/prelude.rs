@@ -0,0 +1,13 @@
+use crate::{
+    ast::{Parser, Program},
+    lexer::Lexer,
+};
+
+/// Standard library written in Monkey, defined before any user code
+const SOURCE: &str = include_str!("prelude.monkey");
+
+pub fn program() -> Program {
+    Parser::new(Lexer::new(SOURCE.into()))
+        .parse()
+        .expect("Prelude should parse")
+}
@@ -0,0 +1,67 @@
+use std::{
+    cell::Cell,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+thread_local! {
+    /// SplitMix64 state shared by both engines, seeded from the clock unless set
+    static STATE: Cell<u64> = Cell::new(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64),
+    );
+}
+
+/// Makes the following random builtins reproducible
+pub fn seed(seed: u64) {
+    STATE.with(|s| s.set(seed));
+}
+
+pub fn next_u64() -> u64 {
+    let state = STATE.with(|s| {
+        let next = s.get().wrapping_add(0x9E3779B97F4A7C15);
+        s.set(next);
+        next
+    });
+
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+/// Uniform float in `0..1`
+pub fn next_float() -> f64 {
+    (next_u64() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Uniform integer in `0..n`, without modulo bias
+pub fn below(n: u64) -> u64 {
+    assert!(n > 0, "Cannot pick a number below 0");
+    let limit = u64::MAX - u64::MAX % n;
+    loop {
+        let x = next_u64();
+        if x < limit {
+            return x % n;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn seeded_sequence() {
+        seed(42);
+        let first: Vec<_> = (0..5).map(|_| next_u64()).collect();
+        seed(42);
+        let second: Vec<_> = (0..5).map(|_| next_u64()).collect();
+        assert_eq!(first, second);
+
+        for _ in 0..100 {
+            assert!(below(3) < 3);
+            assert!((0.0..1.0).contains(&next_float()));
+        }
+    }
+}
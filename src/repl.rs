@@ -1,27 +1,45 @@
 use crate::{
     ast::Parser,
     compiler::{Compiler, SymbolTableRef},
-    eval::Object,
+    eval::{define_macros, expand_macros, Environment, Object},
     lexer::Lexer,
+    module::load_imports,
     vm::Vm,
 };
-use std::io::Write;
+use std::{cell::RefCell, io::Write, path::Path, rc::Rc};
+
+/// Symbol table and constants carried from one input to the next
+type CompState = (SymbolTableRef, Vec<Object>);
 
 pub fn start() {
-    let mut comp_state = None;
-    let mut vm_state = None;
+    let (comp, globals) = prelude().expect("Prelude should run");
+    let mut comp_state = Some(comp);
+    let mut vm_state = Some(globals);
+    let macro_env = Environment::new();
 
     loop {
-        match run(&mut comp_state, &mut vm_state) {
+        match run(&mut comp_state, &mut vm_state, &macro_env) {
             Ok(o) => println!("{}", o),
             Err(s) => println!("Errors: {}", s),
         }
     }
 }
 
+/// Compiles and runs the prelude once, giving the state every input starts from
+fn prelude() -> Result<(CompState, Vec<Object>), String> {
+    let mut comp = Compiler::default();
+    comp.compile_prelude()?;
+    let state = comp.state();
+
+    let mut vm = Vm::new(comp.bytecode());
+    vm.run()?;
+    Ok((state, vm.state()))
+}
+
 fn run(
-    comp_state: &mut Option<(SymbolTableRef, Vec<Object>)>,
+    comp_state: &mut Option<CompState>,
     vm_state: &mut Option<Vec<Object>>,
+    macro_env: &Rc<RefCell<Environment>>,
 ) -> Result<Object, String> {
     print!("> ");
     std::io::stdout().flush().unwrap();
@@ -32,17 +50,21 @@ fn run(
     let lexer = Lexer::new(input);
     let mut parser = Parser::new(lexer);
 
-    let program = parser.parse().map_err(|e| {
+    let mut program = parser.parse().map_err(|e| {
         e.into_iter().fold(String::new(), |mut acc, e| {
             acc += &format!("{:?}", e);
             acc
         })
     })?;
+    define_macros(&mut program, macro_env);
+    let mut program = expand_macros(program, macro_env)?;
+    let modules = load_imports(&mut program, Path::new("."))?;
 
     let mut comp = match comp_state {
         Some((s, c)) => Compiler::new_with_state(s.clone(), c.clone()),
         None => Compiler::default(),
     };
+    comp.compile_modules(modules)?;
     comp.compile(program)?;
     comp_state.replace(comp.state());
 
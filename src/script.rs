@@ -0,0 +1,15 @@
+use std::cell::RefCell;
+
+thread_local! {
+    /// Command line arguments following the script file
+    static ARGS: RefCell<Vec<String>> = RefCell::default();
+}
+
+/// Sets the arguments returned by `args()`
+pub fn set_args(args: Vec<String>) {
+    ARGS.with(|a| *a.borrow_mut() = args);
+}
+
+pub fn args() -> Vec<String> {
+    ARGS.with(|a| a.borrow().clone())
+}
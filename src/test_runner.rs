@@ -0,0 +1,194 @@
+use crate::{
+    ast::{Parser, Statement},
+    builtin::Caller,
+    compiler::{symbol_table::Scope, Bytecode, Compiler},
+    eval::{define_macros, expand_macros, Environment},
+    exception,
+    lexer::Lexer,
+    module,
+    vm::Vm,
+};
+use std::path::{Path, PathBuf};
+
+/// Name, line and global index of a test function
+type Test = (String, usize, usize);
+
+struct Failure {
+    /// `file:line` of the failing code, or the file when it could not be loaded
+    location: String,
+    name: String,
+    error: String,
+}
+
+/// Runs every `test_*` function declared in the `*_test.monkey` files under
+/// `paths`, each in a fresh VM, returning whether all of them passed
+pub fn run(paths: &[String]) -> Result<bool, String> {
+    let mut files = vec![];
+    for path in paths {
+        find_files(Path::new(path), &mut files)?;
+    }
+    files.sort();
+
+    let mut passed = 0;
+    let mut failures = vec![];
+    for file in &files {
+        let display = file.display().to_string();
+        let (bytecode, tests) = match load(file) {
+            Ok(t) => t,
+            Err(error) => {
+                println!("test {} ... FAILED", display);
+                failures.push(Failure {
+                    location: display,
+                    name: String::new(),
+                    error,
+                });
+                continue;
+            }
+        };
+        for (name, line, index) in tests {
+            match run_test(bytecode.clone(), index) {
+                Ok(()) => {
+                    println!("test {}::{} ... ok", display, name);
+                    passed += 1;
+                }
+                Err(error) => {
+                    println!("test {}::{} ... FAILED", display, name);
+                    let line = exception::line(&error).unwrap_or(line);
+                    failures.push(Failure {
+                        location: format!("{}:{}", display, line),
+                        name,
+                        error,
+                    });
+                }
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        println!("\nfailures:");
+        for f in &failures {
+            println!("\n---- {} {} ----\n{}", f.location, f.name, f.error);
+        }
+    }
+    println!(
+        "\ntest result: {}. {} passed; {} failed",
+        if failures.is_empty() { "ok" } else { "FAILED" },
+        passed,
+        failures.len()
+    );
+    Ok(failures.is_empty())
+}
+
+/// Collects test files, taking explicitly given files whatever their name
+fn find_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<(), String> {
+    if !path.is_dir() {
+        if !path.exists() {
+            return Err(format!("cannot find {}", path.display()));
+        }
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+
+    let entries = path
+        .read_dir()
+        .map_err(|e| format!("cannot list {}: {}", path.display(), e))?;
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("cannot list {}: {}", path.display(), e))?;
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+        if path.is_dir() {
+            find_files(&path, files)?;
+        } else if name.ends_with("_test.monkey") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Compiles a test file along with the tests it declares
+fn load(file: &Path) -> Result<(Bytecode, Vec<Test>), String> {
+    let contents = std::fs::read_to_string(file)
+        .map_err(|e| format!("cannot open {}: {}", file.display(), e))?;
+    let mut program = Parser::new(Lexer::new(contents))
+        .parse()
+        .map_err(|e| format!("Parse error: {:?}", e))?;
+
+    let macro_env = Environment::new();
+    define_macros(&mut program, &macro_env);
+    let mut program =
+        expand_macros(program, &macro_env).map_err(|e| format!("Macro expansion error: {}", e))?;
+    let dir = file.parent().unwrap_or(Path::new("."));
+    let modules =
+        module::load_imports(&mut program, dir).map_err(|e| format!("Import error: {}", e))?;
+
+    let names: Vec<_> = program
+        .statements
+        .iter()
+        .filter_map(|s| match s {
+            Statement::Func(f) if f.name.starts_with("test_") => Some((f.name.clone(), f.line)),
+            _ => None,
+        })
+        .collect();
+
+    let mut compiler = Compiler::default();
+    compiler.compile_prelude()?;
+    compiler.compile_modules(modules)?;
+    compiler.compile(program)?;
+    let (symbols, _) = compiler.state();
+
+    let mut tests = vec![];
+    for (name, line) in names {
+        match symbols.borrow_mut().resolve(&name) {
+            Some(sym) if sym.scope == Scope::Global => tests.push((name, line, sym.index as usize)),
+            _ => return Err(format!("test {} is not a global function", name)),
+        }
+    }
+    Ok((compiler.bytecode(), tests))
+}
+
+/// Runs the file's top level, then calls the test function stored in the global
+fn run_test(bytecode: Bytecode, index: usize) -> Result<(), String> {
+    let mut vm = Vm::new(bytecode);
+    vm.run()?;
+    let func = vm.global(index).clone();
+    vm.call_func(&func, vec![]).map(|_| ())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::module::test::write_files;
+
+    #[test]
+    fn run_tests() {
+        let dir = write_files(
+            "run_tests",
+            &[
+                ("lib.monkey", "fn double(x) { x * 2 } export double;"),
+                (
+                    "a/lib_test.monkey",
+                    "import \"../lib.monkey\" as lib;\nfn test_double() { assert_eq(lib.double(2), 4) }\n\nfn test_fails() {\n  assert_eq(lib.double(2), 5)\n}\nfn helper() { assert(false) }",
+                ),
+                ("a/skipped.monkey", "fn test_skipped() { assert(false) }"),
+            ],
+        );
+
+        let (bytecode, tests) = load(&dir.join("a/lib_test.monkey")).unwrap();
+        let names: Vec<_> = tests.iter().map(|(n, l, _)| (n.as_str(), *l)).collect();
+        assert_eq!(names, [("test_double", 2), ("test_fails", 4)]);
+        assert_eq!(run_test(bytecode.clone(), tests[0].2), Ok(()));
+        assert_eq!(
+            run_test(bytecode, tests[1].2),
+            Err("assertion failed\n- 4\n+ 5".into())
+        );
+        assert_eq!(exception::line("assertion failed\n- 4\n+ 5"), Some(5));
+
+        let mut files = vec![];
+        find_files(&dir, &mut files).unwrap();
+        assert_eq!(files, [dir.join("a/lib_test.monkey")]);
+        assert_eq!(run(&[dir.display().to_string()]), Ok(false));
+    }
+}
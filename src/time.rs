@@ -0,0 +1,85 @@
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+thread_local! {
+    /// Origin of `monotonic()`
+    static START: Instant = Instant::now();
+}
+
+/// Milliseconds since the Unix epoch
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64)
+}
+
+/// Milliseconds since the first call, which never go backwards
+pub fn monotonic() -> f64 {
+    START.with(|start| start.elapsed().as_secs_f64() * 1000.0)
+}
+
+/// Formats milliseconds since the epoch as UTC with `%Y %m %d %H %M %S %L %%`
+pub fn format(ms: i64, fmt: &str) -> Result<String, String> {
+    let days = ms.div_euclid(86_400_000);
+    let ms_of_day = ms.rem_euclid(86_400_000);
+    let (year, month, day) = civil_from_days(days);
+
+    let mut out = String::new();
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => out += &format!("{:04}", year),
+            Some('m') => out += &format!("{:02}", month),
+            Some('d') => out += &format!("{:02}", day),
+            Some('H') => out += &format!("{:02}", ms_of_day / 3_600_000),
+            Some('M') => out += &format!("{:02}", ms_of_day / 60_000 % 60),
+            Some('S') => out += &format!("{:02}", ms_of_day / 1000 % 60),
+            Some('L') => out += &format!("{:03}", ms_of_day % 1000),
+            Some('%') => out.push('%'),
+            Some(c) => return Err(format!("unknown time format specifier %{}", c)),
+            None => return Err("time format ends with %".into()),
+        }
+    }
+    Ok(out)
+}
+
+/// Gregorian date of a day counted from 1970-01-01
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn format_dates() {
+        let iso = "%Y-%m-%dT%H:%M:%S.%LZ";
+        assert_eq!(format(0, iso).unwrap(), "1970-01-01T00:00:00.000Z");
+        assert_eq!(
+            format(951_782_400_123, iso).unwrap(),
+            "2000-02-29T00:00:00.123Z"
+        );
+        assert_eq!(
+            format(1_700_000_000_000, "%d/%m/%Y %H:%M %%").unwrap(),
+            "14/11/2023 22:13 %"
+        );
+        assert_eq!(format(-1, iso).unwrap(), "1969-12-31T23:59:59.999Z");
+        assert_eq!(
+            format(0, "%q"),
+            Err("unknown time format specifier %q".into())
+        );
+    }
+}
@@ -1,11 +1,12 @@
 #![allow(dead_code)]
 
-use std::rc::Rc;
+use std::{cell::RefCell, rc::Rc};
 
 use crate::{
-    builtin::Builtin,
+    builtin::{Builtin, Caller},
     compiler::{Bytecode, Bytes, OpCode},
-    eval::{CompiledFuncObj, Object},
+    eval::{char_at, splice, ClosureObj, CompiledFuncObj, HashObj, Object, RangeObj},
+    exception,
 };
 
 const STACK_SIZE: usize = 2048;
@@ -13,8 +14,26 @@ const GLOBALS_SIZE: usize = 0xFFFF;
 
 struct Frame {
     func: Rc<CompiledFuncObj>,
+    /// Set when `func` was called through a closure
+    closure: Option<Rc<ClosureObj>>,
     ip: usize,
     sp: usize,
+    /// Number of arguments the function was called with
+    args: usize,
+}
+
+/// Handler set up by a `try`, where errors raised before its end are caught
+struct Handler {
+    /// Index of the frame running the `try`
+    frame: usize,
+    /// Stack pointer when the `try` started
+    sp: usize,
+    /// Instruction the error value is pushed for
+    catch: usize,
+    /// Line of the `try`, where errors without a line of their own are reported
+    line: usize,
+    /// Whether the handler runs a `finally` block, which gets the error set aside
+    finally: bool,
 }
 
 pub struct Vm {
@@ -26,18 +45,20 @@ pub struct Vm {
     sp: usize,
 
     frames: Vec<Frame>,
+    handlers: Vec<Handler>,
 }
 
 impl Vm {
     pub fn new(b: Bytecode) -> Self {
         let frame = Frame {
             func: Rc::new(CompiledFuncObj {
-                instructions: b.instructions,
-                locals: 0,
-                params: 0,
+                lines: b.lines,
+                ..CompiledFuncObj::new(b.instructions, 0, 0)
             }),
+            closure: None,
             ip: 0,
             sp: 0,
+            args: 0,
         };
         Vm {
             // instructions: b.instructions,
@@ -48,6 +69,7 @@ impl Vm {
             sp: 0,
 
             frames: vec![frame],
+            handlers: vec![],
         }
     }
 
@@ -56,12 +78,13 @@ impl Vm {
 
         let frame = Frame {
             func: Rc::new(CompiledFuncObj {
-                instructions: b.instructions,
-                locals: 0,
-                params: 0,
+                lines: b.lines,
+                ..CompiledFuncObj::new(b.instructions, 0, 0)
             }),
+            closure: None,
             ip: 0,
             sp: 0,
+            args: 0,
         };
 
         Self {
@@ -71,6 +94,7 @@ impl Vm {
             sp: 0,
 
             frames: vec![frame],
+            handlers: vec![],
         }
     }
 
@@ -78,135 +102,387 @@ impl Vm {
         self.globals.clone()
     }
 
+    pub fn global(&self, index: usize) -> &Object {
+        &self.globals[index]
+    }
+
     pub fn run(&mut self) -> RunResult {
-        while self.ip() < self.instructions().len() {
-            let op: OpCode = self.instructions().read(self.ip());
-            *self.ip_mut() += 1;
-
-            match op {
-                OpCode::Constant => {
-                    let const_idx: u16 = self.instructions().read(self.ip());
-                    *self.ip_mut() += 2;
-                    self.push(self.constants[const_idx as usize].clone())?;
-                }
-                OpCode::Add
-                | OpCode::Sub
-                | OpCode::Mul
-                | OpCode::Div
-                | OpCode::Greater
-                | OpCode::Eq
-                | OpCode::NotEq => self.execute_bin_op(op)?,
-                OpCode::Pop => {
-                    self.pop();
-                }
-                OpCode::True => self.push(Object::Bool(true))?,
-                OpCode::False => self.push(Object::Bool(false))?,
-                OpCode::Minus => {
-                    let right = self.pop();
-                    match right {
-                        Object::Integer(right) => self.push(Object::Integer(-right))?,
-                        _ => return Err(format!("unknown operator: -{}", right.kind())),
-                    }
-                }
-                OpCode::Bang => {
-                    let right = self.pop();
-                    self.push(Object::Bool(!right.is_truthy()))?
-                }
-                OpCode::JumpNotTrue => {
-                    let jmp_to: u16 = self.instructions().read(self.ip());
-                    *self.ip_mut() += 2;
+        exception::reset();
+        self.run_frames(0)
+    }
 
-                    let cond = self.pop();
-                    if !cond.is_truthy() {
-                        *self.ip_mut() = jmp_to as usize;
-                    }
+    /// Runs until the number of frames drops to `depth`, or the main frame ends
+    fn run_frames(&mut self, depth: usize) -> RunResult {
+        while self.frames.len() > depth && self.ip() < self.instructions().len() {
+            let (frame, ip) = (self.frames.len() - 1, self.ip());
+            if let Err(err) = self.step() {
+                let line = self.frames.get(frame).and_then(|f| f.func.line_at(ip));
+                let err = match line {
+                    Some(line) => exception::locate(err, line),
+                    None => err,
+                };
+                self.unwind(err, depth)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Jumps to the innermost handler set up in a frame above `depth`, with
+    /// the caught error value on the stack
+    fn unwind(&mut self, err: String, depth: usize) -> RunResult {
+        let handler = match self.handlers.last() {
+            Some(h) if h.frame >= depth => self.handlers.pop().unwrap(),
+            _ => return Err(err),
+        };
+
+        self.frames.truncate(handler.frame + 1);
+        self.sp = handler.sp;
+        *self.ip_mut() = handler.catch;
+        if handler.finally {
+            return self.push(exception::suspend(err));
+        }
+        let error = exception::catch(err, handler.line);
+        self.push(Rc::unwrap_or_clone(error))
+    }
+
+    fn step(&mut self) -> RunResult {
+        let start = self.ip();
+        let op: OpCode = self.instructions().read(start);
+        *self.ip_mut() += 1;
+
+        match op {
+            OpCode::Constant => {
+                let const_idx: u16 = self.instructions().read(self.ip());
+                *self.ip_mut() += 2;
+                self.push(self.constants[const_idx as usize].clone())?;
+            }
+            OpCode::Add
+            | OpCode::Sub
+            | OpCode::Mul
+            | OpCode::Div
+            | OpCode::Greater
+            | OpCode::Eq
+            | OpCode::NotEq => self.execute_bin_op(op)?,
+            OpCode::Pop => {
+                self.pop();
+            }
+            OpCode::True => self.push(Object::Bool(true))?,
+            OpCode::False => self.push(Object::Bool(false))?,
+            OpCode::Minus => {
+                let right = self.pop();
+                match right {
+                    Object::Integer(right) => self.push_integer(right.checked_neg())?,
+                    Object::Float(right) => self.push(Object::Float(-right))?,
+                    _ => return Err(format!("unknown operator: -{}", right.kind())),
                 }
-                OpCode::Jump => {
-                    let jmp_to: u16 = self.instructions().read(self.ip());
+            }
+            OpCode::Bang => {
+                let right = self.pop();
+                self.push(Object::Bool(!right.is_truthy()))?
+            }
+            OpCode::JumpNotTrue => {
+                let jmp_to: u16 = self.instructions().read(self.ip());
+                *self.ip_mut() += 2;
+
+                let cond = self.pop();
+                if !cond.is_truthy() {
                     *self.ip_mut() = jmp_to as usize;
                 }
-                OpCode::SetGlobal => {
-                    let idx: u16 = self.instructions().read(self.ip());
-                    *self.ip_mut() += 2;
+            }
+            OpCode::JumpHasArg => {
+                let idx: u8 = self.instructions().read(self.ip());
+                let jmp_to: u16 = self.instructions().read(self.ip() + 1);
+                *self.ip_mut() += 3;
 
-                    self.globals[idx as usize] = self.pop();
+                if self.frame().args > idx as usize {
+                    *self.ip_mut() = jmp_to as usize;
                 }
-                OpCode::GetGlobal => {
-                    let idx: u16 = self.instructions().read(self.ip());
-                    *self.ip_mut() += 2;
+            }
+            OpCode::Jump => {
+                let jmp_to: u16 = self.instructions().read(self.ip());
+                *self.ip_mut() = jmp_to as usize;
+            }
+            OpCode::SetGlobal => {
+                let idx: u16 = self.instructions().read(self.ip());
+                *self.ip_mut() += 2;
 
-                    self.push(self.globals[idx as usize].clone())?
-                }
-                OpCode::Array => {
-                    let len: u16 = self.instructions().read(self.ip());
-                    let len = len as usize;
-                    *self.ip_mut() += 2;
-
-                    let mut arr = vec![Object::Null.into(); len];
-                    for i in (0..len).rev() {
-                        arr[i] = Rc::new(self.pop());
-                    }
+                self.globals[idx as usize] = self.pop();
+            }
+            OpCode::GetGlobal => {
+                let idx: u16 = self.instructions().read(self.ip());
+                *self.ip_mut() += 2;
 
-                    self.push(Object::Array(crate::eval::ArrayObj { elements: arr }))?
+                self.push(self.globals[idx as usize].clone())?
+            }
+            OpCode::Array => {
+                let len: u16 = self.instructions().read(self.ip());
+                let len = len as usize;
+                *self.ip_mut() += 2;
+
+                let mut arr = vec![Object::Null.into(); len];
+                for i in (0..len).rev() {
+                    arr[i] = Rc::new(self.pop());
                 }
-                OpCode::Hash => {
-                    let len: u16 = self.instructions().read(self.ip());
-                    let len = len as usize;
-                    *self.ip_mut() += 2;
-
-                    let mut pairs = vec![];
-                    for _ in 0..len {
-                        let v = Rc::new(self.pop());
-                        let k = Rc::new(self.pop());
-                        pairs.push((k, v));
+
+                self.push(Object::Array(crate::eval::ArrayObj { elements: arr }))?
+            }
+            OpCode::Hash => {
+                let len: u16 = self.instructions().read(self.ip());
+                let len = len as usize;
+                *self.ip_mut() += 2;
+
+                let mut pairs = vec![];
+                for _ in 0..len {
+                    let v = Rc::new(self.pop());
+                    let k = self.pop();
+                    if !k.is_hashable() {
+                        return Err(format!("unusable as hash key: {}", k.kind()));
                     }
-                    self.push(Object::Hash(crate::eval::HashObj {
-                        map: pairs.into_iter().collect(),
-                    }))?
+                    pairs.push((Rc::new(k), v));
                 }
-                OpCode::Index => {
-                    let index = self.pop();
-                    let left = self.pop();
-                    self.execute_index_op(left, index)?;
+                let hash: HashObj = pairs.into_iter().rev().collect();
+                self.push(Object::Hash(hash))?
+            }
+            OpCode::Index => {
+                let index = self.pop();
+                let left = self.pop();
+                self.execute_index_op(left, index)?;
+            }
+            OpCode::Slice => {
+                let end = self.pop();
+                let start = self.pop();
+                let left = self.pop();
+                self.push(left.slice(&start, &end)?)?;
+            }
+            OpCode::Template => {
+                let len: u16 = self.instructions().read(self.ip());
+                *self.ip_mut() += 2;
+
+                let start = self.sp - len as usize;
+                let res: String = self.stack[start..self.sp]
+                    .iter()
+                    .map(|o| o.to_string())
+                    .collect();
+                self.sp = start;
+                self.push(Object::String(res))?;
+            }
+            OpCode::Iter => {
+                let items = self.pop().iterable()?;
+                self.push(items)?;
+                self.push(Object::Integer(0))?;
+            }
+            OpCode::IterNext => {
+                let end: u16 = self.instructions().read(self.ip());
+                *self.ip_mut() += 2;
+
+                let index = match self.stack[self.sp - 1] {
+                    Object::Integer(i) => i,
+                    _ => return Err("for-in lost its index".into()),
+                };
+                match self.stack[self.sp - 2].iter_item(index as usize) {
+                    Some(item) => {
+                        self.stack[self.sp - 1] = Object::Integer(index + 1);
+                        self.push((*item).clone())?;
+                    }
+                    None => {
+                        self.sp -= 2;
+                        *self.ip_mut() = end as usize;
+                    }
                 }
-                OpCode::Call => {
-                    let args: u8 = self.instructions().read(self.ip());
-                    *self.ip_mut() += 1;
+            }
+            OpCode::Range => {
+                let inclusive: u8 = self.instructions().read(self.ip());
+                *self.ip_mut() += 1;
 
-                    self.execute_call(args)?;
-                }
-                OpCode::ReturnValue => {
-                    let val = self.pop();
-                    self.sp = self.pop_frame().sp - 1;
-                    self.push(val)?;
-                }
-                OpCode::Return => {
-                    self.sp = self.pop_frame().sp - 1;
-                    self.push(Object::Null)?;
+                let end = self.pop();
+                let start = self.pop();
+                self.push(Object::Range(RangeObj::new(&start, &end, inclusive != 0)?))?;
+            }
+            OpCode::MatchArray => {
+                let len: u16 = self.instructions().read(self.ip());
+                let has_rest: u8 = self.instructions().read(self.ip() + 2);
+                *self.ip_mut() += 3;
+
+                let matches = match self.pop() {
+                    Object::Array(a) if has_rest != 0 => a.elements.len() >= len as usize,
+                    Object::Array(a) => a.elements.len() == len as usize,
+                    _ => false,
+                };
+                self.push(Object::Bool(matches))?
+            }
+            OpCode::MatchKey => {
+                let key = self.pop();
+                let matches = match self.pop() {
+                    Object::Hash(h) => h.contains_key(&key),
+                    _ => false,
+                };
+                self.push(Object::Bool(matches))?
+            }
+            OpCode::ArrayRest => {
+                let start: u16 = self.instructions().read(self.ip());
+                *self.ip_mut() += 2;
+
+                match self.pop() {
+                    Object::Array(a) => {
+                        let elements = a.elements[start as usize..].to_vec();
+                        self.push(Object::Array(crate::eval::ArrayObj { elements }))?
+                    }
+                    o => return Err(format!("cannot take rest of {}", o.kind())),
                 }
-                OpCode::SetLocal => {
-                    let idx: u8 = self.instructions().read(self.ip());
-                    *self.ip_mut() += 1;
+            }
+            OpCode::DestructureFail => {
+                let pattern_idx: u16 = self.instructions().read(self.ip());
+                *self.ip_mut() += 2;
+
+                let value = self.pop();
+                return Err(format!(
+                    "cannot destructure {} with pattern {}",
+                    value.kind(),
+                    self.constants[pattern_idx as usize]
+                ));
+            }
+            OpCode::Call => {
+                let args: u8 = self.instructions().read(self.ip());
+                *self.ip_mut() += 1;
 
-                    let val = self.pop();
-                    self.stack[self.frame().sp + idx as usize] = val;
-                }
-                OpCode::GetLocal => {
-                    let idx: u8 = self.instructions().read(self.ip());
-                    *self.ip_mut() += 1;
+                self.execute_call(args as usize)?;
+            }
+            OpCode::CallSpread => {
+                let arrays: u8 = self.instructions().read(self.ip());
+                *self.ip_mut() += 1;
 
-                    let val = self.stack[self.frame().sp + idx as usize].clone();
-                    self.push(val)?;
+                let args = self.spread_args(arrays as usize)?;
+                self.execute_call(args)?;
+            }
+            OpCode::Method => {
+                let name: u16 = self.instructions().read(self.ip());
+                let args: u8 = self.instructions().read(self.ip() + 2);
+                let spread: u8 = self.instructions().read(self.ip() + 3);
+                *self.ip_mut() += 4;
+
+                let args = if spread != 0 {
+                    self.spread_args(args as usize)?
+                } else {
+                    args as usize
+                };
+                self.execute_method(name as usize, args)?;
+            }
+            OpCode::ReturnValue => {
+                let val = self.pop();
+                self.sp = self.pop_frame().sp - 1;
+                self.push(val)?;
+            }
+            OpCode::Return => {
+                self.sp = self.pop_frame().sp - 1;
+                self.push(Object::Null)?;
+            }
+            OpCode::SetLocal => {
+                let idx: u8 = self.instructions().read(self.ip());
+                *self.ip_mut() += 1;
+
+                let val = self.pop();
+                self.stack[self.frame().sp + idx as usize] = val;
+            }
+            OpCode::GetLocal => {
+                let idx: u8 = self.instructions().read(self.ip());
+                *self.ip_mut() += 1;
+
+                let val = self.stack[self.frame().sp + idx as usize].clone();
+                self.push(val)?;
+            }
+            OpCode::GetFree => {
+                let idx: u8 = self.instructions().read(self.ip());
+                *self.ip_mut() += 1;
+
+                let closure = self
+                    .frame()
+                    .closure
+                    .as_ref()
+                    .expect("free variable outside of a closure");
+                let val = closure.free.borrow()[idx as usize].clone();
+                self.push(val)?;
+            }
+            OpCode::SetFree => {
+                let idx: u8 = self.instructions().read(self.ip());
+                *self.ip_mut() += 1;
+
+                let val = self.pop();
+                match self.pop() {
+                    Object::Closure(c) => c.free.borrow_mut()[idx as usize] = val,
+                    o => return Err(format!("cannot set free variable of {}", o.kind())),
                 }
-                OpCode::GetBuiltin => {
-                    let idx: u8 = self.instructions().read(self.ip());
-                    *self.ip_mut() += 1;
+            }
+            OpCode::CurrentClosure => {
+                let current = match &self.frame().closure {
+                    Some(c) => Object::Closure(c.clone()),
+                    None => Object::CompiledFunc(self.frame().func.clone()),
+                };
+                self.push(current)?;
+            }
+            OpCode::Closure => {
+                let const_idx: u16 = self.instructions().read(self.ip());
+                let free: u8 = self.instructions().read(self.ip() + 2);
+                *self.ip_mut() += 3;
+
+                let func = match &self.constants[const_idx as usize] {
+                    Object::CompiledFunc(f) => f.clone(),
+                    o => return Err(format!("not a function: {}", o.kind())),
+                };
+                let start = self.sp - free as usize;
+                let free = self.stack[start..self.sp].to_vec();
+                self.sp = start;
+
+                self.push(Object::Closure(Rc::new(ClosureObj {
+                    func,
+                    free: RefCell::new(free),
+                })))?;
+            }
+            OpCode::GetBuiltin => {
+                let idx: u8 = self.instructions().read(self.ip());
+                *self.ip_mut() += 1;
 
-                    let builtin =
-                        Builtin::from_u8(idx).ok_or(&format!("unknown builtin {}", idx))?;
-                    self.push(Object::Builtin(builtin))?;
+                let builtin = Builtin::from_u8(idx).ok_or(&format!("unknown builtin {}", idx))?;
+                self.push(Object::Builtin(builtin))?;
+            }
+            op @ (OpCode::Try | OpCode::TryFinally) => {
+                let catch: u16 = self.instructions().read(self.ip());
+                *self.ip_mut() += 2;
+
+                self.handlers.push(Handler {
+                    frame: self.frames.len() - 1,
+                    sp: self.sp,
+                    catch: catch as usize,
+                    line: self.frame().func.line_at(start).unwrap_or_default(),
+                    finally: op == OpCode::TryFinally,
+                });
+            }
+            OpCode::EndTry => {
+                self.handlers.pop();
+            }
+            OpCode::Throw => {
+                let line = self.frame().func.line_at(start).unwrap_or_default();
+                let value = self.pop();
+                return Err(exception::throw(Rc::new(value), line));
+            }
+            OpCode::Quote => {
+                let quote: u16 = self.instructions().read(self.ip());
+                let count: u16 = self.instructions().read(self.ip() + 2);
+                *self.ip_mut() += 4;
+
+                let Object::Quote(expr) = self.constants[quote as usize].clone() else {
+                    unreachable!("OpQuote should point to a quote constant");
+                };
+                let mut values = vec![Object::Null.into(); count as usize];
+                for i in (0..count as usize).rev() {
+                    values[i] = Rc::new(self.pop());
                 }
-                _ => todo!(),
+                let mut values = values.into_iter();
+                let quote = splice(expr, |_| Ok(values.next().unwrap()))?;
+                self.push(Rc::unwrap_or_clone(quote))?;
+            }
+            OpCode::Rethrow => {
+                let suspended = self.pop();
+                return Err(exception::resume(&suspended));
             }
         }
 
@@ -229,7 +505,7 @@ impl Vm {
 impl Vm {
     fn push(&mut self, obj: Object) -> RunResult {
         if self.sp >= STACK_SIZE {
-            Err(format!("Stack overflow"))
+            Err("Stack overflow".to_string())
         } else {
             self.stack[self.sp] = obj;
             self.sp += 1;
@@ -243,43 +519,129 @@ impl Vm {
         obj
     }
 
-    fn execute_call(&mut self, args: u8) -> RunResult {
-        match self
-            .stack
-            .get(self.sp - 1 - args as usize)
-            .expect("nothing to call")
-        {
-            Object::CompiledFunc(c) => self.call_func(args, c.clone()),
+    fn execute_call(&mut self, args: usize) -> RunResult {
+        match self.stack.get(self.sp - 1 - args).expect("nothing to call") {
+            Object::CompiledFunc(c) => self.call_func(args, c.clone(), None),
+            Object::Closure(c) => self.call_func(args, c.func.clone(), Some(c.clone())),
             Object::Builtin(b) => self.call_builtin(args, *b),
-            o => return Err(format!("cannot call object {:?}", o)),
+            o => Err(format!("cannot call object {:?}", o)),
+        }
+    }
+
+    /// Flattens the arrays on top of the stack, returning the number of arguments
+    fn spread_args(&mut self, arrays: usize) -> Result<usize, String> {
+        let start = self.sp - arrays;
+        let arrays = self.stack[start..self.sp].to_vec();
+        self.sp = start;
+
+        let mut args = 0;
+        for arr in arrays {
+            match arr {
+                Object::Array(a) => {
+                    for el in a.elements {
+                        self.push(Rc::unwrap_or_clone(el))?;
+                        args += 1;
+                    }
+                }
+                o => return Err(format!("spread argument must be ARRAY, got {}", o.kind())),
+            }
         }
+        Ok(args)
     }
 
-    fn call_builtin(&mut self, args: u8, b: Builtin) -> RunResult {
-        let args: Vec<Object> = self.stack[(self.sp - args as usize)..self.sp]
-            .into_iter()
-            .map(|x| x.clone())
-            .collect();
+    /// Calls a function stored in a hash under the method name, or a builtin
+    /// method of the receiver, which is placed below the arguments
+    fn execute_method(&mut self, name: usize, args: usize) -> RunResult {
+        let name = match &self.constants[name] {
+            Object::String(s) => s.clone(),
+            o => return Err(format!("invalid method name: {}", o.kind())),
+        };
+        let receiver = self.sp - 1 - args;
+
+        if let Object::Hash(h) = &self.stack[receiver] {
+            if let Some(f) = h.get(&Object::String(name.clone())) {
+                self.stack[receiver] = (**f).clone();
+                return self.execute_call(args);
+            }
+        }
+
+        match Builtin::method(&self.stack[receiver], &name) {
+            Some(b) => {
+                self.push(Object::Builtin(b))?;
+                self.stack[receiver..self.sp].rotate_right(1);
+                self.execute_call(args + 1)
+            }
+            None => Err(format!(
+                "unknown method {} for {}",
+                name,
+                self.stack[receiver].kind()
+            )),
+        }
+    }
+
+    fn call_builtin(&mut self, args: usize, b: Builtin) -> RunResult {
+        let start = self.sp - args;
+        let args: Vec<Object> = self.stack[start..self.sp].to_vec();
         let a: Vec<&Object> = args.iter().collect();
 
-        let o: Object = b.call(a)?;
+        let o: Object = b.call(a, self)?;
+        self.sp = start - 1;
         self.push(o)
     }
 
-    fn call_func(&mut self, args: u8, func: Rc<CompiledFuncObj>) -> RunResult {
-        if args as usize != func.params {
+    fn call_func(
+        &mut self,
+        args: usize,
+        func: Rc<CompiledFuncObj>,
+        closure: Option<Rc<ClosureObj>>,
+    ) -> RunResult {
+        if args < func.required || (!func.rest && args > func.params) {
+            let expected = if func.rest {
+                format!("at least {}", func.required)
+            } else if func.required == func.params {
+                func.params.to_string()
+            } else {
+                format!("{} to {}", func.required, func.params)
+            };
             return Err(format!(
                 "wrong number of arguments. expected {}, got {}",
-                func.params, args
+                expected, args
             ));
         }
+
+        // Missing params get their default values from the function itself
+        for _ in args..func.params {
+            self.push(Object::Null)?;
+        }
+        if func.rest {
+            let start = self.sp - args.saturating_sub(func.params);
+            let elements = self.stack[start..self.sp]
+                .iter()
+                .cloned()
+                .map(Rc::new)
+                .collect();
+            self.sp = start;
+            self.push(Object::Array(crate::eval::ArrayObj { elements }))?;
+        }
+
+        let base = self.sp - func.params - func.rest as usize;
         let locals = func.locals;
+        if base + locals > STACK_SIZE {
+            return Err("Stack overflow".to_string());
+        }
+        // Hoisted declarations may capture locals before they are set
+        for slot in &mut self.stack[self.sp..base + locals] {
+            *slot = Object::Null;
+        }
+
         self.push_frame(Frame {
             func,
+            closure,
             ip: 0,
-            sp: self.sp - args as usize,
+            sp: base,
+            args,
         });
-        self.sp += locals;
+        self.sp = base + locals;
         Ok(())
     }
 
@@ -287,15 +649,22 @@ impl Vm {
         match (&left, &index) {
             (Object::Array(a), Object::Integer(i)) => {
                 let el = a
-                    .elements
-                    .get(*i as usize)
+                    .get(*i)
                     .map(|i| Rc::unwrap_or_clone(i.clone()))
                     .unwrap_or(Object::Null);
                 self.push(el)
             }
+            (Object::Range(r), Object::Integer(i)) => {
+                self.push(r.get(*i).map_or(Object::Null, Object::Integer))
+            }
+            (Object::String(s), Object::Integer(i)) => {
+                self.push(char_at(s, *i).map_or(Object::Null, Object::String))
+            }
+            (Object::Hash(_), _) if !index.is_hashable() => {
+                Err(format!("unusable as hash key: {}", index.kind()))
+            }
             (Object::Hash(h), _) => {
                 let el = h
-                    .map
                     .get(&index)
                     .map(|i| Rc::unwrap_or_clone(i.clone()))
                     .unwrap_or(Object::Null);
@@ -315,17 +684,38 @@ impl Vm {
 
         match (&left, &right) {
             (Object::Integer(left), Object::Integer(right)) => match op {
-                OpCode::Add => self.push(Object::Integer(left + right)),
-                OpCode::Sub => self.push(Object::Integer(left - right)),
-                OpCode::Mul => self.push(Object::Integer(left * right)),
-                OpCode::Div => self.push(Object::Integer(left / right)),
+                OpCode::Add => self.push_integer(left.checked_add(*right)),
+                OpCode::Sub => self.push_integer(left.checked_sub(*right)),
+                OpCode::Mul => self.push_integer(left.checked_mul(*right)),
+                OpCode::Div if *right == 0 => Err("division by zero".into()),
+                OpCode::Div => self.push_integer(left.checked_div(*right)),
                 OpCode::Eq => self.push(Object::Bool(left == right)),
                 OpCode::NotEq => self.push(Object::Bool(left != right)),
                 OpCode::Greater => self.push(Object::Bool(left > right)),
-                _ => unreachable!(),
+                _ => Err(format!("unknown operation: INTEGER {} INTEGER", op)),
             },
+            (l, r) if Object::is_float_op(l, r) => {
+                let (left, right) = (l.as_float().unwrap(), r.as_float().unwrap());
+                match op {
+                    OpCode::Add => self.push(Object::Float(left + right)),
+                    OpCode::Sub => self.push(Object::Float(left - right)),
+                    OpCode::Mul => self.push(Object::Float(left * right)),
+                    OpCode::Div => self.push(Object::Float(left / right)),
+                    OpCode::Eq => self.push(Object::Bool(left == right)),
+                    OpCode::NotEq => self.push(Object::Bool(left != right)),
+                    OpCode::Greater => self.push(Object::Bool(left > right)),
+                    _ => Err(format!(
+                        "unknown operation: {} {} {}",
+                        l.kind(),
+                        op,
+                        r.kind()
+                    )),
+                }
+            }
             (Object::String(l), Object::String(r)) => match op {
                 OpCode::Add => self.push(Object::String(l.to_owned() + r)),
+                OpCode::Eq => self.push(Object::Bool(l == r)),
+                OpCode::NotEq => self.push(Object::Bool(l != r)),
                 _ => Err(format!(
                     "unknown operation: {} {} {}",
                     left.kind(),
@@ -333,16 +723,18 @@ impl Vm {
                     right.kind()
                 )),
             },
-            _ if left.kind() == right.kind() => match op {
-                OpCode::Eq => self.push(Object::Bool(left == right)),
-                OpCode::NotEq => self.push(Object::Bool(left != right)),
-                _ => Err(format!(
-                    "unknown operation: {} {} {}",
-                    left.kind(),
-                    op,
-                    right.kind()
-                )),
-            },
+            _ if left.kind() == right.kind() || matches!(op, OpCode::Eq | OpCode::NotEq) => {
+                match op {
+                    OpCode::Eq => self.push(Object::Bool(left == right)),
+                    OpCode::NotEq => self.push(Object::Bool(left != right)),
+                    _ => Err(format!(
+                        "unknown operation: {} {} {}",
+                        left.kind(),
+                        op,
+                        right.kind()
+                    )),
+                }
+            }
             _ => Err(format!(
                 "unknown operation: {} {} {}",
                 left.kind(),
@@ -352,6 +744,12 @@ impl Vm {
         }
     }
 
+    /// Pushes the result of a checked integer operation, `None` meaning it overflowed
+    fn push_integer(&mut self, value: Option<i64>) -> RunResult {
+        let value = value.ok_or("integer overflow")?;
+        self.push(Object::Integer(value))
+    }
+
     fn push_frame(&mut self, frame: Frame) {
         self.frames.push(frame);
     }
@@ -386,6 +784,22 @@ impl Vm {
     }
 }
 
+/// Builtins call functions by running them to completion on top of the current frames
+impl Caller for Vm {
+    fn call_func(&mut self, func: &Object, args: Vec<Rc<Object>>) -> Result<Rc<Object>, String> {
+        let depth = self.frames.len();
+        let count = args.len();
+        self.push(func.clone())?;
+        for arg in args {
+            self.push(Rc::unwrap_or_clone(arg))?;
+        }
+
+        self.execute_call(count)?;
+        self.run_frames(depth)?;
+        Ok(Rc::new(self.pop()))
+    }
+}
+
 pub type RunResult = Result<(), String>;
 
 #[cfg(test)]
@@ -2,10 +2,11 @@ use super::*;
 use crate::{
     ast::Parser,
     compiler::Compiler,
-    eval::{ArrayObj, HashObj},
+    eval::{ArrayObj, HashObj, RangeObj},
     lexer::Lexer,
+    module::{load_imports, test::write_files},
 };
-use std::{collections::HashMap, rc::Rc};
+use std::rc::Rc;
 
 macro_rules! test {
     ($($case:expr),* $(,)?) => {
@@ -70,6 +71,10 @@ fn bool_expressions() {
         ("!!false", Object::Bool(false)),
         ("!!5", Object::Bool(true)),
         ("!(if (false) { 5; })", Object::Bool(true)),
+        (r#""a" == "a""#, Object::Bool(true)),
+        (r#""a" != "b""#, Object::Bool(true)),
+        (r#"1 == "1""#, Object::Bool(false)),
+        ("true != 1", Object::Bool(true)),
     )
 }
 
@@ -101,6 +106,43 @@ fn global_let() {
             "let one = 1; let two = one + one; one + two",
             Object::Integer(3)
         ),
+        (
+            "let a = 1; let f = fn() { a }; let a = 2; f()",
+            Object::Integer(2)
+        ),
+    )
+}
+
+#[test]
+fn destructure() {
+    test!(
+        (
+            "let [a, b, ..rest] = [1, 2, 3, 4]; a + b + len(rest)",
+            Object::Integer(5)
+        ),
+        ("let [a, [b, _]] = [1, [2, 3]]; a + b", Object::Integer(3)),
+        (
+            r#"let {name, "age": years} = {"name": "Monkey", "age": 3}; name"#,
+            Object::String("Monkey".into())
+        ),
+        (
+            r#"let f = fn(p) { let {x, y} = p; x * y }; f({"x": 2, "y": 5})"#,
+            Object::Integer(10)
+        ),
+    );
+    test_err!(
+        (
+            "let [a, b] = [1];",
+            "cannot destructure ARRAY with pattern [a, b]"
+        ),
+        (
+            "let [a] = 1;",
+            "cannot destructure INTEGER with pattern [a]"
+        ),
+        (
+            r#"let {name} = {"age": 3};"#,
+            "cannot destructure HASH with pattern {name: name}"
+        ),
     )
 }
 
@@ -113,6 +155,9 @@ fn strings() {
             r#" "mon" + "key" + "banana" "#,
             Object::String("monkeybanana".into())
         ),
+        (r#" "monkey" == "monkey" "#, Object::Bool(true)),
+        (r#" "monkey" != "banana" "#, Object::Bool(true)),
+        (r#" "monkey" == 1 "#, Object::Bool(false)),
     )
 }
 
@@ -146,35 +191,332 @@ fn arrays() {
 #[test]
 fn hashes() {
     test!(
-        (
-            "{}",
-            Object::Hash(HashObj {
-                map: HashMap::new()
-            })
-        ),
+        ("{}", Object::Hash(HashObj::default())),
         (
             "{1: 2, 2: 3}",
-            Object::Hash(HashObj {
-                map: [
+            Object::Hash(
+                [
                     (Rc::new(Object::Integer(1)), Rc::new(Object::Integer(2))),
                     (Rc::new(Object::Integer(2)), Rc::new(Object::Integer(3))),
                 ]
-                .into()
-            })
+                .into_iter()
+                .collect()
+            )
         ),
         (
             "{1 + 1: 2 * 2, 3 + 3: 4 * 4}",
-            Object::Hash(HashObj {
-                map: [
+            Object::Hash(
+                [
                     (Rc::new(Object::Integer(2)), Rc::new(Object::Integer(4))),
                     (Rc::new(Object::Integer(6)), Rc::new(Object::Integer(16))),
                 ]
-                .into()
-            })
+                .into_iter()
+                .collect()
+            )
         ),
     )
 }
 
+#[test]
+fn conversion_builtins() {
+    test!(
+        (r#"type("a")"#, Object::String("STRING".into())),
+        (r#"int("7") + 1"#, Object::Integer(8)),
+        ("float(1) / 2 + 1", Object::Float(1.5)),
+        ("float(2) > 1", Object::Bool(true)),
+        ("1 < float(2)", Object::Bool(true)),
+        ("-float(2)", Object::Float(-2.0)),
+        (
+            r#"repr({"a": "b"})"#,
+            Object::String(r#"{"a": "b"}"#.into())
+        ),
+        ("bool(1).str()", Object::String("true".into())),
+    );
+    test_err!((r#"int("one")"#, r#"could not parse "one" as INTEGER"#));
+}
+
+#[test]
+fn higher_order_builtins() {
+    let string = |s: &str| Object::String(s.into());
+    test!(
+        ("str(map([1, 2, 3], fn(x) { x * 2 }))", string("[2, 4, 6]")),
+        ("str((1..6).filter(fn(x) { x > 3 }))", string("[4, 5]")),
+        (
+            "reduce([1, 2, 3], 10, fn(acc, x) { acc + x })",
+            Object::Integer(16)
+        ),
+        (
+            "let k = 3; str(sort([1, 5, 3], fn(a, b) { b - a }).map(fn(x) { x * k }))",
+            string("[15, 9, 3]")
+        ),
+        (
+            "fn fact(n) { if (n < 2) { 1 } else { n * fact(n - 1) } } str(map(1..5, fact))",
+            string("[1, 2, 6, 24]")
+        ),
+        (
+            "str(map([[1, 2], [3]], fn(xs) { reduce(xs, 0, fn(a, b) { a + b }) }))",
+            string("[3, 3]")
+        ),
+        (r#"str(map(["a", "bc"], len))"#, string("[1, 2]")),
+        (
+            "let a = 1; each([1, 2], fn(x) { x }); a + 1",
+            Object::Integer(2)
+        ),
+    );
+    test_err!(
+        (
+            "map([1], fn(x, y) { x })",
+            "wrong number of arguments. expected 2, got 1"
+        ),
+        (
+            "sort([1, 2], fn(a, b) { true })",
+            "sort comparator must return INTEGER, got BOOL"
+        ),
+    );
+}
+
+#[test]
+fn array_builtins() {
+    let string = |s: &str| Object::String(s.into());
+    test!(
+        ("str([3, 1, 2].sort())", string("[1, 2, 3]")),
+        ("str([1, 2, 3].reverse())", string("[3, 2, 1]")),
+        ("[1, 2, 3].index_of(3)", Object::Integer(2)),
+        ("str(concat([1], [2]).flatten())", string("[1, 2]")),
+        ("str(unique([2, 1, 2]))", string("[2, 1]")),
+        ("min(4, 2) + max([1, 7]) + sum([1, 2])", Object::Integer(12)),
+        ("str([1, 2, 3].slice(-2))", string("[2, 3]")),
+        ("str([1, 3].insert(-2, 2).remove_at(0))", string("[2, 3]")),
+    );
+    test_err!((r#"max(1, "a")"#, "cannot compare STRING and INTEGER"));
+}
+
+#[test]
+fn math_builtins() {
+    test!(
+        ("abs(-3) + pow(2, 3) + gcd(4, 6)", Object::Integer(13)),
+        ("sqrt(9)", Object::Float(3.0)),
+        ("floor(PI) + ceil(E)", Object::Integer(6)),
+        ("round(acos(-1) * 100)", Object::Integer(314)),
+        ("clamp(10, 1, 5)", Object::Integer(5)),
+        ("let E = 1; E", Object::Integer(1)),
+        ("fn f() { PI } f() == PI", Object::Bool(true)),
+    );
+    test_err!(
+        ("pow(2, 64)", "integer overflow"),
+        ("sum([9223372036854775807, 1])", "integer overflow"),
+        ("ceil(sqrt(-1))", "could not convert NaN to INTEGER"),
+    );
+}
+
+#[test]
+fn for_in() {
+    test!(
+        (
+            "let sum = 0; for (x in 1..=4) { let sum = sum + x; } sum",
+            Object::Integer(10)
+        ),
+        (
+            r#"let s = ""; for (c in "héy") { let s = c + s; } s"#,
+            Object::String("yéh".into())
+        ),
+        (
+            r#"let s = ""; for (k in {"a": 1, "b": 2}) { let s = s + k; } s"#,
+            Object::String("ab".into())
+        ),
+        ("let x = 1; for (x in [5]) { x }; x", Object::Integer(1)),
+        ("for (x in []) { x }", Object::Null),
+        (
+            "let f = fn() { for (x in 0..1000000) { if (x > 2) { return x; } } }; f()",
+            Object::Integer(3)
+        ),
+        (
+            "let fs = []; for (i in 0..2) { let fs = push(fs, fn() { i }); } fs[0]() + fs[1]()",
+            Object::Integer(1)
+        ),
+        (
+            "let f = fn(xs) { let n = 0; for (x in xs) { for (y in xs) { let n = n + x * y; } } n }; f([1, 2])",
+            Object::Integer(9)
+        ),
+    );
+    test_err!(("for (x in 5) { x }", "cannot iterate over INTEGER"),);
+}
+
+#[test]
+fn try_catch() {
+    let string = |s: &str| Object::String(s.into());
+    let f =
+        r#"let f = fn(x) { if (x > 2) { throw {"kind": "ValueError", "message": "big"} }; x };"#;
+    test!(
+        (
+            "try { 1 + true } catch (e) { [e.kind, e.location].str() }",
+            string("[runtime, line 1]")
+        ),
+        (
+            &format!(
+                "{} try {{ f(1) + f(5) }} catch (e) {{ e.kind + e.message }}",
+                f
+            ),
+            string("ValueErrorbig")
+        ),
+        (
+            "try { throw 42 } catch (e) { e.value }",
+            Object::Integer(42)
+        ),
+        (
+            &format!("{} try {{ map([1, 5], f) }} catch (e) {{ e.message }}", f),
+            string("big")
+        ),
+        (
+            &format!(
+                "{} let h = fn() {{ f(9) }}; let k = try {{ h() }} catch (e) {{ 0 }}; k + f(2)",
+                f
+            ),
+            Object::Integer(2)
+        ),
+        (
+            "1 + try { [1, 2, 3 + true] } catch (e) { 10 }",
+            Object::Integer(11)
+        ),
+        (
+            r#"try { try { throw "a" } finally { 1 } } catch (e) { e.message }"#,
+            string("a")
+        ),
+        (
+            "try { try { 1 / 0 } finally { 0 } } catch (e) { e.message }",
+            string("division by zero")
+        ),
+        (
+            r#"try { try { throw "a" } catch (e) { throw e } } catch (e) { e.message }"#,
+            string("a")
+        ),
+        ("try { 5 } finally { 6 }", Object::Integer(5)),
+        (
+            "let g = fn() { try { return 1 } finally { 2 }; 3 }; g()",
+            Object::Integer(1)
+        ),
+        (
+            "let g = fn() { try { return 1 } finally { return 7 } }; g()",
+            Object::Integer(7)
+        ),
+        (
+            "let g = fn() { try { 1 + true } catch (e) { return 2 } finally { 3 }; 4 }; g()",
+            Object::Integer(2)
+        ),
+        (
+            "let a = 1;\ntry {\n  throw a\n} catch (e) { e.location }",
+            string("line 3")
+        ),
+        (
+            "\ntry { len(1) } catch (e) { e.location }",
+            string("line 2")
+        ),
+        (
+            "let f = fn(x) {\n  x / 0\n};\ntry { f(1) } catch (e) { e.location }",
+            string("line 2")
+        ),
+        (
+            "try {\n  {}[[]]\n} catch (e) { e.location }",
+            string("line 2")
+        ),
+        (
+            &format!(
+                "try {{{}throw 1 }} catch (e) {{ e.location }}",
+                "\n".repeat(70000)
+            ),
+            string("line 70001")
+        ),
+        (
+            "try { 1 / 0 } catch (e) { e.message }",
+            string("division by zero")
+        ),
+        (
+            r#"let e = 1; let r = try { throw "x" } catch (e) { let b = 2; e.message }; [e, b, r].str()"#,
+            string("[1, 2, x]")
+        ),
+        (
+            r#"let f = fn() { let e = 1; try { throw "x" } catch (e) { 0 }; e }; f()"#,
+            Object::Integer(1)
+        ),
+    );
+    test_err!(
+        ("9223372036854775807 * 2", "integer overflow"),
+        ("-(-9223372036854775807 - 1)", "integer overflow"),
+        ("5 / 0", "division by zero"),
+        (r#"throw "boom""#, "error: boom at line 1"),
+        (
+            "try { 1 } catch (e) { 2 }; 1 + true",
+            "unknown operation: INTEGER OpAdd BOOL"
+        ),
+        (r#"try { throw "a" } finally { 1 }"#, "error: a at line 1"),
+        ("try { 1 / 0 } finally { 0 }", "division by zero"),
+        (
+            r#"try { 1 / 0 } finally { try { throw "b" } catch (e) { 0 } }"#,
+            "division by zero"
+        ),
+    );
+}
+
+#[test]
+fn random_builtins_match_eval() {
+    let input = "[random(), random_int(0, 1000), shuffle(1..10), choice(1..10)].str()";
+    let program = || Parser::new(Lexer::new(input.into())).parse().unwrap();
+
+    crate::random::seed(99);
+    let mut compiler = Compiler::default();
+    compiler.compile(program()).unwrap();
+    let mut vm = Vm::new(compiler.bytecode());
+    vm.run().unwrap();
+
+    crate::random::seed(99);
+    let env = crate::eval::Environment::new();
+    let evaluated = crate::eval::eval_program(program(), &env).unwrap();
+    assert_eq!(vm.last_popped(), &*evaluated);
+}
+
+#[test]
+fn json_builtins() {
+    test!(
+        (
+            r#"json_stringify(json_parse(json_stringify({"b": 1, "a": [true]})))"#,
+            Object::String(r#"{"b":1,"a":[true]}"#.into())
+        ),
+        (
+            r#"json_parse("12").type()"#,
+            Object::String("INTEGER".into())
+        ),
+    );
+    test_err!((
+        r#"json_parse("tru")"#,
+        "invalid JSON: expected true at line 1, column 4"
+    ));
+}
+
+#[test]
+fn hash_builtins() {
+    let string = |s: &str| Object::String(s.into());
+    test!(
+        (
+            r#"let h = {"b": 1, "a": 2, "b": 3}; join([h, keys(h), h.values()], " ")"#,
+            string("{b: 3, a: 2} [b, a] [3, 2]")
+        ),
+        (
+            r#"join(entries({"x": 1, 2: true}), ";")"#,
+            string("[x, 1];[2, true]")
+        ),
+        (r#"{"a": 1}.has("a")"#, Object::Bool(true)),
+        (
+            r#"join([delete({1: 1, 2: 2, 3: 3}, 2), merge({1: 1, 2: 2}, {3: 3, 1: 4})], " ")"#,
+            string("{1: 1, 3: 3} {1: 4, 2: 2, 3: 3}")
+        ),
+    );
+    test_err!(
+        (r#"delete({}, [1])"#, "unusable as hash key: ARRAY"),
+        (r#"{float(1): 1}"#, "unusable as hash key: FLOAT"),
+        (r#"{1: 1}[float(1)]"#, "unusable as hash key: FLOAT"),
+    );
+}
+
 #[test]
 fn index() {
     test!(
@@ -183,7 +525,11 @@ fn index() {
         ("[[1, 1, 1]][0][0]", Object::Integer(1)),
         ("[][0]", Object::Null),
         ("[1, 2, 3][99]", Object::Null),
-        ("[1][-1]", Object::Null),
+        ("[1][-1]", Object::Integer(1)),
+        ("[1, 2, 3][-2]", Object::Integer(2)),
+        ("[1][-2]", Object::Null),
+        (r#""abc"[-1]"#, Object::String("c".into())),
+        (r#""abc"[5]"#, Object::Null),
         ("{1: 1, 2: 2}[1]", Object::Integer(1)),
         (
             r#"{1: 1, 2: 2, "abc": "def"}["abc"]"#,
@@ -314,6 +660,10 @@ fn funcs_with_bindings() {
             minusOne() + minusTwo();  "#,
             Object::Integer(97)
         ),
+        (
+            "let f = fn() { let a = 1; let a = a + 1; let b = 3; a + b }; f()",
+            Object::Integer(5)
+        ),
     )
 }
 
@@ -357,6 +707,375 @@ fn funcs_with_arguments() {
     )
 }
 
+#[test]
+fn funcs_with_default_and_rest_params() {
+    test!(
+        (
+            "let f = fn(x, y = 10) { x + y }; f(1) + f(1, 2)",
+            Object::Integer(14)
+        ),
+        ("let f = fn(x, y = x * 2) { y }; f(3)", Object::Integer(6)),
+        (
+            "let f = fn(first, ...others) { [first, others] }; f(1, 2, 3)[1]",
+            Object::Array(ArrayObj {
+                elements: vec![Rc::new(Object::Integer(2)), Rc::new(Object::Integer(3))]
+            })
+        ),
+        (
+            "let f = fn(a, b = 5, ...rest) { let c = a + b; c + len(rest) }; f(1) + f(1, 1, 1, 1)",
+            Object::Integer(10)
+        ),
+        ("let f = fn(...all) { len(all) }; f()", Object::Integer(0)),
+        (
+            "let f = fn(a, b, c) { a * b + c }; let args = [2, 3]; f(...args, 4)",
+            Object::Integer(10)
+        ),
+        (
+            "let f = fn(a, ...rest) { len(rest) }; f(...[1, 2], 3, ...[4])",
+            Object::Integer(3)
+        ),
+        ("len(...[[1, 2]])", Object::Integer(2)),
+    );
+    test_err!(
+        (
+            "fn(x, y = 1) { x }()",
+            "wrong number of arguments. expected 1 to 2, got 0"
+        ),
+        (
+            "fn(x, ...y) { x }()",
+            "wrong number of arguments. expected at least 1, got 0"
+        ),
+        (
+            "fn(x) { x }(...1)",
+            "spread argument must be ARRAY, got INTEGER"
+        ),
+    )
+}
+
+#[test]
+fn closures() {
+    test!(
+        (
+            r#"
+            let newClosure = fn(a) { fn() { a; }; };
+            let closure = newClosure(99);
+            closure(); "#,
+            Object::Integer(99)
+        ),
+        (
+            r#"
+            let newAdder = fn(a, b) { fn(c) { a + b + c }; };
+            let adder = newAdder(1, 2);
+            adder(8); "#,
+            Object::Integer(11)
+        ),
+        (
+            r#"
+            let newAdderOuter = fn(a, b) {
+                let c = a + b;
+                fn(d) {
+                    let e = d + c;
+                    fn(f) { e + f; };
+                };
+            };
+            let newAdderInner = newAdderOuter(1, 2)
+            let adder = newAdderInner(3);
+            adder(8); "#,
+            Object::Integer(14)
+        ),
+        (
+            r#"
+            let countDown = fn(x) { if (x == 0) { return 0; } else { countDown(x - 1); } };
+            countDown(10); "#,
+            Object::Integer(0)
+        ),
+        (
+            r#"
+            let wrapper = fn() {
+                let countDown = fn(x) { if (x == 0) { return 0; } else { countDown(x - 1); } };
+                countDown(1);
+            };
+            wrapper(); "#,
+            Object::Integer(0)
+        ),
+    )
+}
+
+#[test]
+fn slices() {
+    test!(
+        (
+            "[1, 2, 3, 4][1:3]",
+            Object::Array(ArrayObj {
+                elements: vec![Rc::new(Object::Integer(2)), Rc::new(Object::Integer(3))]
+            })
+        ),
+        (
+            "let a = [1, 2, 3]; a[:-1]",
+            Object::Array(ArrayObj {
+                elements: vec![Rc::new(Object::Integer(1)), Rc::new(Object::Integer(2))]
+            })
+        ),
+        (
+            "[1, 2, 3][5:]",
+            Object::Array(ArrayObj { elements: vec![] })
+        ),
+        (r#""monkey"[2:]"#, Object::String("nkey".into())),
+        (r#""monkey"[1:3]"#, Object::String("on".into())),
+    );
+    test_err!(
+        ("5[1:]", "slice operator not supported: INTEGER"),
+        (r#""a"["b":]"#, "slice index must be INTEGER, got STRING"),
+    );
+}
+
+#[test]
+fn templates() {
+    test!(
+        (
+            r#"let name = "monkey"; "Hello, ${name}!""#,
+            Object::String("Hello, monkey!".into())
+        ),
+        (
+            r#"let items = [1, 2]; "${len(items)} items: ${items}""#,
+            Object::String("2 items: [1, 2]".into())
+        ),
+        (
+            r#"fn(h) { "${h["a"]} and ${h.b}" }({"a": 1, "b": false})"#,
+            Object::String("1 and false".into())
+        ),
+    );
+}
+
+#[test]
+fn imports() {
+    let dir = write_files(
+        "vm_imports",
+        &[
+            (
+                "math.monkey",
+                "let secret = 2; fn double(x) { x * secret } let inc = fn(x) { x + 1 }; export double, inc;",
+            ),
+            (
+                "lib/quad.monkey",
+                r#"import "../math.monkey" as m; let quad = fn(x) { m.double(m.double(x)) }; export quad;"#,
+            ),
+            (
+                "unless.monkey",
+                r#"let unless = macro(c, a, b) { quote(if (!(unquote(c))) { unquote(a) } else { unquote(b) }) }; let size = fn(x) { unless(x > 1, "small", "big") }; export size;"#,
+            ),
+            ("bad.monkey", "let a = 1; export a, nope;"),
+        ],
+    );
+    let cases = [
+        (
+            r#"import "lib/quad.monkey" as l; let secret = 5; l.quad(3) + secret"#,
+            Ok(Object::Integer(17)),
+        ),
+        (
+            r#"import "math.monkey" as m; [m.inc(1), m.secret]"#,
+            Ok(Object::Array(ArrayObj {
+                elements: vec![Rc::new(Object::Integer(2)), Rc::new(Object::Null)],
+            })),
+        ),
+        (
+            r#"import "math.monkey" as m; secret"#,
+            Err("undefined symbol: secret".into()),
+        ),
+        (
+            r#"import "unless.monkey" as u; u.size(1) + u.size(2)"#,
+            Ok(Object::String("smallbig".into())),
+        ),
+        (
+            r#"import "math.monkey" as m; fn f() { m.double(4) } f()"#,
+            Ok(Object::Integer(8)),
+        ),
+        (
+            r#"import "bad.monkey" as b;"#,
+            Err(format!(
+                "{}: exported name nope is not defined",
+                dir.join("bad.monkey").canonicalize().unwrap().display()
+            )),
+        ),
+    ];
+
+    for (inp, exp) in cases {
+        let lexer = Lexer::new(inp.to_string());
+        let mut parser = Parser::new(lexer);
+        let mut program = parser.parse().expect("Skill issue");
+        let modules = load_imports(&mut program, &dir).unwrap();
+
+        let mut compiler = Compiler::default();
+        let res = compiler
+            .compile_modules(modules)
+            .and_then(|_| compiler.compile(program))
+            .and_then(|_| {
+                let mut vm = Vm::new(compiler.bytecode());
+                vm.run()?;
+                Ok(vm.last_popped().clone())
+            });
+        assert_eq!(res, exp, "{}", inp);
+    }
+}
+
+#[test]
+fn ranges() {
+    test!(
+        ("1..4", Object::Range(RangeObj { start: 1, end: 4 })),
+        ("0..=0", Object::Range(RangeObj { start: 0, end: 1 })),
+        ("len(1..=10)", Object::Integer(10)),
+        ("let n = 3; (0..n * 2)[-1]", Object::Integer(5)),
+        ("(0..1000000000).contains(999)", Object::Bool(true)),
+        ("contains(1..=3, 4)", Object::Bool(false)),
+        (
+            "(1..=3).array()",
+            Object::Array(ArrayObj {
+                elements: vec![
+                    Rc::new(Object::Integer(1)),
+                    Rc::new(Object::Integer(2)),
+                    Rc::new(Object::Integer(3)),
+                ]
+            })
+        ),
+        ("(0..10)[:-5]", Object::Range(RangeObj { start: 0, end: 5 })),
+    );
+    test_err!(
+        (
+            r#""a"..1"#,
+            "range bounds must be INTEGER, got STRING..INTEGER"
+        ),
+        (
+            "len(0..=9223372036854775807)",
+            "range too large: 0..=9223372036854775807"
+        ),
+        (
+            "array(0..10000000000)",
+            "range too large to collect: 0..10000000000"
+        ),
+        (
+            "json_stringify(0..10000000000)",
+            "range too large to collect: 0..10000000000"
+        ),
+        (
+            "reduce(0..10000000000, 0, fn(acc, x) { acc / (3 - x) })",
+            "division by zero"
+        ),
+    );
+}
+
+#[test]
+fn methods() {
+    test!(
+        (
+            r#"let h = {"name": "monkey", "n": 2}; h.name"#,
+            Object::String("monkey".into())
+        ),
+        (r#"{"a": 1}.b"#, Object::Null),
+        ("[1, 2, 3].len()", Object::Integer(3)),
+        (r#""abc".len() + 1"#, Object::Integer(4)),
+        (
+            "let a = [1]; a.push(2).rest()",
+            Object::Array(ArrayObj {
+                elements: vec![Rc::new(Object::Integer(2))]
+            })
+        ),
+        (
+            r#"let p = {"add": fn(a, b) { a + b }}; p.add(...[1, 2])"#,
+            Object::Integer(3)
+        ),
+        (
+            r#"let p = {"k": 3}; fn(x) { p.k * x }(2)"#,
+            Object::Integer(6)
+        ),
+        ("[1, 2].push(...[3]).last()", Object::Integer(3)),
+        (r#"[1, "a"].join(",")"#, Object::String("1,a".into())),
+    );
+    test_err!(
+        ("5.len()", "unknown method len for INTEGER"),
+        (r#""abc".push(1)"#, "unknown method push for STRING"),
+    );
+}
+
+#[test]
+fn pipe() {
+    test!(
+        (
+            "let add = fn(a, b) { a + b }; 1 |> add(2) |> add(3)",
+            Object::Integer(6)
+        ),
+        ("[1, 2] |> push(3) |> len", Object::Integer(3)),
+        ("[1] |> fn(a) { a.first() }", Object::Integer(1)),
+    );
+}
+
+#[test]
+fn func_decls() {
+    test!(
+        (
+            "fn f() { g() + x } let x = 1; let x = x + 1; fn g() { x } f()",
+            Object::Integer(4)
+        ),
+        (
+            "let x = 1; let f = fn() { let x = x + 1; x }; f()",
+            Object::Integer(2)
+        ),
+        (
+            "let g = fn() { let y = 5; fn f() { y } f() }; g()",
+            Object::Integer(5)
+        ),
+        (
+            "let g = fn() { fn f() { y * z } let y = 2; let [z] = [3]; f() }; g()",
+            Object::Integer(6)
+        ),
+        (
+            "let x = 1; let g = fn() { let x = x + 1; fn f() { x } f() }; g()",
+            Object::Integer(2)
+        ),
+        (
+            "let g = fn(n) { fn f() { fn h() { n + m } h() } let m = 10; f() }; g(1)",
+            Object::Integer(11)
+        ),
+        ("fn add(x, y) { x + y } add(1, 2)", Object::Integer(3)),
+        (
+            "let r = double(4); fn double(x) { x * 2 } r",
+            Object::Integer(8)
+        ),
+        (
+            r#"
+            fn is_even(n) { if (n == 0) { true } else { is_odd(n - 1) } }
+            fn is_odd(n) { if (n == 0) { false } else { is_even(n - 1) } }
+            [is_even(10), is_odd(7), is_even(3)]"#,
+            Object::Array(ArrayObj {
+                elements: vec![
+                    Rc::new(Object::Bool(true)),
+                    Rc::new(Object::Bool(true)),
+                    Rc::new(Object::Bool(false)),
+                ]
+            })
+        ),
+        (
+            r#"
+            let parity = fn(n) {
+                let res = is_even(n);
+                fn is_odd(n) { if (n == 0) { false } else { is_even(n - 1) } }
+                fn is_even(n) { if (n == 0) { true } else { is_odd(n - 1) } }
+                res
+            };
+            parity(4)"#,
+            Object::Bool(true)
+        ),
+        (
+            r#"
+            fn fib(n) {
+                fn go(n, a, b) { if (n == 0) { a } else { go(n - 1, b, a + b) } }
+                go(n, 0, 1)
+            }
+            fib(10)"#,
+            Object::Integer(55)
+        ),
+    )
+}
+
 #[test]
 fn call_with_wrong_arguments() {
     test_err!(
@@ -382,6 +1101,7 @@ fn builtins() {
         (r#"len("four")"#, Object::Integer(4)),
         (r#"len("hello world")"#, Object::Integer(11)),
         (r#"len([1, 2, 3, 4])"#, Object::Integer(4)),
+        (r#"1 + len([1, 2]) * 2"#, Object::Integer(5)),
         (r#"first(["a", "b"])"#, Object::String("a".into())),
         (r#"first([])"#, Object::Null),
         (r#"last(["a", "b"])"#, Object::String("b".into())),
@@ -478,6 +1198,131 @@ fn builtins() {
     )
 }
 
+#[test]
+fn string_builtins() {
+    let string = |s: &str| Object::String(s.into());
+    test!(
+        (r#"len("héllo")"#, Object::Integer(5)),
+        (
+            r#""a b".split(" ")"#,
+            Object::Array(ArrayObj {
+                elements: vec![Rc::new(string("a")), Rc::new(string("b"))]
+            })
+        ),
+        (r#"join(["a", "b"], ", ")"#, string("a, b")),
+        (r#"" x ".trim().upper()"#, string("X")),
+        (r#""abc" |> replace("b", "")"#, string("ac")),
+        (r#""héllo".index_of("llo")"#, Object::Integer(2)),
+        (r#""5".pad_left(3, "0")"#, string("005")),
+        (r#""ab".contains("b")"#, Object::Bool(true)),
+    );
+    test_err!(
+        (
+            r#"join("a", "b")"#,
+            "arguments to `join` not supported, got STRING, STRING"
+        ),
+        (
+            r#""a".repeat(-2)"#,
+            "repeat count must not be negative, got -2"
+        ),
+        (
+            r#""ab".repeat(9223372036854775807)"#,
+            "result of `repeat` is too large"
+        ),
+    );
+}
+
+#[test]
+fn match_expr() {
+    test!(
+        (
+            r#"match (2) { 1 => "one", 2 => "two", _ => "many" }"#,
+            Object::String("two".into())
+        ),
+        (
+            r#"match (5) { 1 => "one", _ => "many" }"#,
+            Object::String("many".into())
+        ),
+        ("match (5) { 1 => 1 }", Object::Null),
+        (r#"match ("a") { 1 => 1, "a" => 2 }"#, Object::Integer(2)),
+        ("match (-3) { -3 => true }", Object::Bool(true)),
+        ("match (4) { x => x * 2 }", Object::Integer(8)),
+        (
+            "match (4) { x if x > 5 => 1, x if x > 3 => 2, _ => 3 }",
+            Object::Integer(2)
+        ),
+        (
+            "match ([1, 2, 3]) { [] => 0, [a] => a, [a, b] => a + b, [a, ..rest] => len(rest) }",
+            Object::Integer(2)
+        ),
+        (
+            "match ([1, [2, 3]]) { [a, [b, c]] => a + b + c }",
+            Object::Integer(6)
+        ),
+        ("match ([]) { [_, ..] => 1, [..] => 2 }", Object::Integer(2)),
+        ("match (1) { [..] => 1, _ => 2 }", Object::Integer(2)),
+        (
+            r#"match ({"type": "circle", "r": 2}) { {"type": "square", "a": a} => a * a, {"type": "circle", "r": r} => 3 * r * r }"#,
+            Object::Integer(12)
+        ),
+        (
+            r#"match ({"a": 1}) { {"b": b} => b, _ => 0 }"#,
+            Object::Integer(0)
+        ),
+        (
+            r#"
+            let describe = fn(x) {
+                match (x) {
+                    [a, b] => a + b,
+                    [a, ..rest] if len(rest) > 1 => len(rest),
+                    _ => 0,
+                }
+            };
+            describe([1, 2, 3, 4]) + describe([5, 6]) + describe(1) "#,
+            Object::Integer(14)
+        ),
+        ("let x = 1; match (2) { x => x }; x", Object::Integer(1)),
+        (
+            "let f = fn() { let a = 1; match ([2, 3]) { [a, b] if a > 5 => 0, _ => a } }; f()",
+            Object::Integer(1)
+        ),
+        ("match (2) { x => fn() { x } }()", Object::Integer(2)),
+    )
+}
+
+#[test]
+fn quote_unquote() {
+    let cases = [
+        ("quote(5)", "5"),
+        ("quote(foobar + barfoo)", "(foobar + barfoo)"),
+        ("quote(unquote(4 + 4))", "8"),
+        ("quote(8 + unquote(4 + 4))", "(8 + 8)"),
+        (
+            "let q = quote(4 + 4); quote(unquote(q) + 8)",
+            "((4 + 4) + 8)",
+        ),
+        ("quote(unquote(true == false))", "false"),
+        (r#"quote(f(unquote([1, "a"])))"#, "f([1, a])"),
+        (
+            "let f = fn(x) { quote(unquote(x) * unquote(x + 1)) }; f(2)",
+            "(2 * 3)",
+        ),
+    ];
+
+    for (inp, exp) in cases {
+        let program = Parser::new(Lexer::new(inp.to_string())).parse().unwrap();
+        let mut compiler = Compiler::default();
+        compiler.compile(program).unwrap();
+
+        let mut vm = Vm::new(compiler.bytecode());
+        vm.run().unwrap();
+        match vm.last_popped() {
+            Object::Quote(e) => assert_eq!(e.to_string(), exp),
+            o => panic!("expected QUOTE, got {:?}", o),
+        }
+    }
+}
+
 fn test(cases: &[(&str, Object)]) {
     for (inp, exp) in cases {
         let lexer = Lexer::new(inp.to_string());
@@ -514,3 +1359,56 @@ fn test_err(cases: &[(&str, &str)]) {
         }
     }
 }
+
+#[test]
+fn prelude() {
+    let int = |i| Rc::new(Object::Integer(i));
+    let array = |elements| Object::Array(ArrayObj { elements });
+    let cases = [
+        (
+            "map([1, 2, 3], fn(x) { x * 2 })",
+            array(vec![int(2), int(4), int(6)]),
+        ),
+        (
+            "filter(range(6), fn(x) { x > 3 })",
+            array(vec![int(4), int(5)]),
+        ),
+        (
+            "reduce(1..=4, 0, fn(acc, x) { acc + x })",
+            Object::Integer(10),
+        ),
+        (
+            "sort_by([3, 1, 2], fn(x) { -x })",
+            array(vec![int(3), int(2), int(1)]),
+        ),
+        (
+            "[any([1, 2], fn(x) { x > 1 }), all([1, 2], fn(x) { x > 1 })]",
+            array(vec![
+                Rc::new(Object::Bool(true)),
+                Rc::new(Object::Bool(false)),
+            ]),
+        ),
+        (
+            "[any(1..20000, fn(x) { false }), all(1..20000, fn(x) { true })]",
+            array(vec![
+                Rc::new(Object::Bool(false)),
+                Rc::new(Object::Bool(true)),
+            ]),
+        ),
+        (
+            "let map = fn(xs, f) { 0 }; [map([1], len), len(zip([1], [2]))]",
+            array(vec![int(0), int(1)]),
+        ),
+    ];
+
+    for (inp, exp) in cases {
+        let program = Parser::new(Lexer::new(inp.to_string())).parse().unwrap();
+        let mut compiler = Compiler::default();
+        compiler.compile_prelude().unwrap();
+        compiler.compile(program).unwrap();
+
+        let mut vm = Vm::new(compiler.bytecode());
+        vm.run().unwrap();
+        assert_eq!(vm.last_popped(), &exp, "{}", inp);
+    }
+}